@@ -0,0 +1,239 @@
+//! Bounding boxes of the ink on each page
+//!
+//! Characters are taken to fill the box given by their TFM width, height and depth, widened by
+//! their italic correction so slanted letters aren't cut off, and rules fill their own box. This is
+//! the box TeX itself knows about; the shapes of the glyphs can stay slightly inside it, or in
+//! rare cases stick out of it.
+
+use crate::{
+    document::Document,
+    geometry::Rect,
+    interpreter::{Device, Error},
+    tfm::Tfm,
+    FontDef,
+};
+use std::collections::HashMap;
+
+/// The marks made on one page, in dvi units
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ink {
+    /// The box of every character and rule, in the order they were drawn
+    pub boxes: Vec<Rect>,
+}
+
+impl Ink {
+    /// The smallest box containing all the ink, or `None` for a blank page
+    pub fn tight(&self) -> Option<Rect> {
+        self.boxes.iter().copied().reduce(|a, b| a.union(&b))
+    }
+
+    /// The smallest box containing all the ink inside `region`
+    ///
+    /// Marks that are partly inside the region are cut off at its edges.
+    pub fn within(&self, region: &Rect) -> Option<Rect> {
+        self.boxes
+            .iter()
+            .filter_map(|rect| rect.intersection(region))
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
+/// A `Device` that collects the ink on each page
+#[derive(Debug, Clone)]
+struct Collector<'a> {
+    fonts: &'a HashMap<u32, Tfm>,
+    pages: Vec<Ink>,
+}
+
+impl<'a> Collector<'a> {
+    fn push(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        if let Some(page) = self.pages.last_mut() {
+            page.boxes.push(rect);
+        }
+    }
+}
+
+impl<'a> Device for Collector<'a> {
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.pages.push(Ink::default());
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, ch: u32, width: i32) {
+        let metrics = self
+            .fonts
+            .get(&font.number)
+            .and_then(|tfm| tfm.scaled(ch, font.scale_factor));
+        // the interpreter gets widths from the same TFM files, so every character drawn is in one
+        let (height, depth, italic) = match metrics {
+            Some(m) => (m.height, m.depth, m.italic.max(0)),
+            None => return,
+        };
+        let right = h.saturating_add(width).saturating_add(italic);
+        let (top, bottom) = (v.saturating_sub(height), v.saturating_add(depth));
+        self.push(Rect {
+            left: h.min(right),
+            top: top.min(bottom),
+            right: h.max(right),
+            bottom: top.max(bottom),
+        });
+    }
+
+    fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+        self.push(Rect {
+            left: h,
+            top: v.saturating_sub(height),
+            right: h.saturating_add(width),
+            bottom: v,
+        });
+    }
+}
+
+/// Find the ink on every page of a document
+///
+/// `fonts` are the TFM files for each font number.
+pub fn extract(document: &Document, fonts: &HashMap<u32, Tfm>) -> Result<Vec<Ink>, Error> {
+    let mut collector = Collector {
+        fonts,
+        pages: Vec::new(),
+    };
+    document.run(fonts, &mut collector)?;
+    Ok(collector.pages)
+}
+
+/// The tight bounding box of every page of a document, or `None` for blank pages
+pub fn bounding_boxes(
+    document: &Document,
+    fonts: &HashMap<u32, Tfm>,
+) -> Result<Vec<Option<Rect>>, Error> {
+    Ok(extract(document, fonts)?.iter().map(Ink::tight).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        tfm::tests::tfm_bytes,
+        Instruction,
+    };
+
+    fn font(number: u32) -> FontDef {
+        FontDef {
+            number,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("test"),
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let document = Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: vec![
+                Page {
+                    counters: [0; 10],
+                    instructions: vec![
+                        Instruction::Font(0),
+                        Instruction::Set(b'a'.into()),
+                        Instruction::Set(b'b'.into()),
+                        Instruction::Down(20 << 16),
+                        Instruction::PutRule(1 << 16, 2 << 16),
+                    ],
+                },
+                Page {
+                    counters: [0; 10],
+                    instructions: vec![Instruction::Right(100)],
+                },
+            ],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font(0)],
+            },
+        };
+        let mut fonts = HashMap::new();
+        fonts.insert(0, Tfm::parse(&tfm_bytes()).unwrap().1);
+        let ink = extract(&document, &fonts).unwrap();
+        assert_eq!(ink.len(), 2);
+        // a is 5pt wide and 4.375pt high, b is 10pt wide with a 1.25pt depth
+        assert_eq!(
+            ink[0].boxes[..2],
+            [
+                Rect {
+                    left: 0,
+                    top: -286_720,
+                    right: 5 << 16,
+                    bottom: 0,
+                },
+                Rect {
+                    left: 5 << 16,
+                    top: -286_720,
+                    right: 15 << 16,
+                    bottom: 81_920,
+                },
+            ]
+        );
+        assert_eq!(
+            ink[0].tight(),
+            Some(Rect {
+                left: 0,
+                top: -286_720,
+                right: 17 << 16,
+                bottom: 20 << 16,
+            })
+        );
+        let region = Rect {
+            left: 6 << 16,
+            top: -10 << 16,
+            right: 30 << 16,
+            bottom: 10 << 16,
+        };
+        assert_eq!(
+            ink[0].within(&region),
+            Some(Rect {
+                left: 6 << 16,
+                top: -286_720,
+                right: 15 << 16,
+                bottom: 81_920,
+            })
+        );
+        assert_eq!(ink[1].tight(), None);
+        assert_eq!(
+            bounding_boxes(&document, &fonts).unwrap()[0],
+            ink[0].tight()
+        );
+    }
+
+    #[test]
+    fn extreme_positions() {
+        let mut collector = Collector {
+            fonts: &HashMap::new(),
+            pages: Vec::new(),
+        };
+        collector.begin_page(&[0; 10]);
+        // positions come from the file, so these mustn't overflow
+        collector.rule(i32::MAX - 10, i32::MIN + 5, 100, 100);
+        assert_eq!(
+            collector.pages[0].tight(),
+            Some(Rect {
+                left: i32::MAX - 10,
+                top: i32::MIN,
+                right: i32::MAX,
+                bottom: i32::MIN + 5,
+            })
+        );
+    }
+}
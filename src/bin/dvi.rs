@@ -0,0 +1,387 @@
+//! Inspect and edit dvi files
+//!
+//! Usage: `dvi <command> [arguments]`, where the commands are
+//!
+//!  - `info FILE`: the preamble, page count, paper size and fonts
+//!  - `dump FILE`: every instruction with its byte offset
+//!  - `validate FILE`: check the structure of the file and interpret every page
+//!  - `select FILE PAGES [-o OUTPUT]`: copy some pages, given like `1-3,5,8-`
+//!  - `concat FILE... [-o OUTPUT]`: join files one after another
+//!  - `text FILE [--fonts DIR]... [--pages PAGES]`: extract the text, using TFM files
+//!  - `fonts FILE`: the fonts used, with their sizes and the characters used from them
+//!
+//! `-` reads standard input, and output goes to standard output unless `-o` is given. Pages are
+//! numbered from 1, in the order they appear in the file.
+
+use dvi::{
+    checksum::{Directories, FontLocator},
+    document::Document,
+    interpreter::{Device, FontMetrics},
+    tfm::Tfm,
+    usage, FontDef, Instruction, Instructions,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+const USAGE: &str = "usage:
+    dvi info FILE
+    dvi dump FILE
+    dvi validate FILE
+    dvi select FILE PAGES [-o OUTPUT]
+    dvi concat FILE... [-o OUTPUT]
+    dvi text FILE [--fonts DIR]... [--pages PAGES]
+    dvi fonts FILE";
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// The arguments after the command, split into files and options
+struct Args {
+    files: Vec<String>,
+    output: Option<String>,
+    fonts: Vec<PathBuf>,
+    pages: Option<String>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args {
+            files: Vec::new(),
+            output: None,
+            fonts: Vec::new(),
+            pages: None,
+        };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "-o" | "--output" => parsed.output = Some(value()?),
+                "--fonts" => parsed.fonts.push(value()?.into()),
+                "--pages" => parsed.pages = Some(value()?),
+                _ => parsed.files.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The only file argument
+    fn file(&self) -> Result<&str> {
+        match self.files.as_slice() {
+            [file] => Ok(file),
+            [] => Err("no input file".into()),
+            _ => Err("too many arguments".into()),
+        }
+    }
+
+    fn output(&self) -> Result<Box<dyn Write>> {
+        Ok(match &self.output {
+            Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+            None => Box::new(io::BufWriter::new(io::stdout())),
+        })
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut input = Vec::new();
+        io::stdin().read_to_end(&mut input)?;
+        Ok(input)
+    } else {
+        fs::read(path).map_err(|e| format!("{}: {}", path, e).into())
+    }
+}
+
+fn open(path: &str) -> Result<Document> {
+    Document::parse(&read(path)?).map_err(|e| format!("{}: {}", path, e).into())
+}
+
+/// Parse page ranges like `1-3,5,8-` into page indices
+fn page_indices(ranges: &str, len: usize) -> Result<Vec<usize>> {
+    let page = |number: &str| -> Result<usize> {
+        match number.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= len => Ok(n - 1),
+            _ => Err(format!("no page {} in a document with {} pages", number, len).into()),
+        }
+    };
+    let mut indices = Vec::new();
+    for range in ranges.split(',') {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let first = if first.trim().is_empty() {
+                    0
+                } else {
+                    page(first)?
+                };
+                let last = if last.trim().is_empty() {
+                    len.saturating_sub(1)
+                } else {
+                    page(last)?
+                };
+                if first > last {
+                    return Err(format!("page range {} is backwards", range).into());
+                }
+                indices.extend(first..=last);
+            }
+            None => indices.push(page(range)?),
+        }
+    }
+    Ok(indices)
+}
+
+fn info(args: Args) -> Result<()> {
+    let document = open(args.file()?)?;
+    let mut out = args.output()?;
+    let pre = &document.preamble;
+    let post = &document.postamble;
+    let paper = document.paper_size();
+    writeln!(out, "format: {}", pre.format)?;
+    writeln!(out, "comment: {}", String::from_utf8_lossy(&pre.comment))?;
+    writeln!(out, "units: {}/{}", pre.numerator, pre.denominator)?;
+    writeln!(out, "magnification: {}", pre.magnification)?;
+    writeln!(out, "pages: {}", document.pages.len())?;
+    writeln!(
+        out,
+        "paper: {:.1}mm x {:.1}mm",
+        paper.width_mm(),
+        paper.height_mm()
+    )?;
+    writeln!(out, "max stack depth: {}", post.max_stack_depth)?;
+    writeln!(out, "fonts: {}", post.fonts.len())?;
+    for font in usage::font_usage(&document) {
+        writeln!(
+            out,
+            "  {} {} at {:.2}pt",
+            font.def.number,
+            font.name(),
+            font.at_size
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn dump(args: Args) -> Result<()> {
+    let input = read(args.file()?)?;
+    let mut out = args.output()?;
+    let mut instructions = Instructions::new(&input);
+    loop {
+        let offset = instructions.offset();
+        match instructions.next() {
+            Some(Ok(instruction)) => {
+                writeln!(out, "{}: {}", offset, Instruction::from(instruction))?
+            }
+            Some(Err(_)) => {
+                out.flush()?;
+                return Err(format!("could not parse instruction at byte {}", offset).into());
+            }
+            None => break,
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Every character is zero width, which is enough to check the structure of the pages
+struct Zero;
+
+impl FontMetrics for Zero {
+    fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+        Some(0)
+    }
+}
+
+struct Null;
+
+impl Device for Null {}
+
+/// Problems with a document that parsed, found by interpreting it
+fn problems(document: &Document) -> Vec<String> {
+    let mut problems = Vec::new();
+    let postamble: HashMap<u32, &FontDef> = document
+        .postamble
+        .fonts
+        .iter()
+        .map(|def| (def.number, def))
+        .collect();
+    let mut defined = HashSet::new();
+    let mut interpreter = document.interpreter(Zero);
+    let mut depth = 0;
+    for (index, page) in document.pages.iter().enumerate() {
+        let number = index + 1;
+        let bop = Instruction::Bop(page.counters, -1);
+        let result = interpreter.execute(&bop, &mut Null);
+        let result = result.and_then(|()| {
+            page.instructions.iter().try_for_each(|instruction| {
+                match instruction {
+                    Instruction::FontDef(def) => {
+                        if postamble.get(&def.number) != Some(&def) {
+                            problems.push(format!(
+                                "page {}: font {} doesn't match the postamble",
+                                number, def.number
+                            ));
+                        }
+                        defined.insert(def.number);
+                    }
+                    Instruction::Font(font) if defined.insert(*font) => problems.push(format!(
+                        "page {}: font {} is used before it is defined",
+                        number, font
+                    )),
+                    _ => (),
+                }
+                interpreter.execute(instruction, &mut Null)?;
+                depth = depth.max(interpreter.stack_depth());
+                Ok(())
+            })
+        });
+        if let Err(e) = result.and_then(|()| {
+            if interpreter.stack_depth() != 0 {
+                problems.push(format!(
+                    "page {}: {} pushes without a pop",
+                    number,
+                    interpreter.stack_depth()
+                ));
+            }
+            interpreter.execute(&Instruction::Eop, &mut Null)
+        }) {
+            problems.push(format!("page {}: {}", number, e));
+        }
+    }
+    if depth > usize::from(document.postamble.max_stack_depth) {
+        problems.push(format!(
+            "the stack is {} deep, but the postamble says {}",
+            depth, document.postamble.max_stack_depth
+        ));
+    }
+    problems
+}
+
+fn validate(args: Args) -> Result<()> {
+    let path = args.file()?;
+    let document = open(path)?;
+    let problems = problems(&document);
+    for problem in &problems {
+        eprintln!("{}: {}", path, problem);
+    }
+    if problems.is_empty() {
+        println!("{}: ok, {} pages", path, document.pages.len());
+        Ok(())
+    } else {
+        Err(format!("{} problems found", problems.len()).into())
+    }
+}
+
+fn select(args: Args) -> Result<()> {
+    let (path, ranges) = match args.files.as_slice() {
+        [path, ranges] => (path, ranges),
+        _ => return Err("select needs a file and a list of pages".into()),
+    };
+    let document = open(path)?;
+    let selected = document.select(&page_indices(ranges, document.pages.len())?);
+    let mut out = args.output()?;
+    selected.dump(&mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn concat(args: Args) -> Result<()> {
+    let mut files = args.files.iter();
+    let mut document = open(files.next().ok_or("no input files")?)?;
+    for path in files {
+        document
+            .append(&open(path)?)
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+    let mut out = args.output()?;
+    document.dump(&mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn text(args: Args) -> Result<()> {
+    let path = args.file()?;
+    let mut document = open(path)?;
+    if let Some(ranges) = &args.pages {
+        document = document.select(&page_indices(ranges, document.pages.len())?);
+    }
+    // look next to the dvi file, then in the current directory
+    let mut directories = args.fonts.clone();
+    if let Some(parent) = Path::new(path).parent() {
+        directories.push(parent.to_path_buf());
+    }
+    directories.push(PathBuf::from("."));
+    let locator = Directories::new(directories, 600);
+    let mut fonts = HashMap::new();
+    for def in &document.postamble.fonts {
+        let name = String::from_utf8_lossy(&def.filename);
+        let bytes = locator
+            .tfm(def)
+            .ok_or_else(|| format!("no TFM file for {}, try --fonts", name))?;
+        let (_, tfm) =
+            Tfm::parse(&bytes).map_err(|_| format!("could not read the TFM file for {}", name))?;
+        fonts.insert(def.number, tfm);
+    }
+    let mut out = args.output()?;
+    for (index, page) in dvi::text::extract(&document, &fonts)?.iter().enumerate() {
+        if index > 0 {
+            // a form feed between pages, as pdftotext does
+            write!(out, "\x0c")?;
+        }
+        writeln!(out, "{}", page)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn fonts(args: Args) -> Result<()> {
+    let document = open(args.file()?)?;
+    let mut out = args.output()?;
+    for font in usage::font_usage(&document) {
+        writeln!(
+            out,
+            "{} {} at {:.2}pt (design {:.2}pt, mag {}), {} characters on {} pages",
+            font.def.number,
+            font.name(),
+            font.at_size,
+            font.design_size,
+            font.magnification,
+            font.chars.len(),
+            font.pages.len()
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Err(USAGE.into()),
+    };
+    let args = Args::parse(args)?;
+    match command.as_str() {
+        "info" => info(args),
+        "dump" => dump(args),
+        "validate" => validate(args),
+        "select" => select(args),
+        "concat" => concat(args),
+        "text" => text(args),
+        "fonts" => fonts(args),
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("unknown command {}\n{}", command, USAGE).into()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("dvi: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,71 @@
+//! Convert a dvi file to JSON or YAML
+//!
+//! Usage: `dvi2json [--compact] [--yaml] [input.dvi [output.json]]`
+//!
+//! Reads standard input and writes standard output when files aren't given. The JSON is a
+//! `Document`, in the shape described in the crate documentation. YAML is written instead with
+//! `--yaml` or when the output file ends in `.yaml` or `.yml`, in the same shape.
+
+use dvi::document::Document;
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    process,
+};
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut compact = false;
+    let mut yaml = false;
+    let mut files = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--compact" => compact = true,
+            "--yaml" => yaml = true,
+            "-h" | "--help" => {
+                println!("usage: dvi2json [--compact] [--yaml] [input.dvi [output.json]]");
+                return Ok(());
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.len() > 2 {
+        return Err("too many arguments".into());
+    }
+    yaml |= files
+        .get(1)
+        .is_some_and(|path| path.ends_with(".yaml") || path.ends_with(".yml"));
+
+    let input = match files.first() {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            input
+        }
+    };
+    let document = Document::parse(&input)?;
+    let mut output: Box<dyn Write> = match files.get(1) {
+        Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout())),
+    };
+    if yaml {
+        // YAML already ends with a newline
+        serde_yaml::to_writer(&mut output, &document)?;
+    } else {
+        if compact {
+            serde_json::to_writer(&mut output, &document)?;
+        } else {
+            serde_json::to_writer_pretty(&mut output, &document)?;
+        }
+        writeln!(output)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("dvi2json: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,63 @@
+//! Convert JSON or YAML written by `dvi2json` back to a dvi file
+//!
+//! Usage: `json2dvi [--yaml] [input.json [output.dvi]]`
+//!
+//! Reads standard input and writes standard output when files aren't given. The input is read as
+//! YAML with `--yaml` or when the input file ends in `.yaml` or `.yml`. The pointers between
+//! pages are worked out again, so pages can be added, removed or reordered in the JSON.
+
+use dvi::document::Document;
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    process,
+};
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut yaml = false;
+    let mut files = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--yaml" => yaml = true,
+            "-h" | "--help" => {
+                println!("usage: json2dvi [--yaml] [input.json [output.dvi]]");
+                return Ok(());
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.len() > 2 {
+        return Err("too many arguments".into());
+    }
+    yaml |= files
+        .first()
+        .is_some_and(|path| path.ends_with(".yaml") || path.ends_with(".yml"));
+
+    let input = match files.first() {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+    let document: Document = if yaml {
+        serde_yaml::from_str(&input)?
+    } else {
+        serde_json::from_str(&input)?
+    };
+    let mut output: Box<dyn Write> = match files.get(1) {
+        Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout())),
+    };
+    document.dump(&mut output)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("json2dvi: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,217 @@
+//! Instructions that borrow their payloads from the input
+//!
+//! Parsing an `Instruction` copies the payloads of specials, font definitions and the preamble
+//! into `Vec`s. For files with a lot of specials, like TikZ output full of PostScript, that copying
+//! is most of the work. [`InstructionRef`] points into the input instead, and can be turned into
+//! an `Instruction` when one is needed.
+//!
+//! [`InstructionRef`]: ./enum.InstructionRef.html
+
+use crate::{parser, FontDef, Instruction, ParseLimits};
+use nom::error::ErrorKind;
+
+/// A font definition borrowing its names from the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontDefRef<'a> {
+    pub number: u32,
+    pub checksum: u32,
+    pub scale_factor: u32,
+    pub design_size: u32,
+    pub directory: Option<&'a [u8]>,
+    pub filename: &'a [u8],
+}
+
+impl<'a> FontDefRef<'a> {
+    /// Copy the names to make a `FontDef`
+    pub fn to_owned(&self) -> FontDef {
+        FontDef {
+            number: self.number,
+            checksum: self.checksum,
+            scale_factor: self.scale_factor,
+            design_size: self.design_size,
+            directory: self.directory.map(<[u8]>::to_vec),
+            filename: self.filename.to_vec(),
+        }
+    }
+}
+
+/// An instruction borrowing its payload from the input
+///
+/// Only the instructions with payloads have their own variants, everything else is kept as the
+/// `Instruction` it is, which doesn't need to allocate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstructionRef<'a> {
+    /// A special, see `Instruction::Xxx`
+    Xxx(&'a [u8]),
+    /// A font definition, see `Instruction::FontDef`
+    FontDef(FontDefRef<'a>),
+    /// The preamble, see `Instruction::Pre`
+    Pre {
+        format: u8,
+        numerator: u32,
+        denominator: u32,
+        magnification: u32,
+        comment: &'a [u8],
+    },
+    /// Any other instruction
+    Other(Instruction),
+}
+
+impl<'a> InstructionRef<'a> {
+    /// Parse an instruction without copying its payload
+    pub fn parse(bytes: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+        parser::parse_ref(bytes)
+    }
+
+    /// Parse an instruction without copying its payload, failing with `ErrorKind::TooLarge` if
+    /// the payload is longer than the limits allow
+    pub fn parse_limited(bytes: &'a [u8], limits: &ParseLimits) -> nom::IResult<&'a [u8], Self> {
+        parser::check_limits(bytes, limits)?;
+        parser::parse_ref(bytes)
+    }
+
+    /// Copy the payload to make an `Instruction`
+    pub fn to_owned(&self) -> Instruction {
+        match *self {
+            InstructionRef::Xxx(payload) => Instruction::Xxx(payload.to_vec()),
+            InstructionRef::FontDef(ref def) => Instruction::FontDef(def.to_owned()),
+            InstructionRef::Pre {
+                format,
+                numerator,
+                denominator,
+                magnification,
+                comment,
+            } => Instruction::Pre {
+                format,
+                numerator,
+                denominator,
+                magnification,
+                comment: comment.to_vec(),
+            },
+            InstructionRef::Other(ref instruction) => instruction.clone(),
+        }
+    }
+}
+
+impl<'a> From<InstructionRef<'a>> for Instruction {
+    fn from(instruction: InstructionRef<'a>) -> Self {
+        match instruction {
+            InstructionRef::Other(instruction) => instruction,
+            instruction => instruction.to_owned(),
+        }
+    }
+}
+
+/// An iterator over the instructions in a byte slice
+///
+/// Iteration stops at the end of the input, or after the first error.
+#[derive(Debug, Clone)]
+pub struct Instructions<'a> {
+    input: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> Instructions<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Instructions {
+            input,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// The offset of the next instruction in the input
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The input that hasn't been parsed yet
+    pub fn rest(&self) -> &'a [u8] {
+        self.input
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<InstructionRef<'a>, nom::Err<(&'a [u8], ErrorKind)>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() || self.failed {
+            return None;
+        }
+        match parser::parse_ref(self.input) {
+            Ok((rest, instruction)) => {
+                self.offset += self.input.len() - rest.len();
+                self.input = rest;
+                Some(Ok(instruction))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{vec, vec::Vec};
+
+    #[test]
+    fn borrow() {
+        let instructions = vec![
+            Instruction::Pre {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::from("comment"),
+            },
+            Instruction::FontDef(FontDef {
+                number: 300,
+                checksum: 1,
+                scale_factor: 2,
+                design_size: 3,
+                directory: Some(Vec::from("fonts")),
+                filename: Vec::from("cmr10"),
+            }),
+            Instruction::Xxx(vec![b'x'; 300]),
+            Instruction::Right(-5),
+        ];
+        let mut input = Vec::new();
+        for instruction in &instructions {
+            instruction.dump(&mut input).unwrap();
+        }
+
+        let mut iter = Instructions::new(&input);
+        let parsed = iter.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(iter.offset(), input.len());
+        match parsed[2] {
+            // the payload points into the input
+            InstructionRef::Xxx(payload) => {
+                assert_eq!(payload.as_ptr(), input[input.len() - 302..].as_ptr())
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(parsed[3], InstructionRef::Other(Instruction::Right(-5)));
+        let owned = parsed
+            .into_iter()
+            .map(Instruction::from)
+            .collect::<Vec<_>>();
+        assert_eq!(owned, instructions);
+    }
+
+    #[test]
+    fn stops_at_errors() {
+        let input = [138, 250, 138];
+        let mut iter = Instructions::new(&input);
+        assert_eq!(
+            iter.next(),
+            Some(Ok(InstructionRef::Other(Instruction::Nop)))
+        );
+        assert!(iter.next().unwrap().is_err());
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.rest(), &input[1..]);
+    }
+}
@@ -0,0 +1,380 @@
+//! Checking font definitions against the font files
+//!
+//! Every font definition in a dvi file records the checksum and design size of the TFM file TeX
+//! used. If the font files a driver finds are from a different version of the font, the document
+//! still renders, just with the wrong widths or shapes. [`verify`] compares the definitions with
+//! the TFM, PK and VF files a [`FontLocator`] finds, and reports the differences.
+//!
+//! A checksum of 0 on either side means the checksum isn't known, so it isn't compared.
+//!
+//! [`verify`]: ./fn.verify.html
+//! [`FontLocator`]: ./trait.FontLocator.html
+
+use crate::{document::Document, pk::PkFont, tfm::Tfm, usage, FontDef};
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{be_u32, be_u8},
+    IResult,
+};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Somewhere to find the files for a font
+///
+/// Each method returns the contents of the file, or `None` if there isn't one.
+pub trait FontLocator {
+    fn tfm(&self, _font: &FontDef) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Find a PK file, for a font used at `magnification` (times 1000)
+    fn pk(&self, _font: &FontDef, _magnification: u32) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn vf(&self, _font: &FontDef) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A `FontLocator` that looks in a list of directories
+///
+/// TFM and VF files are found as `<name>.tfm` and `<name>.vf`, and PK files as `<name>.<dpi>pk`
+/// for the font's resolution at its magnification, as `mktexpk` names them. The font's own
+/// directory, if it has one, is searched first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directories {
+    pub directories: Vec<PathBuf>,
+    /// The resolution of PK files in dots per inch, before magnification
+    pub resolution: u32,
+}
+
+impl Directories {
+    pub fn new(directories: Vec<PathBuf>, resolution: u32) -> Self {
+        Directories {
+            directories,
+            resolution,
+        }
+    }
+
+    fn find(&self, font: &FontDef, file: &str) -> Option<Vec<u8>> {
+        let own = font
+            .directory
+            .as_ref()
+            .map(|dir| PathBuf::from(String::from_utf8_lossy(dir).into_owned()));
+        own.iter()
+            .chain(&self.directories)
+            .find_map(|dir| fs::read(Path::new(dir).join(file)).ok())
+    }
+}
+
+impl FontLocator for Directories {
+    fn tfm(&self, font: &FontDef) -> Option<Vec<u8>> {
+        self.find(font, &format!("{}.tfm", name(font)))
+    }
+
+    fn pk(&self, font: &FontDef, magnification: u32) -> Option<Vec<u8>> {
+        self.find(font, &pk_name(font, self.resolution, magnification))
+    }
+
+    fn vf(&self, font: &FontDef) -> Option<Vec<u8>> {
+        self.find(font, &format!("{}.vf", name(font)))
+    }
+}
+
+fn name(font: &FontDef) -> String {
+    String::from_utf8_lossy(&font.filename).into_owned()
+}
+
+/// The file name of a PK file, like `cmr10.600pk`
+fn pk_name(font: &FontDef, resolution: u32, magnification: u32) -> String {
+    let dpi = (f64::from(resolution) * f64::from(magnification) / 1000.0).round();
+    format!("{}.{}pk", name(font), dpi)
+}
+
+/// The kind of font file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    Tfm,
+    Pk,
+    Vf,
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FileKind::Tfm => "TFM",
+            FileKind::Pk => "PK",
+            FileKind::Vf => "VF",
+        })
+    }
+}
+
+/// Something wrong with a font
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// None of the font's files could be found
+    NotFound,
+    /// A file was found but couldn't be read
+    Invalid(FileKind),
+    ChecksumMismatch {
+        file: FileKind,
+        expected: u32,
+        found: u32,
+    },
+    /// The design sizes differ, both in dvi units
+    DesignSizeMismatch {
+        file: FileKind,
+        expected: u32,
+        found: u32,
+    },
+}
+
+/// A problem with one of the fonts in a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub font: FontDef,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "font {} ({}): ", self.font.number, name(&self.font))?;
+        match self.problem {
+            Problem::NotFound => write!(f, "no font files found"),
+            Problem::Invalid(file) => write!(f, "{} file is invalid", file),
+            Problem::ChecksumMismatch {
+                file,
+                expected,
+                found,
+            } => write!(
+                f,
+                "checksum mismatch: dvi file has {:08x}, {} file has {:08x}",
+                expected, file, found
+            ),
+            Problem::DesignSizeMismatch {
+                file,
+                expected,
+                found,
+            } => write!(
+                f,
+                "design size mismatch: dvi file has {}, {} file has {}",
+                expected, file, found
+            ),
+        }
+    }
+}
+
+/// Check every font in a document against the files `locator` finds
+///
+/// Design sizes are compared assuming the document uses TeX's units, where a dvi unit is a scaled
+/// point.
+pub fn verify<L: FontLocator>(document: &Document, locator: &L) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for usage in usage::font_usage(document) {
+        let font = usage.def;
+        let mut found = false;
+        let files = [
+            (FileKind::Tfm, locator.tfm(&font)),
+            (FileKind::Pk, locator.pk(&font, usage.magnification)),
+            (FileKind::Vf, locator.vf(&font)),
+        ];
+        for (kind, bytes) in files.iter() {
+            let bytes = match bytes {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            found = true;
+            let mut report = |problem| {
+                diagnostics.push(Diagnostic {
+                    font: font.clone(),
+                    problem,
+                })
+            };
+            let header = match kind {
+                FileKind::Tfm => Tfm::parse(bytes)
+                    .ok()
+                    .map(|(_, tfm)| (tfm.checksum, tfm.design_size as u32)),
+                FileKind::Pk => PkFont::parse(bytes)
+                    .ok()
+                    .map(|(_, pk)| (pk.checksum, pk.design_size)),
+                FileKind::Vf => vf_header(bytes).ok().map(|(_, header)| header),
+            };
+            let (checksum, design_size) = match header {
+                Some(header) => header,
+                None => {
+                    report(Problem::Invalid(*kind));
+                    continue;
+                }
+            };
+            if checksum != 0 && font.checksum != 0 && checksum != font.checksum {
+                report(Problem::ChecksumMismatch {
+                    file: *kind,
+                    expected: font.checksum,
+                    found: checksum,
+                });
+            }
+            // font files give the design size as a fix_word, with 20 fractional bits
+            let design_size = design_size >> 4;
+            if design_size != font.design_size {
+                report(Problem::DesignSizeMismatch {
+                    file: *kind,
+                    expected: font.design_size,
+                    found: design_size,
+                });
+            }
+        }
+        if !found {
+            diagnostics.push(Diagnostic {
+                font,
+                problem: Problem::NotFound,
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Read the checksum and design size from the preamble of a VF file
+fn vf_header(input: &[u8]) -> IResult<&[u8], (u32, u32)> {
+    let (input, _) = tag(&[247, 202][..])(input)?;
+    let (input, comment_len) = be_u8(input)?;
+    let (input, _) = take(comment_len)(input)?;
+    let (input, checksum) = be_u32(input)?;
+    let (input, design_size) = be_u32(input)?;
+    Ok((input, (checksum, design_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Postamble, Preamble},
+        tfm::tests::tfm_bytes,
+    };
+    use std::collections::HashMap;
+
+    /// Files for each font by name
+    #[derive(Default)]
+    struct Files(HashMap<(String, FileKind), Vec<u8>>);
+
+    impl FontLocator for Files {
+        fn tfm(&self, font: &FontDef) -> Option<Vec<u8>> {
+            self.0.get(&(name(font), FileKind::Tfm)).cloned()
+        }
+
+        fn pk(&self, font: &FontDef, _: u32) -> Option<Vec<u8>> {
+            self.0.get(&(name(font), FileKind::Pk)).cloned()
+        }
+
+        fn vf(&self, font: &FontDef) -> Option<Vec<u8>> {
+            self.0.get(&(name(font), FileKind::Vf)).cloned()
+        }
+    }
+
+    fn font(number: u32, filename: &str, checksum: u32) -> FontDef {
+        FontDef {
+            number,
+            checksum,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from(filename),
+        }
+    }
+
+    fn pk_bytes(checksum: u32, design_size: u32) -> Vec<u8> {
+        let mut out = vec![247, 89, 0];
+        out.extend_from_slice(&design_size.to_be_bytes());
+        out.extend_from_slice(&checksum.to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 245]);
+        out
+    }
+
+    fn vf_bytes(checksum: u32, design_size: u32) -> Vec<u8> {
+        let mut out = vec![247, 202, 2, b'h', b'i'];
+        out.extend_from_slice(&checksum.to_be_bytes());
+        out.extend_from_slice(&design_size.to_be_bytes());
+        out.push(248);
+        out
+    }
+
+    #[test]
+    fn mismatches() {
+        let document = Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: Vec::new(),
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![
+                    font(0, "good", 0x1234_5678),
+                    font(1, "unknown", 0),
+                    font(2, "bad", 0x1234_5678),
+                    font(3, "missing", 0x1234_5678),
+                ],
+            },
+        };
+        let mut files = Files::default();
+        let mut add = |name: &str, kind, bytes| {
+            files.0.insert((name.to_string(), kind), bytes);
+        };
+        add("good", FileKind::Tfm, tfm_bytes());
+        add("good", FileKind::Pk, pk_bytes(0x1234_5678, 10 << 20));
+        add("good", FileKind::Vf, vf_bytes(0, 10 << 20));
+        add("unknown", FileKind::Pk, pk_bytes(0xdead_beef, 10 << 20));
+        add("bad", FileKind::Pk, pk_bytes(0xdead_beef, 12 << 20));
+        add("bad", FileKind::Vf, vec![247, 202, 10]);
+
+        let diagnostics = verify(&document, &files);
+        let bad = font(2, "bad", 0x1234_5678);
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    font: bad.clone(),
+                    problem: Problem::ChecksumMismatch {
+                        file: FileKind::Pk,
+                        expected: 0x1234_5678,
+                        found: 0xdead_beef,
+                    },
+                },
+                Diagnostic {
+                    font: bad.clone(),
+                    problem: Problem::DesignSizeMismatch {
+                        file: FileKind::Pk,
+                        expected: 10 << 16,
+                        found: 12 << 16,
+                    },
+                },
+                Diagnostic {
+                    font: bad,
+                    problem: Problem::Invalid(FileKind::Vf),
+                },
+                Diagnostic {
+                    font: font(3, "missing", 0x1234_5678),
+                    problem: Problem::NotFound,
+                },
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "font 2 (bad): checksum mismatch: dvi file has 12345678, PK file has deadbeef"
+        );
+    }
+
+    #[test]
+    fn pk_names() {
+        let cmr10 = font(0, "cmr10", 0);
+        assert_eq!(pk_name(&cmr10, 600, 1000), "cmr10.600pk");
+        assert_eq!(pk_name(&cmr10, 600, 1200), "cmr10.720pk");
+    }
+}
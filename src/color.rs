@@ -0,0 +1,116 @@
+//! Tracking of dvips colour specials
+//!
+//! dvips keeps a stack of colours that is changed by `color push`, `color pop` and `color`
+//! specials. The stack isn't reset between pages, so the colour at the start of a page depends on
+//! every page before it. The [`Interpreter`] keeps a [`ColorStack`] up to date and reports changes
+//! to its device, and [`Document::color_stacks`] works out the stack at the start of each page so
+//! pages can be interpreted out of order.
+//!
+//! [`Interpreter`]: ../interpreter/struct.Interpreter.html
+//! [`ColorStack`]: ./struct.ColorStack.html
+//! [`Document::color_stacks`]: ../document/struct.Document.html#method.color_stacks
+
+pub use crate::special::Color;
+use crate::special::Special;
+
+/// The colours saved by `color push` specials
+///
+/// The bottom of the stack is the colour set by the last `color` special, black by default, and
+/// it can't be popped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorStack {
+    stack: Vec<Color>,
+}
+
+impl Default for ColorStack {
+    fn default() -> Self {
+        ColorStack {
+            stack: vec![Color::Gray(0.0)],
+        }
+    }
+}
+
+impl ColorStack {
+    /// A stack holding only black
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The colour things are drawn in
+    pub fn current(&self) -> &Color {
+        self.stack.last().unwrap()
+    }
+
+    /// The colours on the stack, starting from the bottom
+    pub fn stack(&self) -> &[Color] {
+        &self.stack
+    }
+
+    /// Save the current colour and switch to `color`
+    pub fn push(&mut self, color: Color) {
+        self.stack.push(color);
+    }
+
+    /// Go back to the colour before the last push
+    ///
+    /// Like dvips, popping the last colour is ignored.
+    pub fn pop(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    /// Throw away the stack, and use `color` from now on
+    pub fn set(&mut self, color: Color) {
+        self.stack.clear();
+        self.stack.push(color);
+    }
+
+    /// Update the stack for a special, returning whether it was a colour special
+    pub fn apply(&mut self, special: &Special) -> bool {
+        match special {
+            Special::ColorPush(color) => self.push(color.clone()),
+            Special::ColorPop => self.pop(),
+            Special::Color(color) => self.set(color.clone()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Update the stack for the payload of an `Xxx`, returning whether it was a colour special
+    ///
+    /// Only payloads starting with `color` are parsed, so PostScript and PDF specials, which can
+    /// be long, are skipped without being copied.
+    pub fn apply_payload(&mut self, payload: &[u8]) -> bool {
+        let start = payload
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(payload.len());
+        payload[start..].starts_with(b"color") && self.apply(&Special::parse(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack() {
+        let mut colors = ColorStack::new();
+        assert_eq!(colors.current(), &Color::Gray(0.0));
+        colors.pop();
+        assert_eq!(colors.stack().len(), 1);
+        assert!(colors.apply(&Special::ColorPush(Color::Named("Red".into()))));
+        assert!(colors.apply(&Special::ColorPush(Color::Rgb(0.0, 0.0, 1.0))));
+        assert_eq!(colors.current(), &Color::Rgb(0.0, 0.0, 1.0));
+        assert!(colors.apply(&Special::ColorPop));
+        assert_eq!(colors.current(), &Color::Named("Red".into()));
+        assert!(colors.apply(&Special::Color(Color::Gray(0.5))));
+        assert_eq!(colors.stack(), &[Color::Gray(0.5)]);
+        assert!(!colors.apply(&Special::Landscape));
+        assert!(colors.apply_payload(b" color push gray 0.25"));
+        assert_eq!(colors.current(), &Color::Gray(0.25));
+        assert!(!colors.apply_payload(b"ps: 1 0 0 setrgbcolor"));
+        assert!(!colors.apply_payload(b"colorful"));
+    }
+}
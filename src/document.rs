@@ -0,0 +1,1191 @@
+//! Whole dvi documents
+//!
+//! Rather than a flat list of instructions, a [`Document`] is split into the preamble, the pages
+//! and the postamble. The pointers that link pages together are recalculated when a document is
+//! dumped, so pages can be added, removed and reordered freely.
+//!
+//! [`Document`]: ./struct.Document.html
+
+use crate::{
+    color::ColorStack,
+    interpreter::{self, Device, FontMetrics, Interpreter},
+    limits::{Limit, Tally},
+    special::Special,
+    util::{bp_per_unit, dimension},
+    FontDef, Instruction, ParseLimits, Sink,
+};
+use nom::{error::ErrorKind, IResult};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryFrom,
+    error, fmt,
+};
+
+/// A parsed dvi file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Document {
+    pub preamble: Preamble,
+    pub pages: Vec<Page>,
+    pub postamble: Postamble,
+}
+
+/// The contents of the `Pre` instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Preamble {
+    pub format: u8,
+    pub numerator: u32,
+    pub denominator: u32,
+    pub magnification: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize"))]
+    pub comment: Vec<u8>,
+}
+
+/// A single page
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Page {
+    /// The ten counters from the `Bop` instruction, usually TeX's `\count0` to `\count9`
+    pub counters: [i32; 10],
+    /// The instructions between `Bop` and `Eop`
+    ///
+    /// Font definitions that appear between pages are moved to the start of the following page.
+    /// Those after the last page must match a definition in the postamble, which takes their
+    /// place.
+    pub instructions: Vec<Instruction>,
+}
+
+/// The postamble, without the pointers, which are worked out when the document is dumped
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Postamble {
+    /// The height plus depth of the tallest page, in dvi units
+    pub tallest_height: i32,
+    /// The width of the widest page, in dvi units
+    pub widest_width: i32,
+    pub max_stack_depth: u16,
+    /// Every font used in the document
+    pub fonts: Vec<FontDef>,
+}
+
+/// Things that can go wrong when reading a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The bytes at this offset aren't a valid instruction
+    Parse(usize),
+    /// The instruction at this offset isn't allowed where it is
+    Unexpected(usize),
+    /// The file ended before the postamble was complete
+    Truncated,
+    /// Documents with different units or magnification can't be put together
+    Incompatible,
+    /// A page uses a font number that isn't defined anywhere in the document
+    UndefinedFont(u32),
+    /// Every font number is taken, so a font can't be renumbered
+    NoFreeFontNumber,
+    /// The instruction at this offset goes over one of the `ParseLimits`
+    Limit(Limit, usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(offset) => write!(f, "could not parse instruction at byte {}", offset),
+            Error::Unexpected(offset) => write!(f, "unexpected instruction at byte {}", offset),
+            Error::Truncated => write!(f, "the file ended before the postamble"),
+            Error::Incompatible => write!(f, "the documents have different units or magnification"),
+            Error::UndefinedFont(number) => write!(f, "font {} is used but never defined", number),
+            Error::NoFreeFontNumber => write!(f, "there are no font numbers left"),
+            Error::Limit(limit, offset) => {
+                write!(f, "the {} limit was exceeded at byte {}", limit, offset)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Where the parser is in the structure of the file
+enum State {
+    Start,
+    BetweenPages,
+    InPage,
+    Postamble,
+}
+
+impl Document {
+    /// Parse a complete dvi file
+    pub fn parse(input: &[u8]) -> Result<Self, Error> {
+        Document::parse_with_limits(input, &ParseLimits::unlimited())
+    }
+
+    /// Parse a complete dvi file, failing with `Error::Limit` as soon as it goes over one of the
+    /// limits
+    ///
+    /// Use this for files from untrusted sources.
+    pub fn parse_with_limits(input: &[u8], limits: &ParseLimits) -> Result<Self, Error> {
+        Document::parse_partial(input, limits).map(|(_, document)| document)
+    }
+
+    /// Parse a dvi file, returning what follows the end of the postamble
+    pub(crate) fn parse_partial<'a>(
+        input: &'a [u8],
+        limits: &ParseLimits,
+    ) -> Result<(&'a [u8], Self), Error> {
+        let mut tally = Tally::new(*limits);
+        let mut rest = input;
+        let mut state = State::Start;
+        let mut preamble = None;
+        let mut pages = Vec::new();
+        let mut between = Vec::new();
+        let mut post = None;
+        let mut fonts = Vec::new();
+        loop {
+            let offset = input.len() - rest.len();
+            tally
+                .check_payload(rest)
+                .map_err(|limit| Error::Limit(limit, offset))?;
+            let (next, instruction) = match Instruction::parse(rest) {
+                Ok(ok) => ok,
+                Err(nom::Err::Incomplete(_)) => return Err(Error::Truncated),
+                Err(_) => return Err(Error::Parse(offset)),
+            };
+            tally
+                .count(&instruction)
+                .map_err(|limit| Error::Limit(limit, offset))?;
+            rest = next;
+            match (&state, instruction) {
+                (
+                    State::Start,
+                    Instruction::Pre {
+                        format,
+                        numerator,
+                        denominator,
+                        magnification,
+                        comment,
+                    },
+                ) => {
+                    preamble = Some(Preamble {
+                        format,
+                        numerator,
+                        denominator,
+                        magnification,
+                        comment,
+                    });
+                    state = State::BetweenPages;
+                }
+                (State::BetweenPages, Instruction::Nop) => (),
+                (State::BetweenPages, Instruction::FontDef(def)) => between.push((offset, def)),
+                (State::BetweenPages, Instruction::Bop(counters, _)) => {
+                    pages.push(Page {
+                        counters,
+                        instructions: between
+                            .drain(..)
+                            .map(|(_, def)| Instruction::FontDef(def))
+                            .collect(),
+                    });
+                    state = State::InPage;
+                }
+                (
+                    State::BetweenPages,
+                    Instruction::Post {
+                        tallest_height,
+                        widest_width,
+                        max_stack_depth,
+                        ..
+                    },
+                ) => {
+                    post = Some((tallest_height, widest_width, max_stack_depth));
+                    state = State::Postamble;
+                }
+                (State::InPage, Instruction::Eop) => state = State::BetweenPages,
+                (State::InPage, Instruction::Bop(..))
+                | (State::InPage, Instruction::Pre { .. })
+                | (State::InPage, Instruction::Post { .. })
+                | (State::InPage, Instruction::PostPost { .. }) => {
+                    return Err(Error::Unexpected(offset))
+                }
+                (State::InPage, instruction) => {
+                    // there is always a page when in this state
+                    if let Some(page) = pages.last_mut() {
+                        page.instructions.push(instruction);
+                    }
+                }
+                (State::Postamble, Instruction::Nop) => (),
+                (State::Postamble, Instruction::FontDef(def)) => fonts.push(def),
+                (State::Postamble, Instruction::PostPost { .. }) => break,
+                _ => return Err(Error::Unexpected(offset)),
+            }
+        }
+        let (tallest_height, widest_width, max_stack_depth) = post.ok_or(Error::Truncated)?;
+        check_trailing_font_defs(&between, &fonts)?;
+        let document = Document {
+            preamble: preamble.ok_or(Error::Truncated)?,
+            pages,
+            postamble: Postamble {
+                tallest_height,
+                widest_width,
+                max_stack_depth,
+                fonts,
+            },
+        };
+        Ok((rest, document))
+    }
+
+    /// Memory-map a dvi file, to read its pages as they are needed
+    ///
+    /// This only reads the postamble and the pointers between pages, so it takes the same time
+    /// however large the file is.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed, by this process or any other, while the returned document
+    /// or anything borrowed from it is alive. The map shares the file's pages with the operating
+    /// system, so a change shows up in memory the parser has already checked, which is undefined
+    /// behaviour, and truncating the file makes reading past the new end crash with `SIGBUS`.
+    /// Use `Document::parse` on the file's contents instead when this can't be guaranteed.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open_mmap(
+        path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<crate::lazy::LazyDocument<memmap2::Mmap>> {
+        let file = std::fs::File::open(path)?;
+        // Safety: the caller promises the file isn't changed while it is mapped
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        crate::lazy::LazyDocument::new(map)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write the document as a dvi file
+    ///
+    /// The page count in the postamble only has 16 bits, so it is 65535 for longer documents.
+    pub fn dump<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<(), S::Error> {
+        let mut writer = Counting {
+            inner: sink,
+            count: 0,
+        };
+        let pre = &self.preamble;
+        Instruction::Pre {
+            format: pre.format,
+            numerator: pre.numerator,
+            denominator: pre.denominator,
+            magnification: pre.magnification,
+            comment: pre.comment.clone(),
+        }
+        .dump(&mut writer)?;
+        let mut last_bop = -1;
+        for page in &self.pages {
+            let bop = writer.count as i32;
+            Instruction::Bop(page.counters, last_bop).dump(&mut writer)?;
+            last_bop = bop;
+            for instruction in &page.instructions {
+                instruction.dump(&mut writer)?;
+            }
+            Instruction::Eop.dump(&mut writer)?;
+        }
+        let post_pointer = writer.count;
+        dump_postamble(
+            pre,
+            &self.postamble,
+            u16::try_from(self.pages.len()).unwrap_or(u16::MAX),
+            last_bop,
+            post_pointer,
+            writer.inner,
+        )
+    }
+
+    /// An interpreter that knows about every font in the document
+    ///
+    /// This allows pages to be interpreted in any order.
+    pub fn interpreter<M: FontMetrics>(&self, metrics: M) -> Interpreter<M> {
+        let mut interpreter = Interpreter::new(metrics);
+        for font in &self.postamble.fonts {
+            interpreter.define_font(font.clone());
+        }
+        interpreter
+    }
+
+    /// Interpret every page of the document
+    pub fn run<M: FontMetrics, D: Device>(
+        &self,
+        metrics: M,
+        device: &mut D,
+    ) -> Result<(), interpreter::Error> {
+        let mut interpreter = self.interpreter(metrics);
+        let pre = &self.preamble;
+        device.preamble(pre.numerator, pre.denominator, pre.magnification);
+        for page in &self.pages {
+            page.run(&mut interpreter, device)?;
+        }
+        Ok(())
+    }
+
+    /// The colour stack at the start of each page
+    ///
+    /// Colour specials carry over from one page to the next, so this has to look at every page
+    /// before the one of interest.
+    pub fn color_stacks(&self) -> Vec<ColorStack> {
+        let mut colors = ColorStack::new();
+        let mut stacks = Vec::with_capacity(self.pages.len());
+        for page in &self.pages {
+            stacks.push(colors.clone());
+            for instruction in &page.instructions {
+                if let Instruction::Xxx(payload) = instruction {
+                    colors.apply_payload(payload);
+                }
+            }
+        }
+        stacks
+    }
+
+    /// Interpret a single page, starting with the colour stack `colors`
+    ///
+    /// Pass the page's entry from `color_stacks` to get the colours it would have when running
+    /// the whole document. Working out the stacks means looking at every page, so do it once
+    /// when running several pages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not the index of a page.
+    pub fn run_page<M: FontMetrics, D: Device>(
+        &self,
+        index: usize,
+        colors: &ColorStack,
+        metrics: M,
+        device: &mut D,
+    ) -> Result<(), interpreter::Error> {
+        let mut interpreter = self.interpreter(metrics);
+        interpreter.set_colors(colors.clone());
+        let pre = &self.preamble;
+        device.preamble(pre.numerator, pre.denominator, pre.magnification);
+        self.pages[index].run(&mut interpreter, device)
+    }
+
+    /// Interpret every page in parallel, each with a new device made by `device`
+    ///
+    /// Each page starts with every font from the postamble defined and the colour stack it would
+    /// have when running the whole document, so the results are the same as running the pages
+    /// one after another. The devices are returned in page order.
+    ///
+    /// For example, `document.par_run(&fonts, |_| Raster::new(&fonts, 600))` renders each page
+    /// into its own `Raster`.
+    #[cfg(feature = "rayon")]
+    pub fn par_run<M, D, F>(&self, metrics: M, device: F) -> Vec<Result<D, interpreter::Error>>
+    where
+        M: FontMetrics + Clone + Send + Sync,
+        D: Device + Send,
+        F: Fn(usize) -> D + Sync,
+    {
+        use rayon::prelude::*;
+
+        let interpreter = self.interpreter(metrics);
+        let pre = &self.preamble;
+        self.pages
+            .par_iter()
+            .zip(self.color_stacks())
+            .enumerate()
+            .map(|(index, (page, colors))| {
+                let mut interpreter = interpreter.clone();
+                interpreter.set_colors(colors);
+                let mut device = device(index);
+                device.preamble(pre.numerator, pre.denominator, pre.magnification);
+                page.run(&mut interpreter, &mut device)?;
+                Ok(device)
+            })
+            .collect()
+    }
+
+    /// The size of the paper for the document
+    ///
+    /// This comes from the first `papersize=` or `pdf:pagesize` special, otherwise from the size of
+    /// the largest page given in the postamble plus TeX's one inch margins, otherwise US letter. A
+    /// `landscape` special anywhere in the document turns the paper on its side.
+    pub fn paper_size(&self) -> PaperSize {
+        let pre = &self.preamble;
+        let scale = bp_per_unit(pre.numerator, pre.denominator, pre.magnification);
+        let explicit = self
+            .pages
+            .iter()
+            .find_map(|page| page.paper_size(&self.preamble));
+        let post = &self.postamble;
+        let mut size = if let Some(size) = explicit {
+            size
+        } else if post.widest_width > 0 && post.tallest_height > 0 {
+            let margins = (144.0 / scale).round() as i32;
+            PaperSize {
+                width: post.widest_width.saturating_add(margins),
+                height: post.tallest_height.saturating_add(margins),
+                scale,
+            }
+        } else {
+            PaperSize::from_bp(612.0, 792.0, scale)
+        };
+        let landscape = self.specials().any(|special| special == Special::Landscape);
+        if landscape && size.height > size.width {
+            std::mem::swap(&mut size.width, &mut size.height);
+        }
+        size
+    }
+
+    /// The size of the paper for page `index`
+    ///
+    /// A `pdf:pagesize` special on the page itself takes precedence over the size for the whole
+    /// document.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not the index of a page.
+    pub fn page_size(&self, index: usize) -> PaperSize {
+        self.own_page_size(index)
+            .unwrap_or_else(|| self.paper_size())
+    }
+
+    /// The size of the paper for every page
+    ///
+    /// This is the same as calling `page_size` for each page, but only works out the size for
+    /// the whole document once.
+    pub fn page_sizes(&self) -> Vec<PaperSize> {
+        let paper = self.paper_size();
+        (0..self.pages.len())
+            .map(|index| self.own_page_size(index).unwrap_or(paper))
+            .collect()
+    }
+
+    /// The size from a `pdf:pagesize` special on page `index`
+    fn own_page_size(&self, index: usize) -> Option<PaperSize> {
+        self.pages[index]
+            .specials()
+            .find_map(|special| match special {
+                Special::Pdf { command, args } if command == "pagesize" => {
+                    let (width, height) = pdf_pagesize(&args)?;
+                    let pre = &self.preamble;
+                    let scale = bp_per_unit(pre.numerator, pre.denominator, pre.magnification);
+                    Some(PaperSize::from_bp(width, height, scale))
+                }
+                _ => None,
+            })
+    }
+
+    /// Every special in the document
+    fn specials(&self) -> impl Iterator<Item = Special> + '_ {
+        self.pages.iter().flat_map(Page::specials)
+    }
+
+    /// A new document with copies of the pages at `indices`, in that order
+    ///
+    /// Pages may be repeated. Each font is defined on the first page that uses it, and the
+    /// postamble only lists the fonts that are still used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of `indices` is not the index of a page.
+    pub fn select(&self, indices: &[usize]) -> Document {
+        let mut document = Document {
+            preamble: self.preamble.clone(),
+            pages: indices.iter().map(|&i| self.pages[i].clone()).collect(),
+            postamble: self.postamble.clone(),
+        };
+        document.place_font_defs();
+        document
+    }
+
+    /// Add the pages of `other` to the end of this document
+    ///
+    /// Both documents must have the same units and magnification. Fonts from `other` that are
+    /// already defined here keep the number they have here, and fonts whose number is taken by a
+    /// different font are given the next free number. Nothing is changed if there is an error.
+    pub fn append(&mut self, other: &Document) -> Result<(), Error> {
+        let (pre, other_pre) = (&self.preamble, &other.preamble);
+        if (pre.numerator, pre.denominator, pre.magnification)
+            != (
+                other_pre.numerator,
+                other_pre.denominator,
+                other_pre.magnification,
+            )
+        {
+            return Err(Error::Incompatible);
+        }
+
+        let mut fonts = self.font_defs();
+        let mut numbers = HashMap::new();
+        for font in other.font_defs().into_values() {
+            let same = |def: &FontDef| {
+                FontDef {
+                    number: font.number,
+                    ..def.clone()
+                } == font
+            };
+            let number = match fonts.values().find(|&def| same(def)) {
+                Some(def) => def.number,
+                None if !fonts.contains_key(&font.number) => font.number,
+                None => match fonts.keys().next_back() {
+                    Some(&n) => n.checked_add(1).ok_or(Error::NoFreeFontNumber)?,
+                    None => 0,
+                },
+            };
+            numbers.insert(font.number, number);
+            fonts.insert(number, FontDef { number, ..font });
+        }
+
+        let renumber = |number: &mut u32| -> Result<(), Error> {
+            *number = *numbers.get(number).ok_or(Error::UndefinedFont(*number))?;
+            Ok(())
+        };
+        let mut pages = other.pages.clone();
+        for page in &mut pages {
+            for instruction in &mut page.instructions {
+                match instruction {
+                    Instruction::Font(number) => renumber(number)?,
+                    Instruction::FontDef(def) => renumber(&mut def.number)?,
+                    _ => (),
+                }
+            }
+        }
+        self.pages.extend(pages);
+        let (post, other_post) = (&mut self.postamble, &other.postamble);
+        post.tallest_height = post.tallest_height.max(other_post.tallest_height);
+        post.widest_width = post.widest_width.max(other_post.widest_width);
+        post.max_stack_depth = post.max_stack_depth.max(other_post.max_stack_depth);
+        post.fonts = fonts.into_values().collect();
+        self.place_font_defs();
+        Ok(())
+    }
+
+    /// Every font defined in the postamble or on a page, by number
+    fn font_defs(&self) -> BTreeMap<u32, FontDef> {
+        let on_pages = self.pages.iter().flat_map(|page| {
+            page.instructions
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::FontDef(def) => Some(def),
+                    _ => None,
+                })
+        });
+        self.postamble
+            .fonts
+            .iter()
+            .chain(on_pages)
+            .map(|def| (def.number, def.clone()))
+            .collect()
+    }
+
+    /// Move each font definition to the start of the first page that uses the font, and list the
+    /// used fonts in the postamble
+    fn place_font_defs(&mut self) {
+        let defs = self.font_defs();
+        let mut used = BTreeSet::new();
+        for page in &mut self.pages {
+            page.instructions
+                .retain(|instruction| !matches!(instruction, Instruction::FontDef(_)));
+            let mut first = Vec::new();
+            for instruction in &page.instructions {
+                if let Instruction::Font(number) = instruction {
+                    if let Some(def) = defs.get(number) {
+                        if used.insert(*number) {
+                            first.push(Instruction::FontDef(def.clone()));
+                        }
+                    }
+                }
+            }
+            page.instructions.splice(0..0, first);
+        }
+        self.postamble.fonts = defs
+            .into_values()
+            .filter(|def| used.contains(&def.number))
+            .collect();
+    }
+}
+
+/// The size of a sheet of paper
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaperSize {
+    /// The width in dvi units
+    pub width: i32,
+    /// The height in dvi units
+    pub height: i32,
+    /// Big points per dvi unit
+    scale: f64,
+}
+
+impl PaperSize {
+    fn from_bp(width: f64, height: f64, scale: f64) -> Self {
+        PaperSize {
+            width: (width / scale).round() as i32,
+            height: (height / scale).round() as i32,
+            scale,
+        }
+    }
+
+    /// The width in millimetres
+    pub fn width_mm(&self) -> f64 {
+        f64::from(self.width) * self.scale * 25.4 / 72.0
+    }
+
+    /// The height in millimetres
+    pub fn height_mm(&self) -> f64 {
+        f64::from(self.height) * self.scale * 25.4 / 72.0
+    }
+
+    /// Whether the paper is wider than it is tall
+    pub fn is_landscape(&self) -> bool {
+        self.width > self.height
+    }
+
+    /// The width and height in big points
+    pub(crate) fn bp(&self) -> (f64, f64) {
+        (
+            f64::from(self.width) * self.scale,
+            f64::from(self.height) * self.scale,
+        )
+    }
+}
+
+/// Parse the arguments of a dvipdfmx `pdf:pagesize width <width> height <height>` special
+fn pdf_pagesize(args: &str) -> Option<(f64, f64)> {
+    let mut words = args.split_whitespace();
+    let (mut width, mut height) = (None, None);
+    while let Some(word) = words.next() {
+        match word {
+            "width" => width = dimension(words.next()?),
+            "height" => height = dimension(words.next()?),
+            _ => (),
+        }
+    }
+    Some((width?, height?))
+}
+
+impl Page {
+    /// Parse a page from its `Bop` to its `Eop`
+    ///
+    /// Font definitions and `Nop`s before the `Bop` are allowed, and font definitions are kept at
+    /// the start of the page like `Document::parse` does.
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let mut rest = input;
+        let mut instructions = Vec::new();
+        let counters = loop {
+            let (next, instruction) = Instruction::parse(rest)?;
+            match instruction {
+                Instruction::Nop => (),
+                Instruction::FontDef(_) => instructions.push(instruction),
+                Instruction::Bop(counters, _) => {
+                    rest = next;
+                    break counters;
+                }
+                _ => return Err(nom::Err::Error((rest, ErrorKind::Tag))),
+            }
+            rest = next;
+        };
+        loop {
+            let (next, instruction) = Instruction::parse(rest)?;
+            match instruction {
+                Instruction::Eop => {
+                    return Ok((
+                        next,
+                        Page {
+                            counters,
+                            instructions,
+                        },
+                    ))
+                }
+                Instruction::Bop(..)
+                | Instruction::Pre { .. }
+                | Instruction::Post { .. }
+                | Instruction::PostPost { .. } => {
+                    return Err(nom::Err::Error((rest, ErrorKind::Tag)))
+                }
+                instruction => instructions.push(instruction),
+            }
+            rest = next;
+        }
+    }
+
+    /// Write the page from its `Bop` to its `Eop`
+    ///
+    /// A page on its own doesn't know where the previous one is, so the pointer in the `Bop` is
+    /// -1. `Document::dump` fills it in properly.
+    pub fn dump<S: Sink + ?Sized>(&self, writer: &mut S) -> Result<(), S::Error> {
+        Instruction::Bop(self.counters, -1).dump(writer)?;
+        for instruction in &self.instructions {
+            instruction.dump(writer)?;
+        }
+        Instruction::Eop.dump(writer)
+    }
+
+    /// The size of paper asked for by a `papersize=` or `pdf:pagesize` special on this page
+    pub fn paper_size(&self, preamble: &Preamble) -> Option<PaperSize> {
+        let scale = bp_per_unit(
+            preamble.numerator,
+            preamble.denominator,
+            preamble.magnification,
+        );
+        self.specials().find_map(|special| {
+            let (width, height) = match special {
+                Special::PaperSize { width, height } => (width, height),
+                Special::Pdf { command, args } if command == "pagesize" => pdf_pagesize(&args)?,
+                _ => return None,
+            };
+            Some(PaperSize::from_bp(width, height, scale))
+        })
+    }
+
+    /// The specials on this page
+    fn specials(&self) -> impl Iterator<Item = Special> + '_ {
+        self.instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Xxx(payload) => Some(Special::parse(payload)),
+                _ => None,
+            })
+    }
+
+    /// Interpret this page, including its `Bop` and `Eop`
+    pub fn run<M: FontMetrics, D: Device>(
+        &self,
+        interpreter: &mut Interpreter<M>,
+        device: &mut D,
+    ) -> Result<(), interpreter::Error> {
+        interpreter.execute(&Instruction::Bop(self.counters, -1), device)?;
+        for instruction in &self.instructions {
+            interpreter.execute(instruction, device)?;
+        }
+        interpreter.execute(&Instruction::Eop, device)
+    }
+}
+
+/// Check that the font definitions after the last page are all in the postamble
+///
+/// There is no page to keep them on, but nothing is lost when the postamble defines the same
+/// fonts.
+pub(crate) fn check_trailing_font_defs(
+    defs: &[(usize, FontDef)],
+    postamble: &[FontDef],
+) -> Result<(), Error> {
+    match defs.iter().find(|(_, def)| !postamble.contains(def)) {
+        Some(&(offset, _)) => Err(Error::Unexpected(offset)),
+        None => Ok(()),
+    }
+}
+
+/// Write the postamble of a file with `pages` pages, whose last `Bop` is at `final_bop_pointer`
+///
+/// `post_pointer` is the number of bytes written before the postamble, which is needed to pad
+/// the file to a multiple of 4 bytes.
+pub(crate) fn dump_postamble<S: Sink + ?Sized>(
+    pre: &Preamble,
+    post: &Postamble,
+    pages: u16,
+    final_bop_pointer: i32,
+    post_pointer: usize,
+    sink: &mut S,
+) -> Result<(), S::Error> {
+    let mut writer = Counting {
+        inner: sink,
+        count: post_pointer,
+    };
+    Instruction::Post {
+        final_bop_pointer,
+        numerator: pre.numerator,
+        denominator: pre.denominator,
+        magnification: pre.magnification,
+        tallest_height: post.tallest_height,
+        widest_width: post.widest_width,
+        max_stack_depth: post.max_stack_depth,
+        total_no_pages: pages,
+    }
+    .dump(&mut writer)?;
+    for font in &post.fonts {
+        Instruction::FontDef(font.clone()).dump(&mut writer)?;
+    }
+    // TeX pads the file to a multiple of 4 bytes, with at least 4 bytes of padding
+    let len = writer.count + 6;
+    Instruction::PostPost {
+        post_pointer: post_pointer as u32,
+        ident: pre.format,
+        two_two_three: 4 + (4 - len % 4) as u32 % 4,
+    }
+    .dump(&mut writer)
+}
+
+/// A sink that counts how many bytes have been written, to work out pointers
+struct Counting<'a, S: ?Sized> {
+    inner: &'a mut S,
+    count: usize,
+}
+
+impl<'a, S: Sink + ?Sized> Sink for Counting<'a, S> {
+    type Error = S::Error;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), S::Error> {
+        self.inner.write_bytes(bytes)?;
+        self.count += bytes.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn document() -> Document {
+        let font = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        };
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::from("test"),
+            },
+            pages: vec![
+                Page {
+                    counters: [1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                    instructions: vec![
+                        Instruction::FontDef(font.clone()),
+                        Instruction::Font(0),
+                        Instruction::Set(b'a'.into()),
+                    ],
+                },
+                Page {
+                    counters: [2, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                    instructions: vec![Instruction::Font(0), Instruction::Set(b'b'.into())],
+                },
+            ],
+            postamble: Postamble {
+                tallest_height: 100,
+                widest_width: 200,
+                max_stack_depth: 1,
+                fonts: vec![font],
+            },
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let document = document();
+        let mut out = Vec::new();
+        document.dump(&mut out).unwrap();
+        assert_eq!(out.len() % 4, 0);
+        assert_eq!(Document::parse(&out), Ok(document));
+    }
+
+    #[test]
+    fn pointers() {
+        let mut out = Vec::new();
+        document().dump(&mut out).unwrap();
+        let mut rest = &out[..];
+        let mut offset = 0;
+        let mut bops = Vec::new();
+        let mut posts = Vec::new();
+        while let Ok((next, instruction)) = Instruction::parse(rest) {
+            match instruction {
+                Instruction::Bop(_, p) => bops.push((offset as i32, p)),
+                Instruction::Post {
+                    final_bop_pointer, ..
+                } => posts.push((offset as u32, final_bop_pointer)),
+                Instruction::PostPost { post_pointer, .. } => {
+                    assert_eq!(post_pointer, posts[0].0);
+                    break;
+                }
+                _ => (),
+            }
+            offset += rest.len() - next.len();
+            rest = next;
+        }
+        assert_eq!(bops[0].1, -1);
+        assert_eq!(bops[1].1, bops[0].0);
+        assert_eq!(posts[0].1, bops[1].0);
+    }
+
+    #[test]
+    fn test_file() {
+        let input = include_bytes!("../tests/source/main.dvi");
+        let document = Document::parse(input).unwrap();
+        assert_eq!(document.pages.len(), 2);
+        assert_eq!(document.preamble.format, 2);
+        assert!(!document.postamble.fonts.is_empty());
+        // TeX writes files the same way
+        let mut out = Vec::new();
+        document.dump(&mut out).unwrap();
+        assert_eq!(&out[..], &input[..]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Document::parse(&[]), Err(Error::Truncated));
+        assert_eq!(Document::parse(&[140]), Err(Error::Unexpected(0)));
+        assert_eq!(Document::parse(&[255]), Err(Error::Parse(0)));
+    }
+
+    #[test]
+    fn trailing_font_defs() {
+        let document = document();
+        let mut out = Vec::new();
+        document.dump(&mut out).unwrap();
+        let mut instructions = crate::Instructions::new(&out);
+        let post = loop {
+            let offset = instructions.offset();
+            if let Some(Ok(crate::InstructionRef::Other(Instruction::Post { .. }))) =
+                instructions.next()
+            {
+                break offset;
+            }
+        };
+        let with_def = |def: FontDef| {
+            let mut input = out[..post].to_vec();
+            Instruction::FontDef(def).dump(&mut input).unwrap();
+            input.extend_from_slice(&out[post..]);
+            input
+        };
+
+        // the same font as in the postamble, so nothing is lost
+        let font = document.postamble.fonts[0].clone();
+        assert_eq!(Document::parse(&with_def(font.clone())), Ok(document));
+        // a font that would be lost
+        let other = FontDef { number: 5, ..font };
+        assert_eq!(
+            Document::parse(&with_def(other)),
+            Err(Error::Unexpected(post))
+        );
+    }
+
+    #[test]
+    fn color_stacks() {
+        let mut document = document();
+        let push = Instruction::Xxx(Vec::from("color push gray 0.5"));
+        document.pages[0].instructions.insert(0, push);
+        let stacks = document.color_stacks();
+        assert_eq!(stacks.len(), 2);
+        assert_eq!(stacks[0], ColorStack::new());
+        assert_eq!(stacks[1].current(), &Color::Gray(0.5));
+
+        struct Zero;
+        impl FontMetrics for Zero {
+            fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+                Some(0)
+            }
+        }
+        struct Colors(Vec<Color>);
+        impl Device for Colors {
+            fn color(&mut self, color: &Color) {
+                self.0.push(color.clone());
+            }
+        }
+        let mut colors = Colors(Vec::new());
+        document.run_page(1, &stacks[1], Zero, &mut colors).unwrap();
+        assert_eq!(colors.0, vec![Color::Gray(0.5)]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_run() {
+        let mut document = document();
+        let push = Instruction::Xxx(Vec::from("color push gray 0.5"));
+        document.pages[0].instructions.insert(0, push);
+        document.pages.extend(document.pages.clone());
+
+        #[derive(Debug, PartialEq)]
+        struct Chars(usize, Vec<(i32, u32, Color)>, Color);
+        impl Device for Chars {
+            fn char(&mut self, h: i32, _: i32, _: &FontDef, ch: u32, _: i32) {
+                self.1.push((h, ch, self.2.clone()));
+            }
+            fn color(&mut self, color: &Color) {
+                self.2 = color.clone();
+            }
+        }
+        #[derive(Clone, Copy)]
+        struct Fixed;
+        impl FontMetrics for Fixed {
+            fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+                Some(10)
+            }
+        }
+        let new = |index| Chars(index, Vec::new(), Color::Gray(0.0));
+        let parallel = document.par_run(Fixed, new);
+        assert_eq!(parallel.len(), 4);
+        let stacks = document.color_stacks();
+        for (index, result) in parallel.into_iter().enumerate() {
+            let mut expected = new(index);
+            document
+                .run_page(index, &stacks[index], Fixed, &mut expected)
+                .unwrap();
+            assert_eq!(result.unwrap(), expected);
+        }
+        // the second page starts in the colour pushed on the first
+        let second = document.par_run(Fixed, new).swap_remove(1).unwrap();
+        assert_eq!(second.1, vec![(0, u32::from(b'b'), Color::Gray(0.5))]);
+    }
+
+    #[test]
+    fn paper_sizes() {
+        let mut document = document();
+        // the postamble gives the size of the largest page
+        let size = document.paper_size();
+        assert_eq!(
+            (size.width, size.height),
+            (200 + 9_472_573, 100 + 9_472_573)
+        );
+
+        document.postamble.widest_width = 0;
+        let size = document.paper_size();
+        assert!((size.width_mm() - 215.9).abs() < 0.001);
+        assert!((size.height_mm() - 279.4).abs() < 0.001);
+        assert!(!size.is_landscape());
+
+        let special = |s: &str| Instruction::Xxx(Vec::from(s));
+        document.pages[0]
+            .instructions
+            .push(special("papersize=210mm,297mm"));
+        document.pages[1]
+            .instructions
+            .push(special("pdf:pagesize width 100bp height 50bp"));
+        let size = document.paper_size();
+        assert!((size.width_mm() - 210.0).abs() < 0.001);
+        assert!((size.height_mm() - 297.0).abs() < 0.001);
+        assert_eq!(document.page_size(0), size);
+        let size = document.page_size(1);
+        assert_eq!(document.page_sizes(), vec![document.page_size(0), size]);
+        assert!((size.width_mm() - 100.0 * 25.4 / 72.0).abs() < 0.001);
+        assert!(size.is_landscape());
+
+        document.pages[1].instructions.push(special("landscape"));
+        let size = document.paper_size();
+        assert!(size.is_landscape());
+        assert!((size.width_mm() - 297.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn limits() {
+        let mut out = Vec::new();
+        document().dump(&mut out).unwrap();
+        let limits = ParseLimits {
+            max_pages: 1,
+            ..ParseLimits::default()
+        };
+        match Document::parse_with_limits(&out, &limits) {
+            Err(Error::Limit(Limit::Pages, offset)) => assert_eq!(out[offset], 139),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Document::parse_with_limits(&out, &ParseLimits::default()).is_ok());
+
+        // a special that claims to be 4GiB long is stopped before it is read
+        let mut input = vec![247, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 3, 232, 0];
+        input.extend_from_slice(&[242, 0xff, 0xff, 0xff, 0xff, b'a']);
+        assert_eq!(
+            Document::parse_with_limits(&input, &ParseLimits::default()),
+            Err(Error::Limit(Limit::SpecialLength, 15))
+        );
+        assert_eq!(Document::parse(&input), Err(Error::Truncated));
+        assert_eq!(
+            Instruction::parse_limited(&input[15..], &ParseLimits::default()),
+            Err(nom::Err::Failure((&input[15..], ErrorKind::TooLarge)))
+        );
+    }
+
+    #[test]
+    fn select() {
+        let document = document();
+        let selected = document.select(&[1, 1]);
+        assert_eq!(selected.pages.len(), 2);
+        // the font is now defined on the first page that remains
+        assert_eq!(
+            selected.pages[0].instructions[0],
+            Instruction::FontDef(document.postamble.fonts[0].clone())
+        );
+        assert_eq!(selected.pages[1], document.pages[1]);
+        assert_eq!(selected.postamble, document.postamble);
+
+        let mut out = Vec::new();
+        selected.dump(&mut out).unwrap();
+        assert_eq!(Document::parse(&out), Ok(selected));
+        assert_eq!(document.select(&[]).postamble.fonts, vec![]);
+    }
+
+    #[test]
+    fn append() {
+        let mut document = document();
+        let mut other = document.clone();
+        // another font with the same number, and a copy of the first one under a new number
+        let font = FontDef {
+            number: 0,
+            filename: Vec::from("cmbx10"),
+            ..other.postamble.fonts[0].clone()
+        };
+        let copy = FontDef {
+            number: 7,
+            ..other.postamble.fonts[0].clone()
+        };
+        other.pages[0].instructions[0] = Instruction::FontDef(font.clone());
+        other.pages[1].instructions = vec![
+            Instruction::FontDef(copy.clone()),
+            Instruction::Font(7),
+            Instruction::Set(b'c'.into()),
+        ];
+        other.postamble.fonts = vec![font.clone(), copy];
+        other.postamble.max_stack_depth = 4;
+        document.append(&other).unwrap();
+
+        assert_eq!(document.pages.len(), 4);
+        assert_eq!(
+            document.pages[2].instructions,
+            vec![
+                Instruction::FontDef(FontDef { number: 1, ..font }),
+                Instruction::Font(1),
+                Instruction::Set(b'a'.into()),
+            ]
+        );
+        assert_eq!(
+            document.pages[3].instructions,
+            vec![Instruction::Font(0), Instruction::Set(b'c'.into())]
+        );
+        assert_eq!(document.postamble.fonts.len(), 2);
+        assert_eq!(document.postamble.max_stack_depth, 4);
+
+        other.preamble.magnification = 2000;
+        assert_eq!(document.append(&other), Err(Error::Incompatible));
+
+        // a font that is used without being defined is an error, and nothing is added
+        let mut undefined = document.clone();
+        undefined.pages[0].instructions = vec![Instruction::Font(9)];
+        undefined.postamble.fonts.clear();
+        let pages = document.pages.len();
+        assert_eq!(document.append(&undefined), Err(Error::UndefinedFont(9)));
+        assert_eq!(document.pages.len(), pages);
+
+        // there is no number after the largest one to move a clashing font to
+        let mut full = document.clone();
+        let font = full.postamble.fonts[0].clone();
+        let last = FontDef {
+            number: u32::MAX,
+            ..font.clone()
+        };
+        full.postamble.fonts.push(last.clone());
+        full.pages[0]
+            .instructions
+            .insert(0, Instruction::FontDef(last));
+        let clash = Document {
+            pages: vec![Page {
+                counters: [0; 10],
+                instructions: vec![Instruction::Font(0)],
+            }],
+            postamble: Postamble {
+                fonts: vec![FontDef {
+                    number: 0,
+                    filename: Vec::from("cmss10"),
+                    ..font
+                }],
+                ..full.postamble.clone()
+            },
+            ..full.clone()
+        };
+        assert_eq!(full.append(&clash), Err(Error::NoFreeFontNumber));
+    }
+}
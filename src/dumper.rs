@@ -1,10 +1,48 @@
 //! functions to dump correct output to stream for each instruction type
+use crate::Sink;
 use crate::{
     util::{byte_width, byte_width_signed},
     FontDef, Instruction,
 };
-use byteorder::{BigEndian, WriteBytesExt};
-use std::io::{self, Write};
+
+/// Writing big-endian integers to a `Sink`
+trait SinkExt: Sink {
+    fn write_u8(&mut self, v: u8) -> Result<(), Self::Error> {
+        self.write_bytes(&[v])
+    }
+
+    fn write_i8(&mut self, v: i8) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes())
+    }
+
+    fn write_u16(&mut self, v: u16) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes())
+    }
+
+    fn write_i16(&mut self, v: i16) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes())
+    }
+
+    /// Write the low 3 bytes of `v`
+    fn write_u24(&mut self, v: u32) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes()[1..])
+    }
+
+    /// Write the low 3 bytes of `v`
+    fn write_i24(&mut self, v: i32) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes()[1..])
+    }
+
+    fn write_u32(&mut self, v: u32) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes())
+    }
+
+    fn write_i32(&mut self, v: i32) -> Result<(), Self::Error> {
+        self.write_bytes(&v.to_be_bytes())
+    }
+}
+
+impl<S: Sink + ?Sized> SinkExt for S {}
 
 // Helper macros
 
@@ -16,7 +54,7 @@ macro_rules! write_small {
     ( signed $v:expr , $writer:ident => $code1:expr , $code2:expr ,
       $code3:expr , $code4:expr ) => {{
         // help the type checker
-        let out: io::Result<()> = {
+        let out: Result<(), W::Error> = {
             match byte_width_signed($v) {
                 1 => {
                     $writer.write_u8($code1)?;
@@ -24,15 +62,15 @@ macro_rules! write_small {
                 }
                 2 => {
                     $writer.write_u8($code2)?;
-                    $writer.write_i16::<BigEndian>($v as i16)?;
+                    $writer.write_i16($v as i16)?;
                 }
                 3 => {
                     $writer.write_u8($code3)?;
-                    $writer.write_i24::<BigEndian>($v)?;
+                    $writer.write_i24($v)?;
                 }
                 4 => {
                     $writer.write_u8($code4)?;
-                    $writer.write_i32::<BigEndian>($v)?;
+                    $writer.write_i32($v)?;
                 }
                 _ => { unreachable!() }
             };
@@ -52,7 +90,7 @@ macro_rules! write_small {
 
     ( unsigned $v:expr , $writer:ident => $code1:expr ,
       $code2:expr , $code3:expr , $code4:expr ) => {{
-        let out: io::Result<()> = {
+        let out: Result<(), W::Error> = {
             match byte_width($v) {
                 1 => {
                     $writer.write_u8($code1)?;
@@ -60,15 +98,15 @@ macro_rules! write_small {
                 }
                 2 => {
                     $writer.write_u8($code2)?;
-                    $writer.write_u16::<BigEndian>($v as u16)?;
+                    $writer.write_u16($v as u16)?;
                 }
                 3 => {
                     $writer.write_u8($code3)?;
-                    $writer.write_u24::<BigEndian>($v)?;
+                    $writer.write_u24($v)?;
                 }
                 4 => {
                     $writer.write_u8($code4)?;
-                    $writer.write_u32::<BigEndian>($v)?;
+                    $writer.write_u32($v)?;
                 }
                 _ => { unreachable!() }
             };
@@ -89,8 +127,8 @@ macro_rules! write_small {
 
 // Encoders
 
-/// Dump an instruction to an impl of Write
-pub(crate) fn dump<W: Write>(i: &Instruction, writer: &mut W) -> io::Result<()> {
+/// Dump an instruction to a `Sink`
+pub(crate) fn dump<W: Sink + ?Sized>(i: &Instruction, writer: &mut W) -> Result<(), W::Error> {
     match *i {
         Instruction::Set(ch) => dump_set(ch, writer),
         Instruction::SetRule(a, b) => dump_set_rule(a, b, writer),
@@ -153,7 +191,7 @@ pub(crate) fn dump<W: Write>(i: &Instruction, writer: &mut W) -> io::Result<()>
     }
 }
 
-fn dump_set<W: Write>(ch: u32, writer: &mut W) -> io::Result<()> {
+fn dump_set<W: Sink + ?Sized>(ch: u32, writer: &mut W) -> Result<(), W::Error> {
     match byte_width(ch) {
         1 => {
             if ch < 128 {
@@ -165,73 +203,73 @@ fn dump_set<W: Write>(ch: u32, writer: &mut W) -> io::Result<()> {
         }
         2 => {
             writer.write_u8(129)?;
-            writer.write_u16::<BigEndian>(ch as u16)?;
+            writer.write_u16(ch as u16)?;
         }
         3 => {
             writer.write_u8(130)?;
-            writer.write_u24::<BigEndian>(ch)?;
+            writer.write_u24(ch)?;
         }
         4 => {
             writer.write_u8(131)?;
-            writer.write_u32::<BigEndian>(ch)?;
+            writer.write_u32(ch)?;
         }
         _ => unreachable!(),
     };
     Ok(())
 }
 
-fn dump_set_rule<W: Write>(a: i32, b: i32, writer: &mut W) -> io::Result<()> {
+fn dump_set_rule<W: Sink + ?Sized>(a: i32, b: i32, writer: &mut W) -> Result<(), W::Error> {
     writer.write_u8(132)?;
-    writer.write_i32::<BigEndian>(a)?;
-    writer.write_i32::<BigEndian>(b)?;
+    writer.write_i32(a)?;
+    writer.write_i32(b)?;
     Ok(())
 }
 
-fn dump_put<W: Write>(v: u32, writer: &mut W) -> io::Result<()> {
+fn dump_put<W: Sink + ?Sized>(v: u32, writer: &mut W) -> Result<(), W::Error> {
     write_small!(unsigned v, writer => 133, 134, 135, 136)
 }
 
-fn dump_put_rule<W: Write>(a: i32, b: i32, writer: &mut W) -> io::Result<()> {
+fn dump_put_rule<W: Sink + ?Sized>(a: i32, b: i32, writer: &mut W) -> Result<(), W::Error> {
     writer.write_u8(137)?;
-    writer.write_i32::<BigEndian>(a)?;
-    writer.write_i32::<BigEndian>(b)?;
+    writer.write_i32(a)?;
+    writer.write_i32(b)?;
     Ok(())
 }
 
-fn dump_bop<W: Write>(c: [i32; 10], p: i32, writer: &mut W) -> io::Result<()> {
+fn dump_bop<W: Sink + ?Sized>(c: [i32; 10], p: i32, writer: &mut W) -> Result<(), W::Error> {
     writer.write_u8(139)?;
     for ci in c.iter() {
-        writer.write_i32::<BigEndian>(*ci)?;
+        writer.write_i32(*ci)?;
     }
-    writer.write_i32::<BigEndian>(p)?;
+    writer.write_i32(p)?;
     Ok(())
 }
 
-fn dump_right<W: Write>(v: i32, writer: &mut W) -> io::Result<()> {
+fn dump_right<W: Sink + ?Sized>(v: i32, writer: &mut W) -> Result<(), W::Error> {
     write_small!(signed v, writer => 143, 144, 145, 146)
 }
 
-fn dump_w<W: Write>(v: Option<i32>, writer: &mut W) -> io::Result<()> {
+fn dump_w<W: Sink + ?Sized>(v: Option<i32>, writer: &mut W) -> Result<(), W::Error> {
     write_small!(signed v, writer => 147, 148, 149, 150, 151)
 }
 
-fn dump_x<W: Write>(v: Option<i32>, writer: &mut W) -> io::Result<()> {
+fn dump_x<W: Sink + ?Sized>(v: Option<i32>, writer: &mut W) -> Result<(), W::Error> {
     write_small!(signed v, writer => 152, 153, 154, 155, 156)
 }
 
-fn dump_down<W: Write>(v: i32, writer: &mut W) -> io::Result<()> {
+fn dump_down<W: Sink + ?Sized>(v: i32, writer: &mut W) -> Result<(), W::Error> {
     write_small!(signed v, writer => 157, 158, 159, 160)
 }
 
-fn dump_y<W: Write>(v: Option<i32>, writer: &mut W) -> io::Result<()> {
+fn dump_y<W: Sink + ?Sized>(v: Option<i32>, writer: &mut W) -> Result<(), W::Error> {
     write_small!(signed v, writer => 161, 162, 163, 164, 165)
 }
 
-fn dump_z<W: Write>(v: Option<i32>, writer: &mut W) -> io::Result<()> {
+fn dump_z<W: Sink + ?Sized>(v: Option<i32>, writer: &mut W) -> Result<(), W::Error> {
     write_small!(signed v, writer => 166, 167, 168, 169, 170)
 }
 
-fn dump_font<W: Write>(f: u32, writer: &mut W) -> io::Result<()> {
+fn dump_font<W: Sink + ?Sized>(f: u32, writer: &mut W) -> Result<(), W::Error> {
     match byte_width(f) {
         1 => {
             if f <= 63 {
@@ -243,88 +281,92 @@ fn dump_font<W: Write>(f: u32, writer: &mut W) -> io::Result<()> {
         }
         2 => {
             writer.write_u8(236)?;
-            writer.write_u16::<BigEndian>(f as u16)?;
+            writer.write_u16(f as u16)?;
         }
         3 => {
             writer.write_u8(237)?;
-            writer.write_u24::<BigEndian>(f)?;
+            writer.write_u24(f)?;
         }
         4 => {
             writer.write_u8(238)?;
-            writer.write_u32::<BigEndian>(f)?;
+            writer.write_u32(f)?;
         }
         _ => unreachable!(),
     };
     Ok(())
 }
 
-fn dump_xxx<W: Write>(data: &[u8], writer: &mut W) -> io::Result<()> {
+fn dump_xxx<W: Sink + ?Sized>(data: &[u8], writer: &mut W) -> Result<(), W::Error> {
     assert!(
-        data.len() < ::std::u32::MAX as usize,
+        data.len() < u32::MAX as usize,
         "The length of extention data won't fit in 32 bits"
     );
     write_small!(unsigned data.len() as u32, writer => 239, 240, 241, 242)?;
-    writer.write_all(data)?;
+    writer.write_bytes(data)?;
     Ok(())
 }
 
 /// Helper for `dump_font_def`
 #[inline]
-fn dump_font_def_helper<W: Write>(v: u32, writer: &mut W) -> io::Result<()> {
+fn dump_font_def_helper<W: Sink + ?Sized>(v: u32, writer: &mut W) -> Result<(), W::Error> {
     write_small!(unsigned v, writer => 243, 244, 245, 246)
 }
 
-fn dump_font_def<W: Write>(def: &FontDef, writer: &mut W) -> io::Result<()> {
+pub(crate) fn dump_font_def<W: Sink + ?Sized>(
+    def: &FontDef,
+    writer: &mut W,
+) -> Result<(), W::Error> {
     assert!(
-        def.filename.len() <= ::std::u8::MAX as usize,
+        def.filename.len() <= u8::MAX as usize,
         "Filename too long in Font Definition"
     );
     assert!(
         if let Some(ref d) = def.directory {
-            d.len() <= ::std::u8::MAX as usize
+            d.len() <= u8::MAX as usize
         } else {
             true
         },
         "Directory name too long in Font Definition"
     );
     dump_font_def_helper(def.number, writer)?;
-    writer.write_u32::<BigEndian>(def.checksum)?;
-    writer.write_u32::<BigEndian>(def.scale_factor)?;
-    writer.write_u32::<BigEndian>(def.design_size)?;
+    writer.write_u32(def.checksum)?;
+    writer.write_u32(def.scale_factor)?;
+    writer.write_u32(def.design_size)?;
     match def.directory {
         Some(ref d) => writer.write_u8(d.len() as u8),
         None => writer.write_u8(0),
     }?;
     writer.write_u8(def.filename.len() as u8)?;
     if let Some(ref d) = def.directory {
-        writer.write_all(&d[..])?
+        writer.write_bytes(&d[..])?
     };
-    writer.write_all(&def.filename[..])?;
+    writer.write_bytes(&def.filename[..])?;
     Ok(())
 }
 
 /// Pre
-fn dump_pre<W: Write>(
+fn dump_pre<W: Sink + ?Sized>(
     format: u8,
     numerator: u32,
     denominator: u32,
     magnification: u32,
     comment: &[u8],
     writer: &mut W,
-) -> io::Result<()> {
+) -> Result<(), W::Error> {
     assert!(comment.len() < 0x100, "Comment length must fit into u8");
     writer.write_u8(247)?;
     writer.write_u8(format)?;
-    writer.write_u32::<BigEndian>(numerator)?;
-    writer.write_u32::<BigEndian>(denominator)?;
-    writer.write_u32::<BigEndian>(magnification)?;
+    writer.write_u32(numerator)?;
+    writer.write_u32(denominator)?;
+    writer.write_u32(magnification)?;
     writer.write_u8(comment.len() as u8)?;
-    writer.write_all(comment)?;
+    writer.write_bytes(comment)?;
     Ok(())
 }
 
 /// Post
-fn dump_post<W: Write>(
+#[allow(clippy::too_many_arguments)]
+fn dump_post<W: Sink + ?Sized>(
     final_bop_pointer: i32,
     numerator: u32,
     denominator: u32,
@@ -334,27 +376,27 @@ fn dump_post<W: Write>(
     max_stack_depth: u16,
     total_no_pages: u16,
     writer: &mut W,
-) -> io::Result<()> {
+) -> Result<(), W::Error> {
     writer.write_u8(248)?;
-    writer.write_i32::<BigEndian>(final_bop_pointer)?;
-    writer.write_u32::<BigEndian>(numerator)?;
-    writer.write_u32::<BigEndian>(denominator)?;
-    writer.write_u32::<BigEndian>(magnification)?;
-    writer.write_i32::<BigEndian>(tallest_height)?;
-    writer.write_i32::<BigEndian>(widest_width)?;
-    writer.write_u16::<BigEndian>(max_stack_depth)?;
-    writer.write_u16::<BigEndian>(total_no_pages)?;
+    writer.write_i32(final_bop_pointer)?;
+    writer.write_u32(numerator)?;
+    writer.write_u32(denominator)?;
+    writer.write_u32(magnification)?;
+    writer.write_i32(tallest_height)?;
+    writer.write_i32(widest_width)?;
+    writer.write_u16(max_stack_depth)?;
+    writer.write_u16(total_no_pages)?;
     Ok(())
 }
 
-fn dump_postpost<W: Write>(
+fn dump_postpost<W: Sink + ?Sized>(
     post_pointer: u32,
     ident: u8,
     two_two_three: u32,
     writer: &mut W,
-) -> io::Result<()> {
+) -> Result<(), W::Error> {
     writer.write_u8(249)?;
-    writer.write_u32::<BigEndian>(post_pointer)?;
+    writer.write_u32(post_pointer)?;
     writer.write_u8(ident)?;
     for _ in 0..two_two_three {
         writer.write_u8(223)?;
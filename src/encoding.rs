@@ -0,0 +1,272 @@
+//! Font encodings used by TeX, mapping character codes to Unicode
+//!
+//! The fonts TeX uses don't carry any information about what their characters mean, so the
+//! meaning has to come from knowing which encoding the font follows. Ligatures decode to the
+//! letters they are made of, so that `ﬁ` comes out as `fi`, and math letters decode to plain
+//! letters.
+
+use std::convert::TryFrom;
+
+/// A font encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// The original TeX text encoding, used by `cmr` and friends
+    Ot1,
+    /// The variant of OT1 used by the typewriter fonts like `cmtt`
+    Ot1Typewriter,
+    /// The Cork encoding, used by the `ec` fonts
+    T1,
+    /// The math italic encoding, used by `cmmi`
+    Oml,
+    /// The math symbol encoding, used by `cmsy`
+    Oms,
+    /// The text companion encoding, used by the `tc` fonts
+    Ts1,
+}
+
+impl Encoding {
+    /// Guess the encoding of a font from its file name
+    ///
+    /// This knows the naming schemes of Computer Modern, the EC and TC fonts, and Latin Modern.
+    /// Fonts it doesn't recognise are assumed to be OT1.
+    pub fn for_font(filename: &[u8]) -> Self {
+        let name = String::from_utf8_lossy(filename).to_ascii_lowercase();
+        let starts = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+        if starts(&["ts1-", "tc"]) {
+            Encoding::Ts1
+        } else if starts(&["ec", "t1-"]) {
+            Encoding::T1
+        } else if starts(&["cmmi", "cmbrmi", "lmmi", "rm-lmmi"]) {
+            Encoding::Oml
+        } else if starts(&["cmsy", "cmbsy", "cmbrs", "lmsy", "rm-lmsy"]) {
+            Encoding::Oms
+        } else if starts(&["cmtt", "cmsltt", "cmitt", "cmtex", "rm-lmtt", "lmtt"]) {
+            Encoding::Ot1Typewriter
+        } else {
+            Encoding::Ot1
+        }
+    }
+
+    /// The text for character `code`, or `None` if it isn't in the encoding
+    ///
+    /// Accents decode to their spacing forms, see [`accent`](#method.accent) for the combining
+    /// forms.
+    pub fn decode(self, code: u32) -> Option<&'static str> {
+        let code = usize::try_from(code).ok()?;
+        let text = match self {
+            Encoding::Ot1 => OT1.get(code)?,
+            Encoding::Ot1Typewriter => match code {
+                11..=15 => ["↑", "↓", "'", "¡", "¿"][code - 11],
+                32..=127 => OT1_TT[code - 32],
+                _ => OT1.get(code)?,
+            },
+            Encoding::T1 => match code {
+                0..=127 => T1_LOW[code],
+                128..=191 => T1_HIGH[code - 128],
+                215 => "Œ",
+                223 => "SS",
+                247 => "œ",
+                255 => "ß",
+                // the rest of the top quarter is the same as Latin-1
+                192..=254 => LATIN1_TOP[code - 192],
+                _ => return None,
+            },
+            Encoding::Oml => OML.get(code)?,
+            Encoding::Oms => OMS.get(code)?,
+            Encoding::Ts1 => match code {
+                128..=191 => TS1_HIGH[code - 128],
+                214 => "×",
+                246 => "÷",
+                _ => TS1_LOW.iter().find(|&&(c, _)| c == code)?.1,
+            },
+        };
+        if text.is_empty() {
+            None
+        } else {
+            Some(text)
+        }
+    }
+
+    /// The combining character for `code`, if it is an accent
+    ///
+    /// TeX draws an accent before the letter it goes over, so a text extractor needs to hold on to
+    /// it and attach it to the next letter.
+    pub fn accent(self, code: u32) -> Option<char> {
+        let spacing = self.decode(code)?;
+        // the typewriter fonts use some of these codes for ascii characters
+        if self == Encoding::Ot1Typewriter && (32..127).contains(&code) {
+            return None;
+        }
+        let combining = match spacing {
+            "`" => '\u{300}',
+            "´" => '\u{301}',
+            "ˆ" => '\u{302}',
+            "˜" => '\u{303}',
+            "¯" => '\u{304}',
+            "˘" => '\u{306}',
+            "˙" => '\u{307}',
+            "¨" => '\u{308}',
+            "˚" => '\u{30a}',
+            "˝" => '\u{30b}',
+            "ˇ" => '\u{30c}',
+            "¸" => '\u{327}',
+            "˛" => '\u{328}',
+            "⃗" => '\u{20d7}',
+            _ => return None,
+        };
+        Some(combining)
+    }
+}
+
+/// The OT1 encoding, which is also the base of the typewriter variant
+const OT1: [&str; 128] = [
+    "Γ", "Δ", "Θ", "Λ", "Ξ", "Π", "Σ", "Υ", "Φ", "Ψ", "Ω", "ff", "fi", "fl", "ffi", "ffl", //
+    "ı", "ȷ", "`", "´", "ˇ", "˘", "¯", "˚", "¸", "ß", "æ", "œ", "ø", "Æ", "Œ", "Ø", //
+    "", "!", "”", "#", "$", "%", "&", "’", "(", ")", "*", "+", ",", "-", ".", "/", //
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ":", ";", "¡", "=", "¿", "?", //
+    "@", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", //
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "[", "“", "]", "ˆ", "˙", //
+    "‘", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", //
+    "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "–", "—", "˝", "˜", "¨", //
+];
+
+/// Codes 32 to 127 of the OT1 typewriter encoding, which are nearly ascii
+const OT1_TT: [&str; 96] = [
+    "␣", "!", "\"", "#", "$", "%", "&", "'", "(", ")", "*", "+", ",", "-", ".", "/", //
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ":", ";", "<", "=", ">", "?", //
+    "@", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", //
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "[", "\\", "]", "^", "_", //
+    "`", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", //
+    "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "{", "|", "}", "~", "¨", //
+];
+
+const T1_LOW: [&str; 128] = [
+    "`", "´", "ˆ", "˜", "¨", "˝", "˚", "ˇ", "˘", "¯", "˙", "¸", "˛", "‚", "‹", "›", //
+    "“", "”", "„", "«", "»", "–", "—", "", "", "ı", "ȷ", "ff", "fi", "fl", "ffi", "ffl", //
+    "␣", "!", "\"", "#", "$", "%", "&", "’", "(", ")", "*", "+", ",", "-", ".", "/", //
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ":", ";", "<", "=", ">", "?", //
+    "@", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", //
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "[", "\\", "]", "^", "_", //
+    "‘", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", //
+    "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "{", "|", "}", "~", "-", //
+];
+
+const T1_HIGH: [&str; 64] = [
+    "Ă", "Ą", "Ć", "Č", "Ď", "Ě", "Ę", "Ğ", "Ĺ", "Ľ", "Ł", "Ń", "Ň", "Ŋ", "Ő", "Ŕ", //
+    "Ř", "Ś", "Š", "Ş", "Ť", "Ţ", "Ű", "Ů", "Ÿ", "Ź", "Ž", "Ż", "Ĳ", "İ", "đ", "§", //
+    "ă", "ą", "ć", "č", "ď", "ě", "ę", "ğ", "ĺ", "ľ", "ł", "ń", "ň", "ŋ", "ő", "ŕ", //
+    "ř", "ś", "š", "ş", "ť", "ţ", "ű", "ů", "ÿ", "ź", "ž", "ż", "ĳ", "¡", "¿", "£", //
+];
+
+/// Latin-1 from `À` to `ÿ`
+const LATIN1_TOP: [&str; 64] = [
+    "À", "Á", "Â", "Ã", "Ä", "Å", "Æ", "Ç", "È", "É", "Ê", "Ë", "Ì", "Í", "Î", "Ï", //
+    "Ð", "Ñ", "Ò", "Ó", "Ô", "Õ", "Ö", "×", "Ø", "Ù", "Ú", "Û", "Ü", "Ý", "Þ", "ß", //
+    "à", "á", "â", "ã", "ä", "å", "æ", "ç", "è", "é", "ê", "ë", "ì", "í", "î", "ï", //
+    "ð", "ñ", "ò", "ó", "ô", "õ", "ö", "÷", "ø", "ù", "ú", "û", "ü", "ý", "þ", "ÿ", //
+];
+
+const OML: [&str; 128] = [
+    "Γ", "Δ", "Θ", "Λ", "Ξ", "Π", "Σ", "Υ", "Φ", "Ψ", "Ω", "α", "β", "γ", "δ", "ϵ", //
+    "ζ", "η", "θ", "ι", "κ", "λ", "μ", "ν", "ξ", "π", "ρ", "σ", "τ", "υ", "ϕ", "χ", //
+    "ψ", "ω", "ε", "ϑ", "ϖ", "ϱ", "ς", "φ", "↼", "↽", "⇀", "⇁", "", "", "▹", "◃", //
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ".", ",", "<", "/", ">", "⋆", //
+    "∂", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", //
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "♭", "♮", "♯", "⌣", "⌢", //
+    "ℓ", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", //
+    "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "ı", "ȷ", "℘", "⃗", "⁀", //
+];
+
+const OMS: [&str; 128] = [
+    "−", "·", "×", "∗", "÷", "⋄", "±", "∓", "⊕", "⊖", "⊗", "⊘", "⊙", "◯", "∘", "∙", //
+    "≍", "≡", "⊆", "⊇", "≤", "≥", "⪯", "⪰", "∼", "≈", "⊂", "⊃", "≪", "≫", "≺", "≻", //
+    "←", "→", "↑", "↓", "↔", "↗", "↘", "≃", "⇐", "⇒", "⇑", "⇓", "⇔", "↖", "↙", "∝", //
+    "′", "∞", "∈", "∋", "△", "▽", "\u{338}", "", "∀", "∃", "¬", "∅", "ℜ", "ℑ", "⊤", "⊥", //
+    "ℵ", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", //
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "∪", "∩", "⊎", "∧", "∨", //
+    "⊢", "⊣", "⌊", "⌋", "⌈", "⌉", "{", "}", "⟨", "⟩", "|", "‖", "↕", "⇕", "\\", "≀", //
+    "√", "⨿", "∇", "∫", "⊔", "⊓", "⊑", "⊒", "§", "†", "‡", "¶", "♣", "♦", "♥", "♠", //
+];
+
+/// The parts of the bottom half of TS1 that have an obvious Unicode equivalent
+const TS1_LOW: [(usize, &str); 25] = [
+    (13, "‚"),
+    (18, "„"),
+    (24, "←"),
+    (25, "→"),
+    (36, "$"),
+    (39, "'"),
+    (42, "*"),
+    (44, ","),
+    (46, "."),
+    (47, "⁄"),
+    (48, "0"),
+    (49, "1"),
+    (50, "2"),
+    (51, "3"),
+    (52, "4"),
+    (53, "5"),
+    (54, "6"),
+    (55, "7"),
+    (56, "8"),
+    (57, "9"),
+    (77, "℧"),
+    (79, "◯"),
+    (87, "Ω"),
+    (94, "↑"),
+    (95, "↓"),
+];
+
+const TS1_HIGH: [&str; 64] = [
+    "˘", "ˇ", "˝", "˝", "†", "‡", "‖", "‰", "•", "℃", "$", "¢", "ƒ", "₡", "₩", "₦", //
+    "₲", "₱", "₤", "℞", "‽", "⸘", "₫", "™", "‱", "¶", "฿", "№", "⁒", "℮", "◦", "℠", //
+    "⁅", "⁆", "¢", "£", "¤", "¥", "¦", "§", "¨", "©", "ª", "🄯", "¬", "℗", "®", "¯", //
+    "°", "±", "²", "³", "´", "µ", "¶", "·", "※", "¹", "º", "√", "¼", "½", "¾", "€", //
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess() {
+        assert_eq!(Encoding::for_font(b"cmr10"), Encoding::Ot1);
+        assert_eq!(Encoding::for_font(b"cmtt10"), Encoding::Ot1Typewriter);
+        assert_eq!(Encoding::for_font(b"ecrm1000"), Encoding::T1);
+        assert_eq!(Encoding::for_font(b"cmmi10"), Encoding::Oml);
+        assert_eq!(Encoding::for_font(b"cmsy10"), Encoding::Oms);
+        assert_eq!(Encoding::for_font(b"tcrm1000"), Encoding::Ts1);
+        assert_eq!(Encoding::for_font(b"rm-lmr10"), Encoding::Ot1);
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(Encoding::Ot1.decode(12), Some("fi"));
+        assert_eq!(Encoding::Ot1.decode(u32::from(b'a')), Some("a"));
+        assert_eq!(Encoding::Ot1.decode(34), Some("”"));
+        assert_eq!(Encoding::Ot1.decode(128), None);
+        assert_eq!(Encoding::Ot1Typewriter.decode(34), Some("\""));
+        assert_eq!(Encoding::Ot1Typewriter.decode(12), Some("↓"));
+        assert_eq!(Encoding::Ot1Typewriter.decode(0), Some("Γ"));
+        assert_eq!(Encoding::T1.decode(28), Some("fi"));
+        assert_eq!(Encoding::T1.decode(0xe9), Some("é"));
+        assert_eq!(Encoding::T1.decode(0xd7), Some("Œ"));
+        assert_eq!(Encoding::T1.decode(0xaa), Some("ł"));
+        assert_eq!(Encoding::Oml.decode(11), Some("α"));
+        assert_eq!(Encoding::Oms.decode(0), Some("−"));
+        assert_eq!(Encoding::Ts1.decode(0xbf), Some("€"));
+        assert_eq!(Encoding::Ts1.decode(1), None);
+    }
+
+    #[test]
+    fn accents() {
+        assert_eq!(Encoding::Ot1.accent(19), Some('\u{301}'));
+        assert_eq!(Encoding::Ot1.accent(127), Some('\u{308}'));
+        assert_eq!(Encoding::Ot1.accent(18), Some('\u{300}'));
+        assert_eq!(Encoding::Ot1.accent(96), None);
+        assert_eq!(Encoding::T1.accent(0), Some('\u{300}'));
+        assert_eq!(Encoding::Ot1Typewriter.accent(127), Some('\u{308}'));
+        assert_eq!(Encoding::Ot1Typewriter.accent(u32::from(b'^')), None);
+        assert_eq!(Encoding::Ot1.accent(u32::from(b'a')), None);
+    }
+}
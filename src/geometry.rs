@@ -0,0 +1,91 @@
+//! Areas on the page, shared by the modules that work out where things were drawn
+
+/// A rectangle on the page, in dvi units with v pointing down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    /// The smallest rectangle containing both `self` and `other`
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    /// The part of `self` inside `other`, or `None` if they don't overlap
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let rect = Rect {
+            left: self.left.max(other.left),
+            top: self.top.max(other.top),
+            right: self.right.min(other.right),
+            bottom: self.bottom.min(other.bottom),
+        };
+        if rect.is_empty() {
+            None
+        } else {
+            Some(rect)
+        }
+    }
+
+    /// Whether the rectangle has no area
+    pub fn is_empty(&self) -> bool {
+        self.left >= self.right || self.top >= self.bottom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects() {
+        let a = Rect {
+            left: 0,
+            top: 0,
+            right: 10,
+            bottom: 10,
+        };
+        let b = Rect {
+            left: 5,
+            top: -5,
+            right: 20,
+            bottom: 5,
+        };
+        assert_eq!(
+            a.union(&b),
+            Rect {
+                left: 0,
+                top: -5,
+                right: 20,
+                bottom: 10,
+            }
+        );
+        assert_eq!(
+            a.intersection(&b),
+            Some(Rect {
+                left: 5,
+                top: 0,
+                right: 10,
+                bottom: 5,
+            })
+        );
+        let touching = Rect { left: 10, ..a };
+        assert!(touching.is_empty());
+        assert_eq!(
+            a.intersection(&Rect {
+                left: 10,
+                right: 20,
+                ..a
+            }),
+            None
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! References to included graphics
+//!
+//! Documents don't contain their figures, only specials telling the driver which file to include
+//! and where. This module finds them, so the files a document depends on can be checked:
+//!
+//!  - dvips `PSfile=` specials, as written by `\includegraphics` with the `dvips` driver, with the
+//!    bounding box in `llx`, `lly`, `urx` and `ury` and the size in `rwi` and `rhi`,
+//!  - dvipdfmx `pdf:image` and `pdf:epdf` specials, with the size in `width` and `height` and the
+//!    bounding box in `bbox`. Images given a name with `@name` and placed again with
+//!    `pdf:uxobj @name` are reported at each place they are used, with any `width` or `height`
+//!    given to `pdf:uxobj`.
+
+use crate::{
+    document::Document,
+    interpreter::{Device, Error, FontMetrics, PageIndex},
+    special::Special,
+    util::dimension,
+};
+
+/// Which special a graphic was included with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// dvips `PSfile=`
+    PsFile,
+    /// dvipdfmx `pdf:image`
+    PdfImage,
+    /// dvipdfmx `pdf:epdf`
+    PdfEpdf,
+}
+
+/// A bounding box in big points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub llx: f64,
+    pub lly: f64,
+    pub urx: f64,
+    pub ury: f64,
+}
+
+/// A graphic included in the document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graphic {
+    pub kind: Kind,
+    pub file: String,
+    /// The index of the page in the document
+    pub page: usize,
+    /// Where the graphic is placed on the page, in dvi units
+    pub h: i32,
+    pub v: i32,
+    /// The part of the file to show
+    pub bbox: Option<BoundingBox>,
+    /// The width it is drawn at in big points, from `rwi` (which is in tenths) or `width`
+    pub width: Option<f64>,
+    /// The height it is drawn at in big points, from `rhi` (which is in tenths) or `height`
+    pub height: Option<f64>,
+    /// The page of a PDF file to include
+    pub page_number: Option<u32>,
+    /// The name given with `@name`, for dvipdfmx images
+    pub name: Option<String>,
+}
+
+impl Graphic {
+    /// Read a graphic from a special found at (h,v) on page `page`
+    ///
+    /// Returns `None` for specials that don't include graphics.
+    pub fn from_special(special: &Special, page: usize, h: i32, v: i32) -> Option<Self> {
+        match special {
+            Special::PsFile { file, options, .. } => {
+                let option = |key: &str| {
+                    options
+                        .iter()
+                        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                        .and_then(|(_, value)| value.parse::<f64>().ok())
+                };
+                let bbox = match (option("llx"), option("lly"), option("urx"), option("ury")) {
+                    (Some(llx), Some(lly), Some(urx), Some(ury)) => {
+                        Some(BoundingBox { llx, lly, urx, ury })
+                    }
+                    _ => None,
+                };
+                Some(Graphic {
+                    kind: Kind::PsFile,
+                    file: file.clone(),
+                    page,
+                    h,
+                    v,
+                    bbox,
+                    width: option("rwi").map(|rwi| rwi / 10.0),
+                    height: option("rhi").map(|rhi| rhi / 10.0),
+                    page_number: None,
+                    name: None,
+                })
+            }
+            Special::Pdf { command, args } => {
+                let kind = match command.as_str() {
+                    "image" => Kind::PdfImage,
+                    "epdf" => Kind::PdfEpdf,
+                    _ => return None,
+                };
+                let mut graphic = Graphic {
+                    kind,
+                    file: String::new(),
+                    page,
+                    h,
+                    v,
+                    bbox: None,
+                    width: None,
+                    height: None,
+                    page_number: None,
+                    name: None,
+                };
+                let tokens = tokens(args)?;
+                let mut tokens = tokens.iter();
+                while let Some(token) = tokens.next() {
+                    match token {
+                        Token::String(file) => graphic.file = file.clone(),
+                        Token::Word(word) => match word.as_str() {
+                            "width" => graphic.width = dimension(tokens.next()?.word()?),
+                            "height" => graphic.height = dimension(tokens.next()?.word()?),
+                            "page" => graphic.page_number = Some(number(&mut tokens)? as u32),
+                            "bbox" => {
+                                graphic.bbox = Some(BoundingBox {
+                                    llx: number(&mut tokens)?,
+                                    lly: number(&mut tokens)?,
+                                    urx: number(&mut tokens)?,
+                                    ury: number(&mut tokens)?,
+                                });
+                            }
+                            name if name.starts_with('@') => {
+                                graphic.name = Some(name[1..].to_string())
+                            }
+                            _ => (),
+                        },
+                    }
+                }
+                if graphic.file.is_empty() {
+                    None
+                } else {
+                    Some(graphic)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Find every graphic included in a document
+pub fn extract<M: FontMetrics>(document: &Document, metrics: M) -> Result<Vec<Graphic>, Error> {
+    let mut collector = Collector::default();
+    document.run(metrics, &mut collector)?;
+    Ok(collector.graphics)
+}
+
+/// A `Device` that collects graphics
+#[derive(Debug, Default)]
+struct Collector {
+    graphics: Vec<Graphic>,
+    page: PageIndex,
+}
+
+impl Device for Collector {
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.page.begin();
+    }
+
+    fn special(&mut self, h: i32, v: i32, payload: &[u8]) {
+        let special = Special::parse(payload);
+        if let Some(graphic) = Graphic::from_special(&special, self.page.get(), h, v) {
+            self.graphics.push(graphic);
+        } else if let Special::Pdf { command, args } = &special {
+            if command != "uxobj" {
+                return;
+            }
+            // placing a named image again, possibly at a different size
+            let tokens = match tokens(args) {
+                Some(tokens) => tokens,
+                None => return,
+            };
+            let name = tokens
+                .iter()
+                .find_map(|token| token.word()?.strip_prefix('@'));
+            let earlier = match name {
+                Some(name) => self
+                    .graphics
+                    .iter()
+                    .rev()
+                    .find(|g| g.name.as_deref() == Some(name)),
+                None => None,
+            };
+            if let Some(earlier) = earlier {
+                let mut graphic = Graphic {
+                    page: self.page.get(),
+                    h,
+                    v,
+                    ..earlier.clone()
+                };
+                // a new size replaces the old one rather than being mixed with it
+                let resized = tokens
+                    .iter()
+                    .any(|token| matches!(token.word(), Some("width") | Some("height")));
+                if resized {
+                    graphic.width = None;
+                    graphic.height = None;
+                }
+                let mut tokens = tokens.iter();
+                while let Some(token) = tokens.next() {
+                    match token.word() {
+                        Some("width") => {
+                            graphic.width = tokens.next().and_then(Token::word).and_then(dimension)
+                        }
+                        Some("height") => {
+                            graphic.height = tokens.next().and_then(Token::word).and_then(dimension)
+                        }
+                        _ => (),
+                    }
+                }
+                self.graphics.push(graphic);
+            }
+        }
+    }
+}
+
+/// A word or a PostScript string in the arguments of a dvipdfmx special
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    String(String),
+}
+
+impl Token {
+    fn word(&self) -> Option<&str> {
+        match self {
+            Token::Word(word) => Some(word),
+            Token::String(_) => None,
+        }
+    }
+}
+
+/// The next token as a number
+fn number<'a>(tokens: &mut impl Iterator<Item = &'a Token>) -> Option<f64> {
+    tokens.next()?.word()?.parse().ok()
+}
+
+/// Split arguments into words and `(strings)`, returning `None` if a string isn't closed
+fn tokens(args: &str) -> Option<Vec<Token>> {
+    let mut out = Vec::new();
+    let mut chars = args.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            let mut string = String::new();
+            let mut depth = 0;
+            loop {
+                match chars.next()? {
+                    '\\' => string.push(chars.next()?),
+                    '(' => {
+                        depth += 1;
+                        string.push('(');
+                    }
+                    ')' if depth == 0 => break,
+                    ')' => {
+                        depth -= 1;
+                        string.push(')');
+                    }
+                    c => string.push(c),
+                }
+            }
+            out.push(Token::String(string));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '(' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            out.push(Token::Word(word));
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        FontDef, Instruction,
+    };
+
+    struct Fixed;
+
+    impl FontMetrics for Fixed {
+        fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+            Some(10)
+        }
+    }
+
+    fn special(s: &str) -> Instruction {
+        Instruction::Xxx(Vec::from(s))
+    }
+
+    #[test]
+    fn extract_all() {
+        let document = Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: vec![
+                Page {
+                    counters: [0; 10],
+                    instructions: vec![
+                        Instruction::Right(100),
+                        special("PSfile=\"fig.eps\" llx=0 lly=0 urx=72 ury=36 rwi=720 rhi=360"),
+                        special("psfile=plain.ps"),
+                    ],
+                },
+                Page {
+                    counters: [0; 10],
+                    instructions: vec![
+                        special(
+                            "pdf:image @logo width 2in bbox 0 0 100 50 page 2 (images/logo (v2).pdf)",
+                        ),
+                        Instruction::Down(500),
+                        special("pdf:uxobj @logo"),
+                        special("pdf:uxobj @logo width 10pt"),
+                        special("pdf:epdf height 10bp (plot.eps)"),
+                        special("pdf:image (unterminated"),
+                        special("pdf:uxobj @nothing"),
+                    ],
+                },
+            ],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: Vec::new(),
+            },
+        };
+        let graphics = extract(&document, Fixed).unwrap();
+        assert_eq!(graphics.len(), 6);
+        assert_eq!(
+            graphics[0],
+            Graphic {
+                kind: Kind::PsFile,
+                file: "fig.eps".into(),
+                page: 0,
+                h: 100,
+                v: 0,
+                bbox: Some(BoundingBox {
+                    llx: 0.0,
+                    lly: 0.0,
+                    urx: 72.0,
+                    ury: 36.0
+                }),
+                width: Some(72.0),
+                height: Some(36.0),
+                page_number: None,
+                name: None,
+            }
+        );
+        assert_eq!(graphics[1].file, "plain.ps");
+        assert_eq!(graphics[1].bbox, None);
+        assert_eq!(graphics[2].kind, Kind::PdfImage);
+        assert_eq!(graphics[2].file, "images/logo (v2).pdf");
+        assert_eq!(graphics[2].page, 1);
+        assert_eq!(graphics[2].width, Some(144.0));
+        assert_eq!(graphics[2].page_number, Some(2));
+        assert_eq!(graphics[2].name.as_deref(), Some("logo"));
+        assert_eq!(graphics[3].file, "images/logo (v2).pdf");
+        assert_eq!(graphics[3].v, 500);
+        assert_eq!(graphics[3].width, Some(144.0));
+        assert_eq!(graphics[4].file, "images/logo (v2).pdf");
+        assert_eq!(graphics[4].width, Some(10.0 * 72.0 / 72.27));
+        assert_eq!(graphics[5].kind, Kind::PdfEpdf);
+        assert_eq!(graphics[5].height, Some(10.0));
+    }
+}
@@ -0,0 +1,458 @@
+//! An interpreter that executes dvi instructions
+//!
+//! A dvi file is a program for a very simple machine. The machine has a current position
+//! (h,v), four spacing registers (w,x,y,z), a stack to save them on and a current font. The
+//! [`Interpreter`] runs instructions on this machine and tells a [`Device`] where things should
+//! be drawn.
+//!
+//! The interpreter doesn't know how wide characters are, since that information lives in font
+//! files and not in the dvi file, so it asks a [`FontMetrics`] implementation.
+//!
+//! dvips colour specials are tracked as well, and the device is told about the colour to draw in
+//! at the start of every page and whenever it changes.
+//!
+//! [`Interpreter`]: ./struct.Interpreter.html
+//! [`Device`]: ./trait.Device.html
+//! [`FontMetrics`]: ./trait.FontMetrics.html
+
+use crate::{
+    color::{Color, ColorStack},
+    FontDef, Instruction,
+};
+use std::{collections::HashMap, error, fmt};
+
+/// The registers of the dvi machine that are saved by `Push`
+///
+/// All values are in dvi units. `h` increases to the right and `v` increases downwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub h: i32,
+    pub v: i32,
+    pub w: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// Provides the widths of characters
+pub trait FontMetrics {
+    /// The width of character `ch` in `font`, in dvi units, or `None` if it isn't known
+    fn char_width(&self, font: &FontDef, ch: u32) -> Option<i32>;
+}
+
+impl<M: FontMetrics + ?Sized> FontMetrics for &M {
+    fn char_width(&self, font: &FontDef, ch: u32) -> Option<i32> {
+        (**self).char_width(font, ch)
+    }
+}
+
+/// Something that receives the output of the interpreter
+///
+/// Every method has an empty default implementation, so a device only needs to implement what it
+/// is interested in. Positions are in dvi units, measured from the reference point of the page,
+/// which by convention sits one inch from the top and left edges of the paper.
+pub trait Device {
+    /// The preamble was read, giving the units of the file
+    fn preamble(&mut self, _numerator: u32, _denominator: u32, _magnification: u32) {}
+
+    /// A new page has started
+    fn begin_page(&mut self, _counters: &[i32; 10]) {}
+
+    /// The current page has ended
+    fn end_page(&mut self) {}
+
+    /// Draw character `ch` from `font` with its reference point at (h,v)
+    fn char(&mut self, _h: i32, _v: i32, _font: &FontDef, _ch: u32, _width: i32) {}
+
+    /// Draw a rule with its bottom left corner at (h,v)
+    ///
+    /// This is only called for rules with a positive height and width.
+    fn rule(&mut self, _h: i32, _v: i32, _height: i32, _width: i32) {}
+
+    /// A `\special` was found at (h,v)
+    fn special(&mut self, _h: i32, _v: i32, _payload: &[u8]) {}
+
+    /// Characters and rules from now on should be drawn in `color`
+    fn color(&mut self, _color: &Color) {}
+}
+
+/// The index of the current page, for devices that record where things are
+///
+/// Call `begin` from `Device::begin_page`. The index is 0 until the second page begins.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PageIndex(Option<usize>);
+
+impl PageIndex {
+    /// A new page has started
+    pub fn begin(&mut self) {
+        self.0 = Some(self.0.map_or(0, |index| index + 1));
+    }
+
+    /// The index of the current page
+    pub fn get(self) -> usize {
+        self.0.unwrap_or(0)
+    }
+}
+
+/// Things that can go wrong while interpreting a dvi file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input could not be parsed, starting at the given byte offset
+    Parse(usize),
+    /// A `Pop` was executed when the stack was empty
+    StackUnderflow,
+    /// A character was typeset before any font was selected
+    NoFont,
+    /// A font was selected that hasn't been defined
+    UndefinedFont(u32),
+    /// The font metrics didn't know the width of a character
+    MissingWidth { font: u32, ch: u32 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(offset) => write!(f, "could not parse instruction at byte {}", offset),
+            Error::StackUnderflow => write!(f, "pop with an empty stack"),
+            Error::NoFont => write!(f, "character typeset before a font was selected"),
+            Error::UndefinedFont(font) => write!(f, "font {} used but not defined", font),
+            Error::MissingWidth { font, ch } => {
+                write!(f, "no width for character {} in font {}", ch, font)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Executes dvi instructions and reports the results to a `Device`
+#[derive(Debug, Clone)]
+pub struct Interpreter<M> {
+    metrics: M,
+    position: Position,
+    stack: Vec<Position>,
+    font: Option<u32>,
+    fonts: HashMap<u32, FontDef>,
+    colors: ColorStack,
+}
+
+impl<M: FontMetrics> Interpreter<M> {
+    /// Create an interpreter that gets character widths from `metrics`
+    pub fn new(metrics: M) -> Self {
+        Interpreter {
+            metrics,
+            position: Position::default(),
+            stack: Vec::new(),
+            font: None,
+            fonts: HashMap::new(),
+            colors: ColorStack::new(),
+        }
+    }
+
+    /// The current position
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// The number of positions currently pushed on the stack
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The currently selected font, if any
+    pub fn current_font(&self) -> Option<&FontDef> {
+        self.font.and_then(|f| self.fonts.get(&f))
+    }
+
+    /// All fonts defined so far, by number
+    pub fn fonts(&self) -> &HashMap<u32, FontDef> {
+        &self.fonts
+    }
+
+    /// Define a font without executing a `FontDef` instruction
+    ///
+    /// This is useful for interpreting pages out of order, using the definitions from the
+    /// postamble.
+    pub fn define_font(&mut self, def: FontDef) {
+        self.fonts.insert(def.number, def);
+    }
+
+    /// The colour stack, which carries over from one page to the next
+    pub fn colors(&self) -> &ColorStack {
+        &self.colors
+    }
+
+    /// Replace the colour stack
+    ///
+    /// This is needed to interpret pages out of order, see
+    /// [`Document::color_stacks`](../document/struct.Document.html#method.color_stacks).
+    pub fn set_colors(&mut self, colors: ColorStack) {
+        self.colors = colors;
+    }
+
+    /// The font metrics used by this interpreter
+    pub fn metrics(&self) -> &M {
+        &self.metrics
+    }
+
+    /// Execute a single instruction
+    pub fn execute<D: Device>(
+        &mut self,
+        instruction: &Instruction,
+        device: &mut D,
+    ) -> Result<(), Error> {
+        let pos = &mut self.position;
+        match *instruction {
+            Instruction::Set(ch) => {
+                let width = self.typeset(ch, device)?;
+                self.position.h = self.position.h.wrapping_add(width);
+            }
+            Instruction::Put(ch) => {
+                self.typeset(ch, device)?;
+            }
+            Instruction::SetRule(height, width) => {
+                if height > 0 && width > 0 {
+                    device.rule(pos.h, pos.v, height, width);
+                }
+                pos.h = pos.h.wrapping_add(width);
+            }
+            Instruction::PutRule(height, width) => {
+                if height > 0 && width > 0 {
+                    device.rule(pos.h, pos.v, height, width);
+                }
+            }
+            Instruction::Nop => (),
+            Instruction::Bop(ref counters, _) => {
+                *pos = Position::default();
+                self.stack.clear();
+                self.font = None;
+                device.begin_page(counters);
+                device.color(self.colors.current());
+            }
+            Instruction::Eop => device.end_page(),
+            Instruction::Push => self.stack.push(*pos),
+            Instruction::Pop => *pos = self.stack.pop().ok_or(Error::StackUnderflow)?,
+            Instruction::Right(b) => pos.h = pos.h.wrapping_add(b),
+            Instruction::W(b) => {
+                if let Some(b) = b {
+                    pos.w = b;
+                }
+                pos.h = pos.h.wrapping_add(pos.w);
+            }
+            Instruction::X(b) => {
+                if let Some(b) = b {
+                    pos.x = b;
+                }
+                pos.h = pos.h.wrapping_add(pos.x);
+            }
+            Instruction::Down(a) => pos.v = pos.v.wrapping_add(a),
+            Instruction::Y(a) => {
+                if let Some(a) = a {
+                    pos.y = a;
+                }
+                pos.v = pos.v.wrapping_add(pos.y);
+            }
+            Instruction::Z(a) => {
+                if let Some(a) = a {
+                    pos.z = a;
+                }
+                pos.v = pos.v.wrapping_add(pos.z);
+            }
+            Instruction::Font(f) => {
+                if !self.fonts.contains_key(&f) {
+                    return Err(Error::UndefinedFont(f));
+                }
+                self.font = Some(f);
+            }
+            Instruction::Xxx(ref payload) => {
+                device.special(pos.h, pos.v, payload);
+                if self.colors.apply_payload(payload) {
+                    device.color(self.colors.current());
+                }
+            }
+            Instruction::FontDef(ref def) => {
+                self.fonts.insert(def.number, def.clone());
+            }
+            Instruction::Pre {
+                numerator,
+                denominator,
+                magnification,
+                ..
+            } => device.preamble(numerator, denominator, magnification),
+            Instruction::Post { .. } | Instruction::PostPost { .. } => (),
+        }
+        Ok(())
+    }
+
+    /// Parse and execute every instruction in `input`
+    pub fn run<D: Device>(&mut self, input: &[u8], device: &mut D) -> Result<(), Error> {
+        let mut rest = input;
+        while !rest.is_empty() {
+            let (next, instruction) =
+                Instruction::parse(rest).map_err(|_| Error::Parse(input.len() - rest.len()))?;
+            self.execute(&instruction, device)?;
+            if let Instruction::PostPost { .. } = instruction {
+                break;
+            }
+            rest = next;
+        }
+        Ok(())
+    }
+
+    /// Typeset a character in the current font, returning its width
+    fn typeset<D: Device>(&mut self, ch: u32, device: &mut D) -> Result<i32, Error> {
+        let number = self.font.ok_or(Error::NoFont)?;
+        let font = self
+            .fonts
+            .get(&number)
+            .ok_or(Error::UndefinedFont(number))?;
+        let width = self
+            .metrics
+            .char_width(font, ch)
+            .ok_or(Error::MissingWidth { font: number, ch })?;
+        device.char(self.position.h, self.position.v, font, ch, width);
+        Ok(width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every character is 10 units wide
+    struct Fixed;
+
+    impl FontMetrics for Fixed {
+        fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+            Some(10)
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        chars: Vec<(i32, i32, u32)>,
+        rules: Vec<(i32, i32, i32, i32)>,
+        colors: Vec<Color>,
+        pages: usize,
+    }
+
+    impl Device for Recorder {
+        fn end_page(&mut self) {
+            self.pages += 1;
+        }
+
+        fn char(&mut self, h: i32, v: i32, _: &FontDef, ch: u32, _: i32) {
+            self.chars.push((h, v, ch));
+        }
+
+        fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+            self.rules.push((h, v, height, width));
+        }
+
+        fn color(&mut self, color: &Color) {
+            self.colors.push(color.clone());
+        }
+    }
+
+    fn font_def(number: u32) -> Instruction {
+        Instruction::FontDef(FontDef {
+            number,
+            checksum: 0,
+            scale_factor: 655360,
+            design_size: 655360,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        })
+    }
+
+    fn run(program: &[Instruction]) -> Result<Recorder, Error> {
+        let mut interpreter = Interpreter::new(Fixed);
+        let mut recorder = Recorder::default();
+        for instruction in program {
+            interpreter.execute(instruction, &mut recorder)?;
+        }
+        Ok(recorder)
+    }
+
+    #[test]
+    fn movement() {
+        let out = run(&[
+            font_def(0),
+            Instruction::Bop([0; 10], -1),
+            Instruction::Font(0),
+            Instruction::Set(b'a'.into()),
+            Instruction::Put(b'b'.into()),
+            Instruction::Push,
+            Instruction::W(Some(5)),
+            Instruction::W(None),
+            Instruction::Down(20),
+            Instruction::Set(b'c'.into()),
+            Instruction::Pop,
+            Instruction::Y(Some(3)),
+            Instruction::Set(b'd'.into()),
+            Instruction::SetRule(4, 6),
+            Instruction::SetRule(-4, 6),
+            Instruction::Set(b'e'.into()),
+            Instruction::Eop,
+        ])
+        .unwrap();
+        assert_eq!(
+            out.chars,
+            vec![
+                (0, 0, b'a'.into()),
+                (10, 0, b'b'.into()),
+                (20, 20, b'c'.into()),
+                (10, 3, b'd'.into()),
+                (32, 3, b'e'.into()),
+            ]
+        );
+        assert_eq!(out.rules, vec![(20, 3, 4, 6)]);
+        assert_eq!(out.pages, 1);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(run(&[Instruction::Pop]).err(), Some(Error::StackUnderflow));
+        assert_eq!(run(&[Instruction::Set(1)]).err(), Some(Error::NoFont));
+        assert_eq!(
+            run(&[Instruction::Font(3)]).err(),
+            Some(Error::UndefinedFont(3))
+        );
+    }
+
+    #[test]
+    fn bop_resets_state() {
+        let mut interpreter = Interpreter::new(Fixed);
+        let mut recorder = Recorder::default();
+        for instruction in &[
+            font_def(0),
+            Instruction::Font(0),
+            Instruction::Push,
+            Instruction::Right(100),
+            Instruction::Bop([0; 10], -1),
+        ] {
+            interpreter.execute(instruction, &mut recorder).unwrap();
+        }
+        assert_eq!(interpreter.position(), Position::default());
+        assert_eq!(interpreter.stack_depth(), 0);
+        assert!(interpreter.current_font().is_none());
+    }
+
+    #[test]
+    fn colors() {
+        let special = |s: &str| Instruction::Xxx(Vec::from(s));
+        let out = run(&[
+            Instruction::Bop([0; 10], -1),
+            special("color push Red"),
+            special("ps: 1 setgray"),
+            Instruction::Eop,
+            Instruction::Bop([0; 10], -1),
+            special("color pop"),
+            Instruction::Eop,
+        ])
+        .unwrap();
+        let red = Color::Named("Red".into());
+        let black = Color::Gray(0.0);
+        assert_eq!(out.colors, vec![black.clone(), red.clone(), red, black]);
+    }
+}
@@ -0,0 +1,387 @@
+//! Documents that are only parsed as their pages are needed
+//!
+//! A dvi file can be read from the end: the postamble is found by skipping back over the 223
+//! bytes, and each `Bop` points to the one before it. [`LazyDocument`] uses this to find every page
+//! without reading them, then parses a page when it is asked for, borrowing specials and font
+//! names from the input. Together with a memory-mapped file (see `Document::open_mmap`, with the
+//! `mmap` feature) only the pages that are used are read from disk.
+//!
+//! Font definitions inside a page are read with its other instructions. Those between pages,
+//! which TeX never writes but the format allows, are not: finding them would mean reading the
+//! page before. `Document::parse` moves them to the start of the following page, so for such
+//! files a page read here can lack definitions that the same page from `Document::parse` has.
+//! The postamble lists every font anyway, and [`LazyDocument::to_document`] reads the whole file
+//! as `Document::parse` does.
+//!
+//! [`LazyDocument`]: ./struct.LazyDocument.html
+//! [`LazyDocument::to_document`]: ./struct.LazyDocument.html#method.to_document
+
+#[cfg(feature = "rayon")]
+use crate::interpreter::{self, Device, FontMetrics};
+use crate::{
+    document::{Document, Error, Page, Postamble, Preamble},
+    FontDef, Instruction, InstructionRef,
+};
+use std::convert::TryFrom;
+
+/// A dvi file whose pages are parsed when they are used
+#[derive(Debug, Clone)]
+pub struct LazyDocument<B> {
+    bytes: B,
+    preamble: Preamble,
+    postamble: Postamble,
+    /// The offset of the `Bop` of each page
+    pages: Vec<usize>,
+}
+
+impl<B: AsRef<[u8]>> LazyDocument<B> {
+    /// Read the preamble and postamble, and find the pages
+    pub fn new(bytes: B) -> Result<Self, Error> {
+        let input = bytes.as_ref();
+        let preamble = match Instruction::parse(input) {
+            Ok((
+                _,
+                Instruction::Pre {
+                    format,
+                    numerator,
+                    denominator,
+                    magnification,
+                    comment,
+                },
+            )) => Preamble {
+                format,
+                numerator,
+                denominator,
+                magnification,
+                comment,
+            },
+            Ok(_) => return Err(Error::Unexpected(0)),
+            Err(nom::Err::Incomplete(_)) => return Err(Error::Truncated),
+            Err(_) => return Err(Error::Parse(0)),
+        };
+
+        // skip back over the 223s, the identification byte and the pointer to the postamble
+        let padding = input.iter().rev().take_while(|&&b| b == 223).count();
+        if padding < 4 || input.len() < padding + 5 {
+            return Err(Error::Truncated);
+        }
+        let end = input.len() - padding - 5;
+        let mut pointer = [0; 4];
+        pointer.copy_from_slice(&input[end..end + 4]);
+        let post = offset(u32::from_be_bytes(pointer), end)?;
+        let (mut rest, final_bop_pointer, mut postamble) = match parse_at(input, post)? {
+            (
+                rest,
+                InstructionRef::Other(Instruction::Post {
+                    final_bop_pointer,
+                    tallest_height,
+                    widest_width,
+                    max_stack_depth,
+                    ..
+                }),
+            ) => {
+                let postamble = Postamble {
+                    tallest_height,
+                    widest_width,
+                    max_stack_depth,
+                    fonts: Vec::new(),
+                };
+                (rest, final_bop_pointer, postamble)
+            }
+            _ => return Err(Error::Unexpected(post)),
+        };
+        loop {
+            let at = input.len() - rest.len();
+            let (next, instruction) = parse_at(input, at)?;
+            match instruction {
+                InstructionRef::FontDef(def) => postamble.fonts.push(def.to_owned()),
+                InstructionRef::Other(Instruction::Nop) => (),
+                InstructionRef::Other(Instruction::PostPost { .. }) => break,
+                _ => return Err(Error::Unexpected(at)),
+            }
+            rest = next;
+        }
+
+        // follow the pointers back from the last page, which must each go further back
+        let mut pages = Vec::new();
+        let mut pointer = final_bop_pointer;
+        let mut limit = post;
+        while pointer != -1 {
+            let bop = offset(pointer as u32, limit)?;
+            match parse_at(input, bop)?.1 {
+                InstructionRef::Other(Instruction::Bop(_, previous)) => pointer = previous,
+                _ => return Err(Error::Unexpected(bop)),
+            }
+            pages.push(bop);
+            limit = bop;
+        }
+        pages.reverse();
+
+        Ok(LazyDocument {
+            bytes,
+            preamble,
+            postamble,
+            pages,
+        })
+    }
+
+    pub fn preamble(&self) -> &Preamble {
+        &self.preamble
+    }
+
+    pub fn postamble(&self) -> &Postamble {
+        &self.postamble
+    }
+
+    /// The number of pages
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// The counters of page `index`, without reading the rest of it
+    pub fn counters(&self, index: usize) -> Option<Result<[i32; 10], Error>> {
+        let bop = *self.pages.get(index)?;
+        Some(match parse_at(self.bytes.as_ref(), bop) {
+            Ok((_, InstructionRef::Other(Instruction::Bop(counters, _)))) => Ok(counters),
+            Ok(_) => Err(Error::Unexpected(bop)),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// The instructions of page `index` between its `Bop` and `Eop`, borrowing from the input
+    ///
+    /// Font definitions before the `Bop` aren't included, unlike in `Document::parse`.
+    pub fn instructions(&self, index: usize) -> Option<Result<Vec<InstructionRef<'_>>, Error>> {
+        let bop = *self.pages.get(index)?;
+        let input = self.bytes.as_ref();
+        let mut at = match parse_at(input, bop) {
+            Ok((rest, _)) => input.len() - rest.len(),
+            Err(e) => return Some(Err(e)),
+        };
+        let mut instructions = Vec::new();
+        Some(loop {
+            let (rest, instruction) = match parse_at(input, at) {
+                Ok(ok) => ok,
+                Err(e) => break Err(e),
+            };
+            match instruction {
+                InstructionRef::Other(Instruction::Eop) => break Ok(instructions),
+                InstructionRef::Other(Instruction::Bop(..))
+                | InstructionRef::Other(Instruction::Post { .. })
+                | InstructionRef::Other(Instruction::PostPost { .. })
+                | InstructionRef::Pre { .. } => break Err(Error::Unexpected(at)),
+                instruction => instructions.push(instruction),
+            }
+            at = input.len() - rest.len();
+        })
+    }
+
+    /// Read page `index`, copying its instructions
+    pub fn page(&self, index: usize) -> Option<Result<Page, Error>> {
+        let counters = match self.counters(index)? {
+            Ok(counters) => counters,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.instructions(index)?.map(|instructions| Page {
+            counters,
+            instructions: instructions.into_iter().map(Instruction::from).collect(),
+        }))
+    }
+
+    /// Every font definition in the postamble
+    pub fn fonts(&self) -> &[FontDef] {
+        &self.postamble.fonts
+    }
+
+    /// Read every page, to make a `Document`
+    pub fn to_document(&self) -> Result<Document, Error> {
+        Document::parse(self.bytes.as_ref())
+    }
+
+    /// The underlying bytes
+    pub fn bytes(&self) -> &B {
+        &self.bytes
+    }
+
+    /// Read every page and interpret them, both in parallel
+    ///
+    /// The pages are interpreted as `Document::par_run` does, once they have all been read.
+    #[cfg(feature = "rayon")]
+    pub fn par_run<M, D, F>(
+        &self,
+        metrics: M,
+        device: F,
+    ) -> Result<Vec<Result<D, interpreter::Error>>, Error>
+    where
+        B: Sync,
+        M: FontMetrics + Clone + Send + Sync,
+        D: Device + Send,
+        F: Fn(usize) -> D + Sync,
+    {
+        use rayon::prelude::*;
+
+        let pages = (0..self.len())
+            .into_par_iter()
+            .filter_map(|index| self.page(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let document = Document {
+            preamble: self.preamble.clone(),
+            pages,
+            postamble: self.postamble.clone(),
+        };
+        Ok(document.par_run(metrics, device))
+    }
+}
+
+/// Check that a pointer is before `limit`, where it can't point at itself or anything after it
+fn offset(pointer: u32, limit: usize) -> Result<usize, Error> {
+    match usize::try_from(pointer) {
+        Ok(offset) if offset < limit => Ok(offset),
+        _ => Err(Error::Unexpected(limit)),
+    }
+}
+
+/// Parse the instruction at `offset`
+fn parse_at(input: &[u8], offset: usize) -> Result<(&[u8], InstructionRef<'_>), Error> {
+    match InstructionRef::parse(&input[offset..]) {
+        Ok(ok) => Ok(ok),
+        Err(nom::Err::Incomplete(_)) => Err(Error::Truncated),
+        Err(_) => Err(Error::Parse(offset)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Document {
+        let font = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        };
+        let page = |n: i32, special: &str| Page {
+            counters: [n, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            instructions: vec![
+                Instruction::Font(0),
+                Instruction::Xxx(Vec::from(special)),
+                Instruction::Set(65),
+            ],
+        };
+        let mut first = page(1, "first");
+        first
+            .instructions
+            .insert(0, Instruction::FontDef(font.clone()));
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::from("lazy"),
+            },
+            pages: vec![first, page(2, "second"), page(3, "third")],
+            postamble: Postamble {
+                tallest_height: 100,
+                widest_width: 200,
+                max_stack_depth: 1,
+                fonts: vec![font],
+            },
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let document = document();
+        let mut bytes = Vec::new();
+        document.dump(&mut bytes).unwrap();
+        let lazy = LazyDocument::new(&bytes[..]).unwrap();
+        assert_eq!(lazy.len(), 3);
+        assert_eq!(lazy.preamble(), &document.preamble);
+        assert_eq!(lazy.postamble(), &document.postamble);
+        assert_eq!(lazy.counters(2).unwrap().unwrap()[0], 3);
+        let instructions = lazy.instructions(1).unwrap().unwrap();
+        assert_eq!(instructions[1], InstructionRef::Xxx(b"second"));
+        for (index, page) in document.pages.iter().enumerate() {
+            assert_eq!(&lazy.page(index).unwrap().unwrap(), page);
+        }
+        assert!(lazy.page(3).is_none());
+        assert_eq!(lazy.to_document().unwrap(), document);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() {
+        let document = document();
+        let path = std::env::temp_dir().join(format!("dvi-lazy-{}.dvi", std::process::id()));
+        document
+            .dump(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+        // Safety: nothing else knows about the file
+        let lazy = unsafe { Document::open_mmap(&path) }.unwrap();
+        assert_eq!(lazy.page(2).unwrap().unwrap(), document.pages[2]);
+        drop(lazy);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_run() {
+        struct Specials(Vec<Vec<u8>>);
+        impl Device for Specials {
+            fn special(&mut self, _: i32, _: i32, payload: &[u8]) {
+                self.0.push(payload.to_vec());
+            }
+        }
+        #[derive(Clone, Copy)]
+        struct Fixed;
+        impl FontMetrics for Fixed {
+            fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+                Some(10)
+            }
+        }
+
+        let mut bytes = Vec::new();
+        document().dump(&mut bytes).unwrap();
+        let lazy = LazyDocument::new(&bytes[..]).unwrap();
+        let specials = lazy
+            .par_run(Fixed, |_| Specials(Vec::new()))
+            .unwrap()
+            .into_iter()
+            .map(|specials| specials.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            specials,
+            vec![
+                vec![Vec::from("first")],
+                vec![Vec::from("second")],
+                vec![Vec::from("third")]
+            ]
+        );
+    }
+
+    #[test]
+    fn broken() {
+        let mut bytes = Vec::new();
+        document().dump(&mut bytes).unwrap();
+        assert_eq!(
+            LazyDocument::new(&bytes[..bytes.len() - 4]).unwrap_err(),
+            Error::Truncated
+        );
+        // point the postamble past the end of the file
+        let end = bytes.iter().rev().take_while(|&&b| b == 223).count() + 5;
+        let at = bytes.len() - end;
+        bytes[at..at + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            LazyDocument::new(&bytes[..]),
+            Err(Error::Unexpected(_))
+        ));
+    }
+}
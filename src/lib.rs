@@ -17,7 +17,11 @@
 //!
 //! Note that currently paths must be utf8, and parsing will error if this is not true
 //!
+//! Files from untrusted sources should be read with `Document::parse_with_limits`, which stops
+//! at the first special, page, font or push that goes over the given [`ParseLimits`].
+//!
 //! [instruction_enum]: ./enum.Instruction.html
+//! [`ParseLimits`]: ./limits/struct.ParseLimits.html
 
 //! ## Implementation notes
 //!
@@ -26,19 +30,101 @@
 //! i32, and distance values, which are signed. Signed values use 2s-complement (same as rust).
 //!
 //! See SPECIFICATION.md for more details
+//!
+//! ## Features
+//!
+//!  - `std` (on by default): everything except the parser and dumper needs the standard library.
+//!    Without it the crate is `no_std`, and only needs `alloc` for `Instruction`, `FontDef`,
+//!    `InstructionRef`, and the `Parse` and `Dump` traits. Instructions are dumped to a `Sink`,
+//!    which is implemented for `Vec<u8>`, or can be implemented for a device's own buffer.
+//!  - `async`: read and write dvi files over `futures-io` streams, with the `stream` module.
+//!  - `bytes`: implement `Parse` for `bytes::Bytes`.
+//!  - `cli`: build the `dvi2json` and `json2dvi` programs, which convert between dvi files and
+//!    JSON or YAML in the shape described below for the `serde` feature, and the `dvi` program,
+//!    which prints information about dvi files, lists their instructions, checks them, extracts
+//!    their text and fonts, and selects and joins pages.
+//!  - `mmap`: open files with `Document::open_mmap`.
+//!  - `rayon`: interpret pages in parallel with `Document::par_run` and `LazyDocument::par_run`.
+//!  - `serde`: implement `Serialize` and `Deserialize` for `Instruction`, `FontDef` and the types
+//!    in `document`, using serde's default shapes. In JSON an instruction looks like `"Nop"`,
+//!    `{"Set": 65}` or `{"Pre": {"format": 2, ...}}`. Byte strings (specials, comments and font
+//!    names) are strings when they are valid UTF-8, and `{"base64": "..."}` with the standard
+//!    alphabet and padding when they aren't.
+//!  - `tokio`: the `async` feature, plus `stream::Tokio` to use tokio's readers and writers.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod bbox;
+mod borrowed;
+#[cfg(feature = "std")]
+pub mod checksum;
+#[cfg(feature = "std")]
+pub mod color;
+#[cfg(feature = "std")]
+pub mod document;
 mod dumper;
+#[cfg(feature = "std")]
+pub mod encoding;
+pub mod geometry;
+#[cfg(feature = "std")]
+pub mod graphics;
+#[cfg(feature = "std")]
+pub mod interpreter;
+#[cfg(feature = "std")]
+pub mod lazy;
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod link;
 mod parser;
+#[cfg(feature = "std")]
+pub mod pdf;
+#[cfg(feature = "std")]
+pub mod pk;
+#[cfg(feature = "std")]
+pub mod ps;
+#[cfg(feature = "std")]
+pub mod raster;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "std")]
+pub mod source;
+#[cfg(feature = "std")]
+pub mod special;
+#[cfg(feature = "async")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod svg;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod tfm;
+#[cfg(feature = "std")]
+pub mod tpic;
 mod traits;
+#[cfg(feature = "std")]
+pub mod usage;
 pub(crate) mod util;
 
+extern crate alloc;
+// the tests are run with the standard library even when the crate is built without it
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
 pub use nom::IResult;
-use std::io::{self, Write};
 
-pub use crate::traits::{Dump, Parse};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+pub use crate::{
+    borrowed::{FontDefRef, InstructionRef, Instructions},
+    limits::ParseLimits,
+    traits::{Dump, Parse, Sink},
+};
 
 /// A font definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FontDef {
     /// The font number of this font (only 1 font per number + once in postamble)
     pub number: u32,
@@ -49,8 +135,10 @@ pub struct FontDef {
     /// How to scale the font
     pub design_size: u32,
     /// Directory of the font file, default if None
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::option"))]
     pub directory: Option<Vec<u8>>,
     /// Name of the font file
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize"))]
     pub filename: Vec<u8>,
 }
 
@@ -59,6 +147,7 @@ pub struct FontDef {
 /// This is the primary unit of a dvi file. Every file is a sequence of instructions following some
 /// rules, for example 'preamble only occurs once at the beginning.'
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     /// typeset a character and move right
     ///
@@ -170,7 +259,7 @@ pub enum Instruction {
     /// DVI-reading programs are being used. TeX82 generates xxx1 when a short enough \special
     /// appears, setting k to the number of bytes being sent. It is recommended that x be a string
     /// having the form of a keyword followed by possible parameters relevant to that keyword.
-    Xxx(Vec<u8>),
+    Xxx(#[cfg_attr(feature = "serde", serde(with = "crate::serialize"))] Vec<u8>),
     /// define the meaning of a font number
     ///
     /// The four-byte value c is the check sum that TeX (or whatever program generated the DVI
@@ -225,6 +314,7 @@ pub enum Instruction {
         numerator: u32,
         denominator: u32,
         magnification: u32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize"))]
         comment: Vec<u8>,
     },
     /// postamble beginning
@@ -294,19 +384,130 @@ pub enum Instruction {
 // See SPECIFICATION.md for opt codes
 impl Instruction {
     /// Convert this instruction to a string;
-    pub fn dump<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        dumper::dump(self, writer)
+    pub fn dump<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<(), S::Error> {
+        dumper::dump(self, sink)
     }
 
     /// Parse an instruction from a byte slice
     pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
         parser::parse(bytes)
     }
+
+    /// Parse an instruction, failing with `ErrorKind::TooLarge` if it is a special or preamble
+    /// longer than the limits allow
+    pub fn parse_limited<'a>(bytes: &'a [u8], limits: &ParseLimits) -> IResult<&'a [u8], Self> {
+        parser::check_limits(bytes, limits)?;
+        parser::parse(bytes)
+    }
+}
+
+/// A one line listing of the instruction, using the names from the specification
+///
+/// Byte strings are shown as text, with anything that isn't printable ASCII escaped.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .flat_map(|&b| core::ascii::escape_default(b))
+                .map(char::from)
+                .collect::<String>()
+        };
+        let spacing = |f: &mut fmt::Formatter, name: &str, amount: Option<i32>| match amount {
+            Some(amount) => write!(f, "{} {}", name, amount),
+            None => write!(f, "{}0", name),
+        };
+        match self {
+            Instruction::Set(ch) => write!(f, "set {}", ch),
+            Instruction::SetRule(height, width) => write!(f, "set_rule {} {}", height, width),
+            Instruction::Put(ch) => write!(f, "put {}", ch),
+            Instruction::PutRule(height, width) => write!(f, "put_rule {} {}", height, width),
+            Instruction::Nop => write!(f, "nop"),
+            Instruction::Bop(counters, previous) => {
+                write!(f, "bop")?;
+                for counter in counters {
+                    write!(f, " {}", counter)?;
+                }
+                write!(f, " previous {}", previous)
+            }
+            Instruction::Eop => write!(f, "eop"),
+            Instruction::Push => write!(f, "push"),
+            Instruction::Pop => write!(f, "pop"),
+            Instruction::Right(amount) => write!(f, "right {}", amount),
+            Instruction::W(amount) => spacing(f, "w", *amount),
+            Instruction::X(amount) => spacing(f, "x", *amount),
+            Instruction::Down(amount) => write!(f, "down {}", amount),
+            Instruction::Y(amount) => spacing(f, "y", *amount),
+            Instruction::Z(amount) => spacing(f, "z", *amount),
+            Instruction::Font(number) => write!(f, "fnt {}", number),
+            Instruction::Xxx(payload) => write!(f, "xxx \"{}\"", text(payload)),
+            Instruction::FontDef(def) => {
+                write!(f, "fnt_def {} \"", def.number)?;
+                if let Some(directory) = &def.directory {
+                    write!(f, "{}/", text(directory))?;
+                }
+                write!(
+                    f,
+                    "{}\" checksum {:08x} scale {} design {}",
+                    text(&def.filename),
+                    def.checksum,
+                    def.scale_factor,
+                    def.design_size
+                )
+            }
+            Instruction::Pre {
+                format,
+                numerator,
+                denominator,
+                magnification,
+                comment,
+            } => write!(
+                f,
+                "pre {} {}/{} mag {} \"{}\"",
+                format,
+                numerator,
+                denominator,
+                magnification,
+                text(comment)
+            ),
+            Instruction::Post {
+                final_bop_pointer,
+                numerator,
+                denominator,
+                magnification,
+                tallest_height,
+                widest_width,
+                max_stack_depth,
+                total_no_pages,
+            } => write!(
+                f,
+                "post {} {}/{} mag {} height {} width {} stack {} pages {}",
+                final_bop_pointer,
+                numerator,
+                denominator,
+                magnification,
+                tallest_height,
+                widest_width,
+                max_stack_depth,
+                total_no_pages
+            ),
+            Instruction::PostPost {
+                post_pointer,
+                ident,
+                two_two_three,
+            } => write!(
+                f,
+                "post_post {} {} padding {}",
+                post_pointer, ident, two_two_three
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::{string::ToString, vec};
 
     /// Helper function to assert that encoding and parsing is a no-op
     fn ser_de(input: Vec<Instruction>) {
@@ -611,4 +812,37 @@ mod tests {
             assert!(output.is_err())
         }
     }
+
+    #[test]
+    fn display() {
+        let font = FontDef {
+            number: 1,
+            checksum: 0x1234abcd,
+            scale_factor: 655360,
+            design_size: 655360,
+            directory: Some(Vec::from("fonts")),
+            filename: Vec::from("cmr10"),
+        };
+        for (instruction, text) in [
+            (Instruction::Set(65), "set 65"),
+            (Instruction::PutRule(1, -2), "put_rule 1 -2"),
+            (Instruction::W(None), "w0"),
+            (Instruction::Z(Some(-3)), "z -3"),
+            (Instruction::Font(300), "fnt 300"),
+            (
+                Instruction::Xxx(b"a \"b\"\n\xff".to_vec()),
+                r#"xxx "a \"b\"\n\xff""#,
+            ),
+            (
+                Instruction::FontDef(font),
+                r#"fnt_def 1 "fonts/cmr10" checksum 1234abcd scale 655360 design 655360"#,
+            ),
+            (
+                Instruction::Bop([1, 2, 0, 0, 0, 0, 0, 0, 0, 0], -1),
+                "bop 1 2 0 0 0 0 0 0 0 0 previous -1",
+            ),
+        ] {
+            assert_eq!(instruction.to_string(), text);
+        }
+    }
 }
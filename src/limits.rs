@@ -0,0 +1,244 @@
+//! Limits on what the parser will accept from untrusted input
+//!
+//! A dvi file can ask for a special of up to 4GiB, or be made of millions of tiny pages, and
+//! nothing in the format stops a page pushing without popping. [`ParseLimits`] puts a bound on
+//! each of these, and `Document::parse_with_limits`, `Instruction::parse_limited` and
+//! `AsyncDviReader::limits` stop with an error naming the [`Limit`] that was exceeded, before
+//! reading or storing anything over it.
+//!
+//! [`ParseLimits`]: ./struct.ParseLimits.html
+//! [`Limit`]: ./enum.Limit.html
+
+#[cfg(feature = "std")]
+use crate::Instruction;
+#[cfg(feature = "std")]
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+/// The largest things the parser will accept
+///
+/// The default limits are generous for documents typeset by TeX, but small enough that a file
+/// can't make the parser allocate much more memory than its own size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The longest payload of an `Xxx`, in bytes
+    pub max_special_len: usize,
+    /// The longest comment in the preamble, in bytes
+    pub max_comment_len: usize,
+    /// The most pages in a document
+    pub max_pages: usize,
+    /// The most `Push` instructions without a `Pop` on a page
+    pub max_stack_depth: usize,
+    /// The most distinct font numbers defined in a document
+    pub max_fonts: usize,
+    /// The most instructions in a document, including the preamble and postamble
+    pub max_instructions: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_special_len: 1 << 20,
+            max_comment_len: 255,
+            max_pages: 100_000,
+            max_stack_depth: 1_000,
+            max_fonts: 10_000,
+            max_instructions: 100_000_000,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// No limits, other than those of the format itself
+    pub const fn unlimited() -> Self {
+        ParseLimits {
+            max_special_len: usize::MAX,
+            max_comment_len: usize::MAX,
+            max_pages: usize::MAX,
+            max_stack_depth: usize::MAX,
+            max_fonts: usize::MAX,
+            max_instructions: usize::MAX,
+        }
+    }
+}
+
+/// One of the limits in `ParseLimits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    SpecialLength,
+    CommentLength,
+    Pages,
+    StackDepth,
+    Fonts,
+    Instructions,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Limit::SpecialLength => "special length",
+            Limit::CommentLength => "comment length",
+            Limit::Pages => "page",
+            Limit::StackDepth => "stack depth",
+            Limit::Fonts => "font",
+            Limit::Instructions => "instruction",
+        })
+    }
+}
+
+/// Check the length of a special or preamble comment before it is read
+///
+/// Only the length needs to be in `input`, so this can be used before waiting for the rest of a
+/// long instruction to arrive.
+pub(crate) fn check_payload(input: &[u8], limits: &ParseLimits) -> Result<(), Limit> {
+    let be = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| (n << 8) | b as usize);
+    let (len, max, limit) = match input {
+        [code @ 239..=242, rest @ ..] => {
+            let width = usize::from(code - 238);
+            match rest.get(..width) {
+                Some(len) => (be(len), limits.max_special_len, Limit::SpecialLength),
+                None => return Ok(()),
+            }
+        }
+        [247, rest @ ..] => match rest.get(13) {
+            Some(&len) => (len.into(), limits.max_comment_len, Limit::CommentLength),
+            None => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+    if len > max {
+        Err(limit)
+    } else {
+        Ok(())
+    }
+}
+
+/// Counts what has been parsed so far, to check it against the limits
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub(crate) struct Tally {
+    limits: ParseLimits,
+    instructions: usize,
+    pages: usize,
+    depth: usize,
+    fonts: BTreeSet<u32>,
+}
+
+#[cfg(feature = "std")]
+impl Tally {
+    pub fn new(limits: ParseLimits) -> Self {
+        Tally {
+            limits,
+            instructions: 0,
+            pages: 0,
+            depth: 0,
+            fonts: BTreeSet::new(),
+        }
+    }
+
+    /// Check the length of a special or preamble comment before it is read
+    pub fn check_payload(&self, input: &[u8]) -> Result<(), Limit> {
+        check_payload(input, &self.limits)
+    }
+
+    /// Count an instruction, or say which limit it goes over
+    pub fn count(&mut self, instruction: &Instruction) -> Result<(), Limit> {
+        self.instructions += 1;
+        if self.instructions > self.limits.max_instructions {
+            return Err(Limit::Instructions);
+        }
+        match instruction {
+            Instruction::Bop(..) => {
+                self.pages += 1;
+                self.depth = 0;
+                if self.pages > self.limits.max_pages {
+                    return Err(Limit::Pages);
+                }
+            }
+            Instruction::Push => {
+                self.depth += 1;
+                if self.depth > self.limits.max_stack_depth {
+                    return Err(Limit::StackDepth);
+                }
+            }
+            Instruction::Pop => self.depth = self.depth.saturating_sub(1),
+            Instruction::FontDef(def) => {
+                self.fonts.insert(def.number);
+                if self.fonts.len() > self.limits.max_fonts {
+                    return Err(Limit::Fonts);
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn payload() {
+        let limits = ParseLimits {
+            max_special_len: 4,
+            max_comment_len: 2,
+            ..ParseLimits::default()
+        };
+        assert_eq!(check_payload(&[239, 4], &limits), Ok(()));
+        assert_eq!(check_payload(&[239, 5], &limits), Err(Limit::SpecialLength));
+        assert_eq!(
+            check_payload(&[242, 0xff, 0xff, 0xff, 0xff], &limits),
+            Err(Limit::SpecialLength)
+        );
+        // the length hasn't all arrived yet
+        assert_eq!(check_payload(&[242, 0xff, 0xff], &limits), Ok(()));
+        let mut pre = vec![247, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 3, 232, 3];
+        assert_eq!(check_payload(&pre, &limits), Err(Limit::CommentLength));
+        pre[14] = 2;
+        assert_eq!(check_payload(&pre, &limits), Ok(()));
+        assert_eq!(check_payload(&[0], &limits), Ok(()));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn tally() {
+        let mut tally = Tally::new(ParseLimits {
+            max_pages: 1,
+            max_stack_depth: 2,
+            max_fonts: 1,
+            ..ParseLimits::default()
+        });
+        let bop = Instruction::Bop([0; 10], -1);
+        assert_eq!(tally.count(&bop), Ok(()));
+        assert_eq!(tally.count(&Instruction::Push), Ok(()));
+        assert_eq!(tally.count(&Instruction::Push), Ok(()));
+        assert_eq!(tally.count(&Instruction::Pop), Ok(()));
+        assert_eq!(tally.count(&Instruction::Push), Ok(()));
+        assert_eq!(tally.count(&Instruction::Push), Err(Limit::StackDepth));
+        let def = |number| {
+            Instruction::FontDef(crate::FontDef {
+                number,
+                checksum: 0,
+                scale_factor: 0,
+                design_size: 0,
+                directory: None,
+                filename: vec![],
+            })
+        };
+        assert_eq!(tally.count(&def(3)), Ok(()));
+        // defining the same font again, as the postamble does, is fine
+        assert_eq!(tally.count(&def(3)), Ok(()));
+        assert_eq!(tally.count(&def(4)), Err(Limit::Fonts));
+        assert_eq!(tally.count(&bop), Err(Limit::Pages));
+
+        let mut tally = Tally::new(ParseLimits {
+            max_instructions: 2,
+            ..ParseLimits::default()
+        });
+        assert_eq!(tally.count(&Instruction::Nop), Ok(()));
+        assert_eq!(tally.count(&Instruction::Nop), Ok(()));
+        assert_eq!(tally.count(&Instruction::Nop), Err(Limit::Instructions));
+    }
+}
@@ -0,0 +1,478 @@
+//! Extraction of hyperlinks
+//!
+//! hyperref marks links in one of two ways, depending on the driver:
+//!
+//!  - with the `hypertex` driver, as `html:<a href="...">` and `html:<a name="...">` specials
+//!    closed by `html:</a>`,
+//!  - with the `dvips` driver, as PostScript specials: `H.S` starts a link, `H.R` ends it, and a
+//!    `pdfmark` with `/ANN` gives its destination, while a `/DEST` pdfmark defines an anchor.
+//!
+//! The area of a link is the union of the characters and rules drawn while it is open, with one
+//! rectangle per line. Since the interpreter only knows the widths of characters, their height is
+//! taken to be three quarters of the font size above the baseline and their depth a quarter
+//! below it.
+//!
+//! A link started while another is open is nested inside it: what is drawn counts towards both,
+//! and the next end closes the inner one. Links still open at the end of a page are closed there.
+
+use crate::{
+    document::Document,
+    geometry::Rect,
+    interpreter::{Device, Error, FontMetrics, PageIndex},
+    special::Special,
+    FontDef,
+};
+
+/// Where a link goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A named anchor in the same document
+    Anchor(String),
+    /// Anything else, usually a web address
+    Uri(String),
+}
+
+/// A link on a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The index of the page in the document
+    pub page: usize,
+    /// The areas that can be clicked, one per line the link is set on
+    ///
+    /// This is empty if nothing was drawn while the link was open.
+    pub rects: Vec<Rect>,
+    pub target: Target,
+}
+
+/// A named destination for links
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anchor {
+    /// The index of the page in the document
+    pub page: usize,
+    pub h: i32,
+    pub v: i32,
+    pub name: String,
+}
+
+/// The links and anchors in a document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Links {
+    pub links: Vec<Link>,
+    pub anchors: Vec<Anchor>,
+}
+
+impl Links {
+    /// The links on page `page`
+    pub fn on_page(&self, page: usize) -> impl Iterator<Item = &Link> {
+        self.links.iter().filter(move |link| link.page == page)
+    }
+
+    /// Find an anchor by name
+    pub fn anchor(&self, name: &str) -> Option<&Anchor> {
+        self.anchors.iter().find(|anchor| anchor.name == name)
+    }
+}
+
+/// Find all the links and anchors in a document
+pub fn extract<M: FontMetrics>(document: &Document, metrics: M) -> Result<Links, Error> {
+    let mut collector = Collector::default();
+    document.run(metrics, &mut collector)?;
+    Ok(collector.links)
+}
+
+/// A link that has been started but not finished
+#[derive(Debug, Default)]
+struct Open {
+    /// The destination, if it was given at the start
+    target: Option<Target>,
+    rects: Vec<Rect>,
+    /// Whether the link area is still being collected
+    collecting: bool,
+}
+
+/// A `Device` that collects links
+#[derive(Debug, Default)]
+struct Collector {
+    links: Links,
+    page: PageIndex,
+    /// The open links, innermost last
+    open: Vec<Open>,
+}
+
+impl Collector {
+    fn start(&mut self, target: Option<Target>) {
+        self.open.push(Open {
+            target,
+            rects: Vec::new(),
+            collecting: true,
+        });
+    }
+
+    /// Finish the innermost open link, with `target` if it didn't have one already
+    fn finish(&mut self, target: Option<Target>) {
+        let open = match self.open.pop() {
+            Some(open) => open,
+            None => return,
+        };
+        let target = match open.target.or(target) {
+            Some(target) => target,
+            None => return,
+        };
+        self.links.links.push(Link {
+            page: self.page.get(),
+            rects: open.rects,
+            target,
+        });
+    }
+
+    fn anchor(&mut self, h: i32, v: i32, name: String) {
+        self.links.anchors.push(Anchor {
+            page: self.page.get(),
+            h,
+            v,
+            name,
+        });
+    }
+
+    /// Add the area of something drawn with its baseline at `v` to every open link
+    fn cover(&mut self, v: i32, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        for open in self.open.iter_mut().filter(|open| open.collecting) {
+            match open.rects.last_mut() {
+                // a new line starts when the drawing doesn't overlap the last rectangle vertically
+                Some(last) if v >= last.top && v <= last.bottom => *last = last.union(&rect),
+                _ => open.rects.push(rect),
+            }
+        }
+    }
+
+    fn html(&mut self, h: i32, v: i32, tag: &str) {
+        let tag = tag.trim();
+        if tag.eq_ignore_ascii_case("</a>") {
+            self.finish(None);
+        } else if let Some(href) = attribute(tag, "href") {
+            let target = match href.strip_prefix('#') {
+                Some(name) => Target::Anchor(name.to_string()),
+                None => Target::Uri(href),
+            };
+            self.start(Some(target));
+        } else if let Some(name) = attribute(tag, "name") {
+            self.anchor(h, v, name);
+        }
+    }
+
+    fn pdfmark(&mut self, h: i32, v: i32, code: &str) {
+        if !code.contains("SDict begin") {
+            return;
+        }
+        if code.contains("H.S") {
+            self.start(None);
+        }
+        if code.contains("H.R") {
+            if let Some(open) = self.open.iter_mut().rev().find(|open| open.collecting) {
+                open.collecting = false;
+            }
+        }
+        if code.contains("/ANN pdfmark") && code.contains("/Link") {
+            let target = ps_string(code, "/Dest")
+                .map(Target::Anchor)
+                .or_else(|| ps_string(code, "/URI").map(Target::Uri));
+            self.finish(target);
+        } else if code.contains("/DEST pdfmark") {
+            if let Some(name) = ps_string(code, "/Dest") {
+                self.anchor(h, v, name);
+            }
+        }
+    }
+}
+
+impl Device for Collector {
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.page.begin();
+    }
+
+    fn end_page(&mut self) {
+        // links can't cross pages
+        while !self.open.is_empty() {
+            self.finish(None);
+        }
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, _: u32, width: i32) {
+        let size = font.scale_factor as i32;
+        let end = h.saturating_add(width);
+        let rect = Rect {
+            left: h.min(end),
+            top: v.saturating_sub(size / 4 * 3),
+            right: h.max(end),
+            bottom: v.saturating_add(size / 4),
+        };
+        self.cover(v, rect);
+    }
+
+    fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+        let rect = Rect {
+            left: h,
+            top: v.saturating_sub(height),
+            right: h.saturating_add(width),
+            bottom: v,
+        };
+        self.cover(v, rect);
+    }
+
+    fn special(&mut self, h: i32, v: i32, payload: &[u8]) {
+        match Special::parse(payload) {
+            Special::Html(tag) => self.html(h, v, &tag),
+            Special::Ps(code) | Special::PsLiteral(code) => self.pdfmark(h, v, &code),
+            _ => (),
+        }
+    }
+}
+
+/// The value of an attribute in an html tag like `<a href="...">`
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    if !lower.starts_with("<a ") {
+        return None;
+    }
+    let start = lower.find(&format!("{}=", name))? + name.len() + 1;
+    let rest = &tag[start..];
+    let value = match rest.chars().next()? {
+        quote @ '"' | quote @ '\'' => rest[1..].split(quote).next()?,
+        _ => rest.split(|c: char| c.is_whitespace() || c == '>').next()?,
+    };
+    Some(value.to_string())
+}
+
+/// The PostScript string following `key` in a pdfmark, like `/URI (http://example.com)`
+fn ps_string(code: &str, key: &str) -> Option<String> {
+    let start = code.find(&format!("{} (", key))? + key.len() + 2;
+    let mut out = String::new();
+    let mut chars = code[start..].chars();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(chars.next()?),
+            '(' => {
+                depth += 1;
+                out.push(c);
+            }
+            ')' if depth == 0 => return Some(out),
+            ')' => {
+                depth -= 1;
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        Instruction,
+    };
+
+    struct Fixed;
+
+    impl FontMetrics for Fixed {
+        fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+            Some(10)
+        }
+    }
+
+    fn document(pages: Vec<Vec<Instruction>>) -> Document {
+        let font = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 100,
+            design_size: 100,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        };
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: pages
+                .into_iter()
+                .map(|instructions| Page {
+                    counters: [0; 10],
+                    instructions,
+                })
+                .collect(),
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font],
+            },
+        }
+    }
+
+    fn special(s: &str) -> Instruction {
+        Instruction::Xxx(Vec::from(s))
+    }
+
+    #[test]
+    fn hypertex() {
+        let links = extract(
+            &document(vec![
+                vec![special("html:<a name=\"sec1\">"), special("html:</a>")],
+                vec![
+                    Instruction::Font(0),
+                    Instruction::Down(1000),
+                    special("html:<a href=\"https://example.com/\">"),
+                    Instruction::Set(b'a'.into()),
+                    Instruction::Set(b'b'.into()),
+                    Instruction::Push,
+                    Instruction::Down(200),
+                    Instruction::Set(b'c'.into()),
+                    Instruction::Pop,
+                    special("html:</a>"),
+                    special("html:<A HREF='#sec1'>"),
+                    special("html:</a>"),
+                ],
+            ]),
+            Fixed,
+        )
+        .unwrap();
+        assert_eq!(
+            links.anchor("sec1"),
+            Some(&Anchor {
+                page: 0,
+                h: 0,
+                v: 0,
+                name: "sec1".into()
+            })
+        );
+        assert_eq!(links.on_page(0).count(), 0);
+        assert_eq!(
+            links.links,
+            vec![
+                Link {
+                    page: 1,
+                    rects: vec![
+                        Rect {
+                            left: 0,
+                            top: 925,
+                            right: 20,
+                            bottom: 1025
+                        },
+                        Rect {
+                            left: 20,
+                            top: 1125,
+                            right: 30,
+                            bottom: 1225
+                        },
+                    ],
+                    target: Target::Uri("https://example.com/".into()),
+                },
+                Link {
+                    page: 1,
+                    rects: vec![],
+                    target: Target::Anchor("sec1".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dvips() {
+        let links = extract(
+            &document(vec![vec![
+                special("ps:SDict begin [/View [/XYZ H.V] /Dest (page.1) cvn /DEST pdfmark end"),
+                Instruction::Font(0),
+                special("ps:SDict begin H.S end"),
+                Instruction::Set(b'a'.into()),
+                special("ps:SDict begin H.R end"),
+                Instruction::Set(b'b'.into()),
+                special(
+                    "ps:SDict begin [/H /I/Border [0 0 1]/Action << /Subtype /URI \
+                     /URI (http://x.org/a\\)b) >> /Subtype /Link H.B /ANN pdfmark end",
+                ),
+                special("ps:SDict begin H.S end"),
+                special("ps:SDict begin H.R end"),
+                special("ps:SDict begin [/Subtype /Link/Dest (page.1) cvn H.B /ANN pdfmark end"),
+            ]]),
+            Fixed,
+        )
+        .unwrap();
+        assert_eq!(links.anchors.len(), 1);
+        assert_eq!(links.anchors[0].name, "page.1");
+        assert_eq!(links.links.len(), 2);
+        assert_eq!(
+            links.links[0].target,
+            Target::Uri("http://x.org/a)b".into())
+        );
+        assert_eq!(
+            links.links[0].rects,
+            vec![Rect {
+                left: 0,
+                top: -75,
+                right: 10,
+                bottom: 25
+            }]
+        );
+        assert_eq!(links.links[1].target, Target::Anchor("page.1".into()));
+    }
+
+    #[test]
+    fn nested() {
+        let links = extract(
+            &document(vec![
+                vec![
+                    Instruction::Font(0),
+                    special("html:<a href=\"#outer\">"),
+                    Instruction::Set(b'a'.into()),
+                    special("html:<a href=\"#inner\">"),
+                    Instruction::Set(b'b'.into()),
+                    special("html:</a>"),
+                    Instruction::Set(b'c'.into()),
+                    special("html:</a>"),
+                    // left open at the end of the page
+                    special("html:<a href=\"#open\">"),
+                    Instruction::Set(b'd'.into()),
+                ],
+                vec![Instruction::Font(0), Instruction::Set(b'e'.into())],
+            ]),
+            Fixed,
+        )
+        .unwrap();
+        let line = |left, right| Rect {
+            left,
+            top: -75,
+            right,
+            bottom: 25,
+        };
+        assert_eq!(
+            links.links,
+            vec![
+                Link {
+                    page: 0,
+                    rects: vec![line(10, 20)],
+                    target: Target::Anchor("inner".into()),
+                },
+                Link {
+                    page: 0,
+                    rects: vec![line(0, 30)],
+                    target: Target::Anchor("outer".into()),
+                },
+                Link {
+                    page: 0,
+                    rects: vec![line(30, 40)],
+                    target: Target::Anchor("open".into()),
+                },
+            ]
+        );
+    }
+}
@@ -1,10 +1,11 @@
 //! Parsers for each instruction type
 
-use crate::{util::parse_223, FontDef, Instruction};
+use crate::{limits, util::parse_223, FontDefRef, Instruction, InstructionRef, ParseLimits};
 
 use nom::{
     bytes::streaming::take,
     combinator::map,
+    multi::length_data,
     number::streaming::{be_i16, be_i24, be_i32, be_i8, be_u16, be_u24, be_u32, be_u8},
     IResult,
 };
@@ -101,63 +102,8 @@ pub fn parse(input: &[u8]) -> IResult<&[u8], Instruction> {
         236 => map(be_u16, |f| Instruction::Font(f.into()))(input),
         237 => map(be_u24, Instruction::Font)(input),
         238 => map(be_u32, Instruction::Font)(input),
-        // Xxx
-        239 => {
-            let (input, length) = be_u8(input)?;
-            let (input, slice) = take(length)(input)?;
-            Ok((input, Instruction::Xxx(slice.to_owned())))
-        }
-        240 => {
-            let (input, length) = be_u16(input)?;
-            let (input, slice) = take(length)(input)?;
-            Ok((input, Instruction::Xxx(slice.to_owned())))
-        }
-        241 => {
-            let (input, length) = be_u24(input)?;
-            let (input, slice) = take(length)(input)?;
-            Ok((input, Instruction::Xxx(slice.to_owned())))
-        }
-        242 => {
-            let (input, length) = be_u32(input)?;
-            let (input, slice) = take(length)(input)?;
-            Ok((input, Instruction::Xxx(slice.to_owned())))
-        }
-        // FontDef
-        243 => {
-            let (input, number) = be_u8(input)?;
-            font_def(input, number.into())
-        }
-        244 => {
-            let (input, number) = be_u16(input)?;
-            font_def(input, number.into())
-        }
-        245 => {
-            let (input, number) = be_u24(input)?;
-            font_def(input, number)
-        }
-        246 => {
-            let (input, number) = be_u32(input)?;
-            font_def(input, number)
-        }
-        // Pre
-        247 => {
-            let (input, format) = be_u8(input)?;
-            let (input, numerator) = be_u32(input)?;
-            let (input, denominator) = be_u32(input)?;
-            let (input, magnification) = be_u32(input)?;
-            let (input, comment_length) = be_u8(input)?;
-            let (input, comment) = take(comment_length)(input)?;
-            Ok((
-                input,
-                Instruction::Pre {
-                    format,
-                    numerator,
-                    denominator,
-                    magnification,
-                    comment: comment.to_owned(),
-                },
-            ))
-        }
+        // Xxx, FontDef and Pre, which have payloads to borrow
+        239..=247 => map(|input| borrowed(input, code), Instruction::from)(input),
         248 => {
             let (input, final_bop_pointer) = be_i32(input)?;
             let (input, numerator) = be_u32(input)?;
@@ -199,7 +145,70 @@ pub fn parse(input: &[u8]) -> IResult<&[u8], Instruction> {
     }
 }
 
-fn font_def(input: &[u8], number: u32) -> IResult<&[u8], Instruction> {
+/// Fail before parsing a special or preamble with a payload longer than the limits
+pub fn check_limits<'a>(input: &'a [u8], limits: &ParseLimits) -> IResult<&'a [u8], ()> {
+    match limits::check_payload(input, limits) {
+        Ok(()) => Ok((input, ())),
+        Err(_) => Err(nom::Err::Failure((input, nom::error::ErrorKind::TooLarge))),
+    }
+}
+
+/// Parse an instruction by reference, without copying its payload
+pub fn parse_ref(input: &[u8]) -> IResult<&[u8], InstructionRef<'_>> {
+    match input.first() {
+        Some(&code) if (239..=247).contains(&code) => borrowed(&input[1..], code),
+        _ => map(parse, InstructionRef::Other)(input),
+    }
+}
+
+/// Parse the instructions that have payloads, after their op code
+fn borrowed(input: &[u8], code: u8) -> IResult<&[u8], InstructionRef<'_>> {
+    match code {
+        // Xxx
+        239 => map(length_data(be_u8), InstructionRef::Xxx)(input),
+        240 => map(length_data(be_u16), InstructionRef::Xxx)(input),
+        241 => map(length_data(be_u24), InstructionRef::Xxx)(input),
+        242 => map(length_data(be_u32), InstructionRef::Xxx)(input),
+        // FontDef
+        243 => {
+            let (input, number) = be_u8(input)?;
+            font_def(input, number.into())
+        }
+        244 => {
+            let (input, number) = be_u16(input)?;
+            font_def(input, number.into())
+        }
+        245 => {
+            let (input, number) = be_u24(input)?;
+            font_def(input, number)
+        }
+        246 => {
+            let (input, number) = be_u32(input)?;
+            font_def(input, number)
+        }
+        // Pre
+        247 => {
+            let (input, format) = be_u8(input)?;
+            let (input, numerator) = be_u32(input)?;
+            let (input, denominator) = be_u32(input)?;
+            let (input, magnification) = be_u32(input)?;
+            let (input, comment) = length_data(be_u8)(input)?;
+            Ok((
+                input,
+                InstructionRef::Pre {
+                    format,
+                    numerator,
+                    denominator,
+                    magnification,
+                    comment,
+                },
+            ))
+        }
+        _ => Err(nom::Err::Error((input, nom::error::ErrorKind::Switch))),
+    }
+}
+
+fn font_def(input: &[u8], number: u32) -> IResult<&[u8], InstructionRef<'_>> {
     let (input, checksum) = be_u32(input)?;
     let (input, scale_factor) = be_u32(input)?;
     let (input, design_size) = be_u32(input)?;
@@ -209,17 +218,17 @@ fn font_def(input: &[u8], number: u32) -> IResult<&[u8], Instruction> {
     let (input, filename) = take(filename_len)(input)?;
     let directory = match directory_len {
         0 => None,
-        _ => Some(directory.to_owned()),
+        _ => Some(directory),
     };
     Ok((
         input,
-        Instruction::FontDef(FontDef {
+        InstructionRef::FontDef(FontDefRef {
             number,
             checksum,
             scale_factor,
             design_size,
             directory,
-            filename: filename.to_owned(),
+            filename,
         }),
     ))
 }
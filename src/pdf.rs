@@ -0,0 +1,392 @@
+//! Convert documents to PDF
+//!
+//! Every page of the document becomes a page of the PDF. Rules are drawn as filled rectangles and
+//! characters are drawn using Type 3 fonts, which are built from the bitmaps in PK fonts. Only the
+//! characters that are actually used are included.
+//!
+//! The size of each page comes from [`Document::page_sizes`], so it follows `papersize=`,
+//! `landscape` and `pdf:pagesize` specials.
+//!
+//! [`Document::page_sizes`]: ../document/struct.Document.html#method.page_sizes
+
+use crate::{
+    document::{Document, PaperSize},
+    interpreter::{Device, Error},
+    pk::{Glyph, PkFont},
+    util::{bp_per_unit, num},
+    FontDef,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+};
+
+/// Render a document to a PDF file
+///
+/// `fonts` are the PK fonts for each font number, made for `resolution` dots per inch.
+pub fn render(
+    document: &Document,
+    fonts: &HashMap<u32, PkFont>,
+    resolution: u32,
+) -> Result<Vec<u8>, Error> {
+    let pre = &document.preamble;
+    let scale = bp_per_unit(pre.numerator, pre.denominator, pre.magnification);
+    let (width, height) = document.paper_size().bp();
+    let sizes: Vec<(f64, f64)> = document.page_sizes().iter().map(PaperSize::bp).collect();
+    let mut pages = Pages {
+        scale,
+        heights: sizes.iter().map(|&(_, height)| height).collect(),
+        pages: Vec::new(),
+        used: BTreeMap::new(),
+    };
+    document.run(fonts, &mut pages)?;
+
+    let mut pdf = Writer::default();
+    // objects 1 and 2 are the catalog and the page tree, fonts and pages come after
+    let mut next = 3;
+    let mut font_ids = BTreeMap::new();
+    for (&number, codes) in &pages.used {
+        font_ids.insert(number, next);
+        next += 1 + codes.len();
+    }
+    let mut font_resources = String::new();
+    for (&number, &id) in &font_ids {
+        let _ = write!(font_resources, "/F{} {} 0 R ", number, id);
+        let glyphs = &fonts[&number].glyphs;
+        write_font(&mut pdf, id, &pages.used[&number], glyphs, resolution);
+    }
+    let mut kids = String::new();
+    for (i, content) in pages.pages.iter().enumerate() {
+        let id = next + 2 * i;
+        let _ = write!(kids, "{} 0 R ", id);
+        pdf.object(
+            id,
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents {} 0 R \
+                 /Resources << /Font << {}>> >> >>",
+                num(sizes[i].0),
+                num(sizes[i].1),
+                id + 1,
+                font_resources
+            )
+            .as_bytes(),
+        );
+        pdf.stream(id + 1, "", content.as_bytes());
+    }
+    pdf.object(1, b"<< /Type /Catalog /Pages 2 0 R >>");
+    pdf.object(
+        2,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} /MediaBox [0 0 {} {}] >>",
+            kids,
+            pages.pages.len(),
+            num(width),
+            num(height)
+        )
+        .as_bytes(),
+    );
+    Ok(pdf.finish())
+}
+
+/// A `Device` that collects the content streams of pages and the characters they use
+struct Pages {
+    /// Big points per dvi unit
+    scale: f64,
+    /// The height of the paper for each page in big points
+    heights: Vec<f64>,
+    pages: Vec<String>,
+    used: BTreeMap<u32, BTreeSet<u8>>,
+}
+
+impl Pages {
+    fn x(&self, h: i32) -> f64 {
+        72.0 + f64::from(h) * self.scale
+    }
+
+    fn y(&self, v: i32) -> f64 {
+        let page = self.pages.len().saturating_sub(1);
+        let height = self.heights.get(page).copied().unwrap_or(792.0);
+        height - 72.0 - f64::from(v) * self.scale
+    }
+
+    fn current(&mut self) -> &mut String {
+        if self.pages.is_empty() {
+            self.pages.push(String::new());
+        }
+        self.pages.last_mut().unwrap()
+    }
+}
+
+impl Device for Pages {
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.pages.push(String::new());
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, ch: u32, _: i32) {
+        // Type 3 fonts can only have 256 characters
+        if ch > 0xff {
+            return;
+        }
+        self.used.entry(font.number).or_default().insert(ch as u8);
+        let (x, y) = (num(self.x(h)), num(self.y(v)));
+        let _ = writeln!(
+            self.current(),
+            "BT /F{} 1 Tf {} {} Td <{:02x}> Tj ET",
+            font.number,
+            x,
+            y,
+            ch
+        );
+    }
+
+    fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+        let (x, y) = (num(self.x(h)), num(self.y(v)));
+        let width = num(f64::from(width) * self.scale);
+        let height = num(f64::from(height) * self.scale);
+        let _ = writeln!(self.current(), "{} {} {} {} re f", x, y, width, height);
+    }
+}
+
+/// Write a Type 3 font as object `id`, followed by its glyphs
+fn write_font(
+    pdf: &mut Writer,
+    id: usize,
+    codes: &BTreeSet<u8>,
+    glyphs: &HashMap<u32, Glyph>,
+    resolution: u32,
+) {
+    let first = *codes.iter().next().unwrap_or(&0);
+    let last = *codes.iter().next_back().unwrap_or(&0);
+    let mut widths = vec![0.0; usize::from(last - first) + 1];
+    let mut char_procs = String::new();
+    let mut differences = String::new();
+    let mut bbox = [0i64; 4];
+    for (i, &code) in codes.iter().enumerate() {
+        let glyph = &glyphs[&u32::from(code)];
+        let proc_id = id + 1 + i;
+        widths[usize::from(code - first)] = f64::from(glyph.dx) / 65536.0;
+        let _ = write!(char_procs, "/g{} {} 0 R ", code, proc_id);
+        let _ = write!(differences, "{} /g{} ", code, code);
+        let (llx, lly, urx, ury) = glyph.bounding_box();
+        bbox = [
+            bbox[0].min(llx),
+            bbox[1].min(lly),
+            bbox[2].max(urx),
+            bbox[3].max(ury),
+        ];
+        pdf.stream(proc_id, "", &char_proc(glyph));
+    }
+    let widths: Vec<String> = widths.into_iter().map(num).collect();
+    let pixel = num(72.0 / f64::from(resolution));
+    pdf.object(
+        id,
+        format!(
+            "<< /Type /Font /Subtype /Type3 /FontBBox [{} {} {} {}] \
+             /FontMatrix [{p} 0 0 {p} 0 0] /CharProcs << {}>> \
+             /Encoding << /Type /Encoding /Differences [{}] >> \
+             /FirstChar {} /LastChar {} /Widths [{}] /Resources << >> >>",
+            bbox[0],
+            bbox[1],
+            bbox[2],
+            bbox[3],
+            char_procs,
+            differences,
+            first,
+            last,
+            widths.join(" "),
+            p = pixel,
+        )
+        .as_bytes(),
+    );
+}
+
+/// The content stream that draws a glyph, in pixel units
+fn char_proc(glyph: &Glyph) -> Vec<u8> {
+    let (llx, lly, urx, ury) = glyph.bounding_box();
+    let mut out = format!(
+        "{} 0 {} {} {} {} d1\n",
+        num(f64::from(glyph.dx) / 65536.0),
+        llx,
+        lly,
+        urx,
+        ury
+    );
+    if glyph.width > 0 && glyph.height > 0 {
+        let _ = write!(
+            out,
+            "q {w} 0 0 {h} {} {} cm\nBI /IM true /W {w} /H {h} /BPC 1 /D [1 0] /F /AHx ID\n",
+            llx,
+            lly,
+            w = glyph.width,
+            h = glyph.height,
+        );
+        for byte in glyph.packed_rows() {
+            let _ = write!(out, "{:02x}", byte);
+        }
+        out.push_str(">\nEI\nQ\n");
+    }
+    out.into_bytes()
+}
+
+/// Builds a PDF file out of numbered objects
+#[derive(Default)]
+struct Writer {
+    objects: BTreeMap<usize, Vec<u8>>,
+}
+
+impl Writer {
+    fn object(&mut self, id: usize, body: &[u8]) {
+        let mut object = format!("{} 0 obj\n", id).into_bytes();
+        object.extend_from_slice(body);
+        object.extend_from_slice(b"\nendobj\n");
+        self.objects.insert(id, object);
+    }
+
+    fn stream(&mut self, id: usize, dict: &str, data: &[u8]) {
+        let mut body = format!("<< {}/Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        self.object(id, &body);
+    }
+
+    /// Write out the objects with a cross-reference table
+    ///
+    /// Objects must be numbered consecutively from 1.
+    fn finish(self) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (_, object) in self.objects {
+            offsets.push(out.len());
+            out.extend_from_slice(&object);
+        }
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+        for offset in offsets.iter() {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            offsets.len() + 1,
+            xref
+        );
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        Instruction,
+    };
+
+    fn document(specials: Vec<Instruction>) -> Document {
+        let font = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("dots"),
+        };
+        let mut instructions = specials;
+        instructions.extend(vec![
+            Instruction::Font(0),
+            Instruction::Set(b'.'.into()),
+            Instruction::SetRule(0x10000, 0x10000),
+        ]);
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: vec![Page {
+                counters: [1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                instructions,
+            }],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font],
+            },
+        }
+    }
+
+    fn fonts() -> HashMap<u32, PkFont> {
+        let mut glyphs = HashMap::new();
+        glyphs.insert(
+            u32::from(b'.'),
+            Glyph {
+                tfm_width: 0x0004_0000,
+                dx: 10 << 16,
+                dy: 0,
+                width: 9,
+                height: 2,
+                h_offset: 0,
+                v_offset: 2,
+                bitmap: vec![true; 18],
+            },
+        );
+        let mut fonts = HashMap::new();
+        fonts.insert(
+            0,
+            PkFont {
+                comment: Vec::new(),
+                design_size: 10 << 20,
+                checksum: 0,
+                hppp: 0,
+                vppp: 0,
+                glyphs,
+            },
+        );
+        fonts
+    }
+
+    #[test]
+    fn structure() {
+        let pdf = render(&document(Vec::new()), &fonts(), 72).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/MediaBox [0 0 612 792]"));
+        assert!(text.contains("/Subtype /Type3"));
+        assert!(text.contains("BT /F0 1 Tf 72 720 Td <2e> Tj ET"));
+        assert!(text.contains("74.491 720 0.996 0.996 re f"));
+        // a 9 pixel wide row takes two bytes
+        assert!(text.contains("ff80ff80>"));
+        // check the cross reference table points at the objects
+        let startxref = text.rfind("startxref\n").unwrap();
+        let xref: usize = text[startxref + 10..]
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let table = String::from_utf8_lossy(&pdf[xref..]);
+        assert!(table.starts_with("xref\n0 7\n"));
+        for (i, line) in table.lines().skip(3).take(6).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj", i + 1).as_bytes()));
+        }
+    }
+
+    #[test]
+    fn paper_size() {
+        let special = Instruction::Xxx(Vec::from("papersize=210mm,297mm"));
+        let pdf = render(&document(vec![special]), &fonts(), 72).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("/MediaBox [0 0 595.276 841.89]"));
+
+        let special = Instruction::Xxx(Vec::from("pdf:pagesize width 300bp height 200bp"));
+        let pdf = render(&document(vec![special]), &fonts(), 72).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("/Parent 2 0 R /MediaBox [0 0 300 200]"));
+        assert!(text.contains("BT /F0 1 Tf 72 128 Td <2e> Tj ET"));
+    }
+}
@@ -0,0 +1,554 @@
+//! Parser for packed bitmap (PK) fonts
+//!
+//! PK files hold the glyphs of a font rendered at one particular resolution, run-length encoded.
+//! They are what `mktexpk` produces from METAFONT sources, and are the usual way to get at the
+//! shapes of the characters that a dvi file refers to.
+
+use crate::{interpreter::FontMetrics, util::scale_fix_word, FontDef};
+use nom::{
+    bytes::streaming::take,
+    number::streaming::{be_i16, be_i32, be_i8, be_u16, be_u24, be_u32, be_u8},
+    IResult,
+};
+use std::collections::HashMap;
+
+/// A font read from a PK file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkFont {
+    /// The comment from the preamble
+    pub comment: Vec<u8>,
+    /// The design size, as a fix_word in points
+    pub design_size: u32,
+    /// The checksum, which should match the TFM file and the dvi font definition
+    pub checksum: u32,
+    /// Horizontal pixels per point, scaled by 2^16
+    pub hppp: u32,
+    /// Vertical pixels per point, scaled by 2^16
+    pub vppp: u32,
+    /// The glyphs, by character code
+    pub glyphs: HashMap<u32, Glyph>,
+}
+
+/// A single character bitmap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glyph {
+    /// The width from the TFM file, as a fix_word relative to the design size
+    pub tfm_width: i32,
+    /// The horizontal escapement in pixels, scaled by 2^16
+    pub dx: i32,
+    /// The vertical escapement in pixels, scaled by 2^16
+    pub dy: i32,
+    /// The width of the bitmap in pixels
+    pub width: u32,
+    /// The height of the bitmap in pixels
+    pub height: u32,
+    /// The distance in pixels from the left of the bitmap to the reference point
+    pub h_offset: i32,
+    /// The distance in pixels from the top of the bitmap to the reference point
+    pub v_offset: i32,
+    /// The pixels, row by row from the top, `true` where the glyph is black
+    pub bitmap: Vec<bool>,
+}
+
+impl Glyph {
+    /// Whether the pixel in column `x` and row `y` is black
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        x < self.width
+            && y < self.height
+            && self.bitmap[y as usize * self.width as usize + x as usize]
+    }
+
+    /// The bounding box of the bitmap in pixels, as `(left, bottom, right, top)`
+    ///
+    /// The reference point is at the origin and y increases upwards, as in PostScript and PDF.
+    pub fn bounding_box(&self) -> (i64, i64, i64, i64) {
+        let left = -i64::from(self.h_offset);
+        let top = i64::from(self.v_offset);
+        (
+            left,
+            top - i64::from(self.height),
+            left + i64::from(self.width),
+            top,
+        )
+    }
+
+    /// The bitmap packed 8 pixels to a byte, with each row padded to a whole byte
+    ///
+    /// This is the layout used by PostScript's `imagemask` and PDF image masks.
+    pub fn packed_rows(&self) -> Vec<u8> {
+        let row_len = (self.width as usize).div_ceil(8);
+        let mut out = vec![0; row_len * self.height as usize];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixel(x, y) {
+                    out[y as usize * row_len + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        out
+    }
+}
+
+impl PkFont {
+    /// Parse a PK file
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, _) = nom::bytes::streaming::tag(&[247, 89][..])(input)?;
+        let (input, comment_len) = be_u8(input)?;
+        let (input, comment) = take(comment_len)(input)?;
+        let (input, design_size) = be_u32(input)?;
+        let (input, checksum) = be_u32(input)?;
+        let (input, hppp) = be_u32(input)?;
+        let (mut input, vppp) = be_u32(input)?;
+        let mut glyphs = HashMap::new();
+        loop {
+            let (rest, flag) = be_u8(input)?;
+            input = match flag {
+                0..=239 => {
+                    let (rest, (code, glyph)) = glyph(rest, flag)?;
+                    glyphs.insert(code, glyph);
+                    rest
+                }
+                // specials
+                240 => {
+                    let (rest, len) = be_u8(rest)?;
+                    take(len)(rest)?.0
+                }
+                241 => {
+                    let (rest, len) = be_u16(rest)?;
+                    take(len)(rest)?.0
+                }
+                242 => {
+                    let (rest, len) = be_u24(rest)?;
+                    take(len)(rest)?.0
+                }
+                243 => {
+                    let (rest, len) = be_u32(rest)?;
+                    take(len)(rest)?.0
+                }
+                244 => be_u32(rest)?.0,
+                // post
+                245 => {
+                    input = rest;
+                    break;
+                }
+                246 => rest,
+                _ => return Err(nom::Err::Failure((input, nom::error::ErrorKind::Tag))),
+            };
+        }
+        Ok((
+            input,
+            PkFont {
+                comment: comment.to_owned(),
+                design_size,
+                checksum,
+                hppp,
+                vppp,
+                glyphs,
+            },
+        ))
+    }
+}
+
+impl FontMetrics for HashMap<u32, PkFont> {
+    fn char_width(&self, font: &FontDef, ch: u32) -> Option<i32> {
+        let glyph = self.get(&font.number)?.glyphs.get(&ch)?;
+        scale_fix_word(glyph.tfm_width, font.scale_factor)
+    }
+}
+
+/// Parse a character packet, given its flag byte
+fn glyph(input: &[u8], flag: u8) -> IResult<&[u8], (u32, Glyph)> {
+    let dyn_f = flag >> 4;
+    let turn_on = flag & 8 != 0;
+    let (input, header, raster_len) = match flag & 7 {
+        // short form
+        0..=3 => {
+            let (input, pl) = be_u8(input)?;
+            let pl = (u32::from(flag & 3) << 8) + u32::from(pl);
+            let (input, code) = be_u8(input)?;
+            let (input, tfm_width) = be_u24(input)?;
+            let (input, dm) = be_u8(input)?;
+            let (input, width) = be_u8(input)?;
+            let (input, height) = be_u8(input)?;
+            let (input, h_offset) = be_i8(input)?;
+            let (input, v_offset) = be_i8(input)?;
+            let header = Header {
+                code: code.into(),
+                tfm_width: tfm_width as i32,
+                dx: i32::from(dm) << 16,
+                dy: 0,
+                width: width.into(),
+                height: height.into(),
+                h_offset: h_offset.into(),
+                v_offset: v_offset.into(),
+            };
+            (input, header, pl.checked_sub(8))
+        }
+        // extended short form
+        4..=6 => {
+            let (input, pl) = be_u16(input)?;
+            let pl = (u32::from(flag & 3) << 16) + u32::from(pl);
+            let (input, code) = be_u8(input)?;
+            let (input, tfm_width) = be_u24(input)?;
+            let (input, dm) = be_u16(input)?;
+            let (input, width) = be_u16(input)?;
+            let (input, height) = be_u16(input)?;
+            let (input, h_offset) = be_i16(input)?;
+            let (input, v_offset) = be_i16(input)?;
+            let header = Header {
+                code: code.into(),
+                tfm_width: tfm_width as i32,
+                dx: i32::from(dm) << 16,
+                dy: 0,
+                width: width.into(),
+                height: height.into(),
+                h_offset: h_offset.into(),
+                v_offset: v_offset.into(),
+            };
+            (input, header, pl.checked_sub(13))
+        }
+        // long form
+        _ => {
+            let (input, pl) = be_u32(input)?;
+            let (input, code) = be_u32(input)?;
+            let (input, tfm_width) = be_i32(input)?;
+            let (input, dx) = be_i32(input)?;
+            let (input, dy) = be_i32(input)?;
+            let (input, width) = be_u32(input)?;
+            let (input, height) = be_u32(input)?;
+            let (input, h_offset) = be_i32(input)?;
+            let (input, v_offset) = be_i32(input)?;
+            let header = Header {
+                code,
+                tfm_width,
+                dx,
+                dy,
+                width,
+                height,
+                h_offset,
+                v_offset,
+            };
+            (input, header, pl.checked_sub(28))
+        }
+    };
+    let fail = |input| nom::Err::Failure((input, nom::error::ErrorKind::Verify));
+    let raster_len = raster_len.ok_or_else(|| fail(input))?;
+    let (rest, raster) = take(raster_len)(input)?;
+    let bitmap = if dyn_f == 14 {
+        unpack_bitmap(raster, header.width, header.height)
+    } else {
+        unpack_runs(raster, dyn_f, turn_on, header.width, header.height)
+    }
+    .ok_or_else(|| fail(input))?;
+    Ok((
+        rest,
+        (
+            header.code,
+            Glyph {
+                tfm_width: header.tfm_width,
+                dx: header.dx,
+                dy: header.dy,
+                width: header.width,
+                height: header.height,
+                h_offset: header.h_offset,
+                v_offset: header.v_offset,
+                bitmap,
+            },
+        ),
+    ))
+}
+
+/// The fields of a character packet before the raster
+struct Header {
+    code: u32,
+    tfm_width: i32,
+    dx: i32,
+    dy: i32,
+    width: u32,
+    height: u32,
+    h_offset: i32,
+    v_offset: i32,
+}
+
+/// Unpack a raster stored as a plain bitmap, with rows running on from each other
+fn unpack_bitmap(raster: &[u8], width: u32, height: u32) -> Option<Vec<bool>> {
+    let len = (width as usize).checked_mul(height as usize)?;
+    if raster.len() * 8 < len {
+        return None;
+    }
+    Some(
+        (0..len)
+            .map(|i| raster[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect(),
+    )
+}
+
+/// Reads nybbles from a raster
+struct Nybbles<'a> {
+    raster: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Nybbles<'a> {
+    fn next(&mut self) -> Option<u32> {
+        let byte = *self.raster.get(self.pos / 2)?;
+        let nybble = if self.pos.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0xf
+        };
+        self.pos += 1;
+        Some(nybble.into())
+    }
+
+    /// Read a packed number, returning the run count and any repeat count
+    ///
+    /// A repeat count can't be followed by another one, as in `pktype`.
+    fn packed_num(&mut self, dyn_f: u32, repeat: &mut u32) -> Option<u32> {
+        let i = match self.next()? {
+            14 => {
+                let first = self.next()?;
+                *repeat = self.run_count(dyn_f, first)?;
+                self.next()?
+            }
+            15 => {
+                *repeat = 1;
+                self.next()?
+            }
+            i => i,
+        };
+        self.run_count(dyn_f, i)
+    }
+
+    /// Read the rest of a packed number that starts with nybble `i`, which isn't a repeat count
+    fn run_count(&mut self, dyn_f: u32, i: u32) -> Option<u32> {
+        if i == 0 {
+            let mut j = 0;
+            let mut i = 0;
+            while i == 0 {
+                i = self.next()?;
+                j += 1;
+                if j > 8 {
+                    return None;
+                }
+            }
+            for _ in 0..j {
+                i = i.checked_mul(16)?.checked_add(self.next()?)?;
+            }
+            (i + (13 - dyn_f) * 16 + dyn_f).checked_sub(15)
+        } else if i <= dyn_f {
+            Some(i)
+        } else if i < 14 {
+            Some((i - dyn_f - 1) * 16 + self.next()? + dyn_f + 1)
+        } else {
+            None
+        }
+    }
+}
+
+/// A piece of a run-length encoded raster
+enum Run {
+    /// This many pixels of one colour
+    Pixels(bool, usize),
+    /// Repeat the row that has just been finished this many times
+    Rows(usize),
+}
+
+/// Walk the runs of a raster until they cover `len` pixels, or fail if the raster ends first
+fn runs(
+    raster: &[u8],
+    dyn_f: u8,
+    mut black: bool,
+    width: usize,
+    len: usize,
+    mut emit: impl FnMut(Run),
+) -> Option<()> {
+    let mut nybbles = Nybbles { raster, pos: 0 };
+    let mut repeat = 0;
+    let (mut pos, mut column) = (0, 0);
+    while pos < len {
+        let mut count = nybbles.packed_num(dyn_f.into(), &mut repeat)? as usize;
+        while count > 0 && pos < len {
+            let n = count.min(width - column).min(len - pos);
+            emit(Run::Pixels(black, n));
+            column += n;
+            pos += n;
+            count -= n;
+            if column == width {
+                let rows = (repeat as usize).min((len - pos) / width);
+                emit(Run::Rows(rows));
+                pos += rows * width;
+                repeat = 0;
+                column = 0;
+            }
+        }
+        black = !black;
+    }
+    Some(())
+}
+
+/// Unpack a run-length encoded raster
+fn unpack_runs(
+    raster: &[u8],
+    dyn_f: u8,
+    black: bool,
+    width: u32,
+    height: u32,
+) -> Option<Vec<bool>> {
+    let (width, height) = (width as usize, height as usize);
+    let len = width.checked_mul(height)?;
+    // the size comes from the file, so check the raster covers it before allocating anything
+    runs(raster, dyn_f, black, width, len, |_| ())?;
+    let mut bitmap = Vec::with_capacity(len);
+    runs(raster, dyn_f, black, width, len, |run| match run {
+        Run::Pixels(black, n) => bitmap.extend((0..n).map(|_| black)),
+        Run::Rows(rows) => {
+            let start = bitmap.len() - width;
+            for _ in 0..rows {
+                bitmap.extend_from_within(start..start + width);
+            }
+        }
+    })?;
+    Some(bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font(packets: &[u8]) -> Vec<u8> {
+        let mut out = vec![247, 89, 2, b'h', b'i'];
+        out.extend_from_slice(&[0, 0xa0, 0, 0]);
+        out.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        out.extend_from_slice(&[0, 0x08, 0x4b, 0x8c]);
+        out.extend_from_slice(&[0, 0x08, 0x4b, 0x8c]);
+        out.extend_from_slice(packets);
+        out.extend_from_slice(&[245, 246, 246]);
+        out
+    }
+
+    #[test]
+    fn bitmap_glyph() {
+        // dyn_f = 14, short form, a 3x3 plus sign
+        let packets = [
+            0xe0,
+            10,
+            b'+',
+            0x08,
+            0,
+            0,
+            4,
+            3,
+            3,
+            0xff,
+            2,
+            0b0101_1101,
+            0b0000_0000,
+        ];
+        let (_, font) = PkFont::parse(&font(&packets)).unwrap();
+        assert_eq!(font.comment, b"hi");
+        assert_eq!(font.checksum, 0xdeadbeef);
+        let glyph = &font.glyphs[&u32::from(b'+')];
+        assert_eq!((glyph.width, glyph.height), (3, 3));
+        assert_eq!((glyph.h_offset, glyph.v_offset), (-1, 2));
+        assert_eq!(glyph.dx, 4 << 16);
+        assert!(glyph.pixel(1, 0) && !glyph.pixel(0, 0) && glyph.pixel(0, 1));
+        assert!(!glyph.pixel(3, 1));
+        assert_eq!(glyph.bounding_box(), (1, -1, 4, 2));
+        assert_eq!(glyph.packed_rows(), vec![0x40, 0xe0, 0x40]);
+    }
+
+    #[test]
+    fn run_length_glyph() {
+        // dyn_f = 1, a 4x4 square outline: runs of black 5, white 2, then black 5 with the
+        // second row repeated once
+        let packets = [
+            0x18, 12, b'o', 0x08, 0, 0, 4, 4, 4, 0, 4, 0x23, 0x20, 0xf2, 0x30,
+        ];
+        let (_, font) = PkFont::parse(&font(&packets)).unwrap();
+        let glyph = &font.glyphs[&u32::from(b'o')];
+        let expected: Vec<bool> = "*****..**..*****".chars().map(|c| c == '*').collect();
+        assert_eq!(glyph.bitmap, expected);
+    }
+
+    #[test]
+    fn oversized_glyph() {
+        // a long form header claiming a 2^16 by 2^16 glyph, with one run of 2 pixels
+        let mut packets = vec![0x17, 0, 0, 0, 29, 0, 0, 0, b'x'];
+        packets.extend_from_slice(&[0; 12]);
+        packets.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 0]);
+        packets.extend_from_slice(&[0; 8]);
+        packets.push(0x20);
+        assert!(PkFont::parse(&font(&packets)).is_err());
+    }
+
+    #[test]
+    fn large_run() {
+        // dyn_f = 1: one zero nybble means one more nybble follows the first non-zero one
+        let mut repeat = 0;
+        let mut nybbles = Nybbles {
+            raster: &[0x01, 0xc0],
+            pos: 0,
+        };
+        assert_eq!(
+            nybbles.packed_num(1, &mut repeat),
+            Some(0x1c - 15 + 12 * 16 + 1)
+        );
+        assert_eq!(repeat, 0);
+    }
+
+    #[test]
+    fn repeated_repeat_counts() {
+        // a repeat count of 2, then a run of 3
+        let mut repeat = 0;
+        let mut nybbles = Nybbles {
+            raster: &[0xe2, 0x30],
+            pos: 0,
+        };
+        assert_eq!(nybbles.packed_num(13, &mut repeat), Some(3));
+        assert_eq!(repeat, 2);
+        // a repeat count straight after another is rejected, however long the raster
+        for &byte in &[0xee, 0xff, 0xef] {
+            let raster = vec![byte; 1 << 20];
+            assert_eq!(unpack_runs(&raster, 1, true, 1 << 10, 1 << 10), None);
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let packets = [
+            0xe0,
+            10,
+            b'+',
+            0x08,
+            0,
+            0,
+            4,
+            3,
+            3,
+            0xff,
+            2,
+            0b0101_1101,
+            0b0000_0000,
+        ];
+        let (_, pk) = PkFont::parse(&font(&packets)).unwrap();
+        let mut fonts = HashMap::new();
+        fonts.insert(3, pk);
+        let def = FontDef {
+            number: 3,
+            checksum: 0xdeadbeef,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("plus"),
+        };
+        // half of the design size
+        assert_eq!(fonts.char_width(&def, b'+'.into()), Some(5 << 16));
+        assert_eq!(fonts.char_width(&def, b'-'.into()), None);
+        let huge = FontDef {
+            scale_factor: 1 << 27,
+            ..def
+        };
+        assert_eq!(fonts.char_width(&huge, b'+'.into()), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Convert documents to PostScript
+//!
+//! The output follows the Document Structuring Conventions, with a `%%Page` comment for every page
+//! labelled from its `Bop` counters, so it can be handled by print spoolers. Characters are drawn
+//! with Type 3 fonts built from the bitmaps in PK fonts, and rules with `rectfill`.
+//!
+//! PostScript specials are passed through in the same way as dvips:
+//!
+//!  - `" code` runs `code` with the origin moved to the current point,
+//!  - `ps: code` runs `code` with the current point set,
+//!  - `ps:: code` runs `code` untouched,
+//!  - `header=file` asks for `file` to be included in the document setup, using an
+//!    `%%IncludeResource` comment for the spooler to resolve.
+
+use crate::{
+    document::Document,
+    interpreter::{Device, Error},
+    pk::{Glyph, PkFont},
+    special::Special,
+    util::{bp_per_unit, num},
+    FontDef,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+};
+
+/// Procedures used by the page descriptions
+const PROLOGUE: &str = "\
+/M { moveto } bind def
+/S { show } bind def
+/R { rectfill } bind def
+/BuildGlyph { exch /CharProcs get exch 2 copy known not { pop /.notdef } if get exec } bind def
+/BuildChar { 1 index /Encoding get exch get 1 index /BuildGlyph get exec } bind def
+";
+
+/// Render a document to a PostScript file
+///
+/// `fonts` are the PK fonts for each font number, made for `resolution` dots per inch.
+pub fn render(
+    document: &Document,
+    fonts: &HashMap<u32, PkFont>,
+    resolution: u32,
+) -> Result<Vec<u8>, Error> {
+    let pre = &document.preamble;
+    let (width, height) = document.paper_size().bp();
+    let mut pages = Pages {
+        scale: bp_per_unit(pre.numerator, pre.denominator, pre.magnification),
+        height,
+        pages: Vec::new(),
+        headers: Vec::new(),
+        used: BTreeMap::new(),
+        font: None,
+    };
+    document.run(fonts, &mut pages)?;
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "%!PS-Adobe-3.0\n%%Creator: dvi-rs\n%%Pages: {}\n%%BoundingBox: 0 0 {} {}\n\
+         %%EndComments\n%%BeginProlog\n{}",
+        pages.pages.len(),
+        bp_ceil(width),
+        bp_ceil(height),
+        PROLOGUE,
+    );
+    for (&number, codes) in &pages.used {
+        write_font(&mut out, number, codes, &fonts[&number].glyphs, resolution);
+    }
+    out.push_str("%%EndProlog\n%%BeginSetup\n");
+    for header in &pages.headers {
+        let _ = writeln!(out, "%%IncludeResource: file {}", header);
+    }
+    out.push_str("%%EndSetup\n");
+    for (i, (label, content)) in pages.pages.iter().enumerate() {
+        let _ = write!(
+            out,
+            "%%Page: {} {}\nsave\n{}restore showpage\n",
+            label,
+            i + 1,
+            content
+        );
+    }
+    out.push_str("%%Trailer\n%%EOF\n");
+    Ok(out.into_bytes())
+}
+
+/// Round up to whole big points, so the bounding box doesn't clip the page
+///
+/// Converting from dvi units leaves sizes like US letter a tiny bit over a whole number, which
+/// shouldn't push them up another point.
+fn bp_ceil(bp: f64) -> f64 {
+    (bp - 1e-3).ceil()
+}
+
+/// The label dvips gives a page: the non-zero counters, separated by dots
+fn label(counters: &[i32; 10]) -> String {
+    let used = counters
+        .iter()
+        .rposition(|&c| c != 0)
+        .map_or(1, |last| last + 1);
+    let parts: Vec<String> = counters[..used].iter().map(|c| c.to_string()).collect();
+    parts.join(".")
+}
+
+/// A `Device` that collects page descriptions and the characters they use
+struct Pages {
+    /// Big points per dvi unit
+    scale: f64,
+    /// The height of the paper in big points
+    height: f64,
+    /// The label and content of each page
+    pages: Vec<(String, String)>,
+    headers: Vec<String>,
+    used: BTreeMap<u32, BTreeSet<u8>>,
+    /// The font currently selected in the output
+    font: Option<u32>,
+}
+
+impl Pages {
+    fn x(&self, h: i32) -> f64 {
+        72.0 + f64::from(h) * self.scale
+    }
+
+    fn y(&self, v: i32) -> f64 {
+        self.height - 72.0 - f64::from(v) * self.scale
+    }
+
+    fn current(&mut self) -> &mut String {
+        if self.pages.is_empty() {
+            self.pages.push((String::from("0"), String::new()));
+        }
+        &mut self.pages.last_mut().unwrap().1
+    }
+}
+
+impl Device for Pages {
+    fn begin_page(&mut self, counters: &[i32; 10]) {
+        self.pages.push((label(counters), String::new()));
+        self.font = None;
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, ch: u32, _: i32) {
+        // Type 3 fonts can only have 256 characters
+        if ch > 0xff {
+            return;
+        }
+        self.used.entry(font.number).or_default().insert(ch as u8);
+        let (x, y) = (num(self.x(h)), num(self.y(v)));
+        if self.font != Some(font.number) {
+            self.font = Some(font.number);
+            let _ = writeln!(self.current(), "/F{} findfont setfont", font.number);
+        }
+        let _ = writeln!(self.current(), "{} {} M <{:02x}> S", x, y, ch);
+    }
+
+    fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+        let (x, y) = (num(self.x(h)), num(self.y(v)));
+        let width = num(f64::from(width) * self.scale);
+        let height = num(f64::from(height) * self.scale);
+        let _ = writeln!(self.current(), "{} {} {} {} R", x, y, width, height);
+    }
+
+    fn special(&mut self, h: i32, v: i32, payload: &[u8]) {
+        let (x, y) = (num(self.x(h)), num(self.y(v)));
+        match Special::parse(payload) {
+            Special::PsQuote(code) => {
+                let _ = writeln!(
+                    self.current(),
+                    "gsave {} {} translate {} grestore",
+                    x,
+                    y,
+                    code
+                );
+            }
+            Special::PsLiteral(code) => {
+                let _ = writeln!(self.current(), "{}", code);
+            }
+            Special::Ps(code) => {
+                let _ = writeln!(self.current(), "{} {} M {}", x, y, code);
+            }
+            Special::Header(file) if !self.headers.contains(&file) => self.headers.push(file),
+            _ => (),
+        }
+    }
+}
+
+/// Define font `number` as a Type 3 font
+fn write_font(
+    out: &mut String,
+    number: u32,
+    codes: &BTreeSet<u8>,
+    glyphs: &HashMap<u32, Glyph>,
+    resolution: u32,
+) {
+    let mut bbox = [0i64; 4];
+    for &code in codes {
+        let (llx, lly, urx, ury) = glyphs[&u32::from(code)].bounding_box();
+        bbox = [
+            bbox[0].min(llx),
+            bbox[1].min(lly),
+            bbox[2].max(urx),
+            bbox[3].max(ury),
+        ];
+    }
+    let pixel = num(72.0 / f64::from(resolution));
+    let _ = write!(
+        out,
+        "/F{} 9 dict dup begin\n/FontType 3 def\n/FontMatrix [{p} 0 0 {p} 0 0] def\n\
+         /FontBBox [{} {} {} {}] def\n\
+         /Encoding 256 array def 0 1 255 {{ Encoding exch /.notdef put }} for\n",
+        number,
+        bbox[0],
+        bbox[1],
+        bbox[2],
+        bbox[3],
+        p = pixel,
+    );
+    for &code in codes {
+        let _ = writeln!(out, "Encoding {} /g{} put", code, code);
+    }
+    let _ = writeln!(
+        out,
+        "/CharProcs {} dict def CharProcs begin\n/.notdef {{ 0 0 setcharwidth }} def",
+        codes.len() + 1
+    );
+    for &code in codes {
+        let glyph = &glyphs[&u32::from(code)];
+        let (llx, lly, urx, ury) = glyph.bounding_box();
+        let _ = write!(
+            out,
+            "/g{} {{ {} 0 {} {} {} {} setcachedevice",
+            code,
+            num(f64::from(glyph.dx) / 65536.0),
+            llx,
+            lly,
+            urx,
+            ury
+        );
+        if glyph.width > 0 && glyph.height > 0 {
+            let _ = write!(
+                out,
+                " {} {} translate {w} {h} scale {w} {h} true [{w} 0 0 -{h} 0 {h}] {{<",
+                llx,
+                lly,
+                w = glyph.width,
+                h = glyph.height,
+            );
+            for byte in glyph.packed_rows() {
+                let _ = write!(out, "{:02x}", byte);
+            }
+            out.push_str(">} imagemask");
+        }
+        out.push_str(" } def\n");
+    }
+    out.push_str("end\n/BuildGlyph load /BuildGlyph exch def\n");
+    out.push_str("/BuildChar load /BuildChar exch def\nend definefont pop\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        Instruction,
+    };
+
+    fn document() -> Document {
+        let font = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("dots"),
+        };
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: vec![Page {
+                counters: [3, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+                instructions: vec![
+                    Instruction::Xxx(Vec::from("header=special.pro")),
+                    Instruction::Xxx(Vec::from("\" newpath 0 0 moveto")),
+                    Instruction::Xxx(Vec::from("ps:: 1 setgray")),
+                    Instruction::Font(0),
+                    Instruction::Set(b'.'.into()),
+                    Instruction::SetRule(0x10000, 0x10000),
+                ],
+            }],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font],
+            },
+        }
+    }
+
+    fn fonts() -> HashMap<u32, PkFont> {
+        let mut glyphs = HashMap::new();
+        glyphs.insert(
+            u32::from(b'.'),
+            Glyph {
+                tfm_width: 0x0004_0000,
+                dx: 10 << 16,
+                dy: 0,
+                width: 9,
+                height: 2,
+                h_offset: 0,
+                v_offset: 2,
+                bitmap: vec![true; 18],
+            },
+        );
+        let mut fonts = HashMap::new();
+        fonts.insert(
+            0,
+            PkFont {
+                comment: Vec::new(),
+                design_size: 10 << 20,
+                checksum: 0,
+                hppp: 0,
+                vppp: 0,
+                glyphs,
+            },
+        );
+        fonts
+    }
+
+    #[test]
+    fn structure() {
+        let ps = render(&document(), &fonts(), 72).unwrap();
+        let ps = String::from_utf8(ps).unwrap();
+        assert!(ps.starts_with("%!PS-Adobe-3.0\n"));
+        assert!(ps.contains("%%Pages: 1\n"));
+        assert!(ps.contains("%%BoundingBox: 0 0 612 792\n"));
+        assert!(ps.contains("%%IncludeResource: file special.pro\n"));
+        assert!(ps.contains("%%Page: 3.1 1\n"));
+        assert!(ps.contains("gsave 72 720 translate  newpath 0 0 moveto grestore\n"));
+        assert!(ps.contains("\n 1 setgray\n"));
+        assert!(ps.contains("/F0 findfont setfont\n72 720 M <2e> S\n"));
+        assert!(ps.contains("74.491 720 0.996 0.996 R\n"));
+        assert!(ps.contains("/g46 { 10 0 0 0 9 2 setcachedevice"));
+        assert!(ps.contains("{<ff80ff80>} imagemask"));
+        assert!(ps.ends_with("restore showpage\n%%Trailer\n%%EOF\n"));
+
+        // A4 is 595.276bp by 841.89bp, and rounding down would clip it
+        let mut document = document();
+        document.pages[0]
+            .instructions
+            .push(Instruction::Xxx(Vec::from("papersize=210mm,297mm")));
+        let ps = String::from_utf8(render(&document, &fonts(), 72).unwrap()).unwrap();
+        assert!(ps.contains("%%BoundingBox: 0 0 596 842\n"));
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(label(&[0; 10]), "0");
+        assert_eq!(label(&[5, 0, 0, 0, 0, 0, 0, 0, 0, 0]), "5");
+        assert_eq!(label(&[1, 0, 2, 0, 0, 0, 0, 0, 0, 0]), "1.0.2");
+        assert_eq!(label(&[-3, 0, 0, 0, 0, 0, 0, 0, 0, 0]), "-3");
+    }
+}
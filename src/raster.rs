@@ -0,0 +1,409 @@
+//! Rasterize dvi pages into grayscale images
+//!
+//! Pages are drawn at the resolution of the PK fonts, then optionally shrunk by an integer factor.
+//! Each output pixel gets a shade of gray depending on how many of the pixels it covers are
+//! black, which gives anti-aliasing in the same way as xdvi.
+
+use crate::{
+    interpreter::{Device, Error, Interpreter},
+    pk::PkFont,
+    util::bp_per_unit,
+    FontDef,
+};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// A grayscale image, where 0 is black and 255 is white
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels, row by row from the top
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Write the image as a binary PGM file
+    pub fn write_pgm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P5\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.pixels)
+    }
+
+    /// Write the image as a PNG file
+    ///
+    /// The image data is stored without compression, so the files are larger than they need to
+    /// be, but can be read by anything that understands PNG.
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bit grayscale, default compression and filtering, not interlaced
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+        png_chunk(writer, b"IHDR", &header)?;
+
+        // each row starts with a filter type byte of 0 (no filter)
+        let mut raw = Vec::with_capacity(self.pixels.len() + self.height as usize);
+        if self.width > 0 {
+            for row in self.pixels.chunks(self.width as usize) {
+                raw.push(0);
+                raw.extend_from_slice(row);
+            }
+        }
+        png_chunk(writer, b"IDAT", &zlib_stored(&raw))?;
+        png_chunk(writer, b"IEND", &[])
+    }
+}
+
+/// A `Device` that draws pages into images using bitmaps from PK fonts
+///
+/// Fonts are looked up by their number in the dvi file.
+#[derive(Debug, Clone)]
+pub struct Raster<'a> {
+    fonts: &'a HashMap<u32, PkFont>,
+    resolution: u32,
+    shrink: u32,
+    page_width: f64,
+    page_height: f64,
+    /// Pixels per dvi unit
+    conv: f64,
+    /// The page being drawn, at full resolution
+    width: u32,
+    height: u32,
+    bits: Vec<bool>,
+    pages: Vec<Image>,
+}
+
+impl<'a> Raster<'a> {
+    /// Create a rasterizer for fonts made for `resolution` dots per inch, on US letter paper
+    pub fn new(fonts: &'a HashMap<u32, PkFont>, resolution: u32) -> Self {
+        let mut raster = Raster {
+            fonts,
+            resolution,
+            shrink: 1,
+            page_width: 612.0,
+            page_height: 792.0,
+            conv: 0.0,
+            width: 0,
+            height: 0,
+            bits: Vec::new(),
+            pages: Vec::new(),
+        };
+        raster.preamble(25_400_000, 473_628_672, 1000);
+        raster
+    }
+
+    /// Shrink the output by `factor`, so its resolution is `resolution / factor`
+    ///
+    /// # Panics
+    ///
+    /// If `factor` is zero.
+    pub fn shrink(mut self, factor: u32) -> Self {
+        assert!(factor > 0, "shrink factor must be positive");
+        self.shrink = factor;
+        self
+    }
+
+    /// Set the size of the paper in big points
+    pub fn page_size(mut self, width: f64, height: f64) -> Self {
+        self.page_width = width;
+        self.page_height = height;
+        self
+    }
+
+    /// The pages that have been drawn so far
+    pub fn pages(&self) -> &[Image] {
+        &self.pages
+    }
+
+    /// Take the finished pages
+    pub fn into_pages(self) -> Vec<Image> {
+        self.pages
+    }
+
+    /// The pixel position of the page reference point, one inch in from the edges
+    fn origin(&self) -> i64 {
+        i64::from(self.resolution)
+    }
+
+    fn pixels(&self, dvi: i32) -> i64 {
+        (f64::from(dvi) * self.conv).round() as i64
+    }
+
+    /// Like `pixels`, but rounding up, as TeX recommends for the size of rules
+    fn rule_pixels(&self, dvi: i32) -> i64 {
+        (f64::from(dvi) * self.conv).ceil() as i64
+    }
+
+    fn fill(&mut self, left: i64, top: i64, right: i64, bottom: i64) {
+        let clamp = |v: i64, max: u32| v.max(0).min(i64::from(max)) as usize;
+        let (left, right) = (clamp(left, self.width), clamp(right, self.width));
+        let (top, bottom) = (clamp(top, self.height), clamp(bottom, self.height));
+        for y in top..bottom {
+            let row = y * self.width as usize;
+            for bit in &mut self.bits[row + left..row + right] {
+                *bit = true;
+            }
+        }
+    }
+
+    fn set(&mut self, x: i64, y: i64) {
+        if x >= 0 && y >= 0 && x < i64::from(self.width) && y < i64::from(self.height) {
+            self.bits[y as usize * self.width as usize + x as usize] = true;
+        }
+    }
+
+    /// Shrink the full resolution page into a grayscale image
+    fn finish(&self) -> Image {
+        let s = self.shrink as usize;
+        let (width, height) = (self.width as usize / s, self.height as usize / s);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut black = 0;
+                for sy in y * s..(y + 1) * s {
+                    let row = sy * self.width as usize;
+                    black += self.bits[row + x * s..row + (x + 1) * s]
+                        .iter()
+                        .filter(|&&b| b)
+                        .count();
+                }
+                pixels.push((255 - black * 255 / (s * s)) as u8);
+            }
+        }
+        Image {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        }
+    }
+}
+
+impl<'a> Device for Raster<'a> {
+    fn preamble(&mut self, numerator: u32, denominator: u32, magnification: u32) {
+        self.conv =
+            bp_per_unit(numerator, denominator, magnification) * f64::from(self.resolution) / 72.0;
+    }
+
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        // round the page up to a whole number of output pixels
+        let (resolution, shrink) = (f64::from(self.resolution), self.shrink);
+        let size = |bp: f64| {
+            let full = (bp / 72.0 * resolution).ceil() as u32;
+            full.div_ceil(shrink) * shrink
+        };
+        self.width = size(self.page_width);
+        self.height = size(self.page_height);
+        self.bits.clear();
+        self.bits
+            .resize(self.width as usize * self.height as usize, false);
+    }
+
+    fn end_page(&mut self) {
+        let image = self.finish();
+        self.pages.push(image);
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, ch: u32, _: i32) {
+        let fonts = self.fonts;
+        let glyph = match fonts.get(&font.number).and_then(|f| f.glyphs.get(&ch)) {
+            Some(glyph) => glyph,
+            None => return,
+        };
+        let left = self.origin() + self.pixels(h) - i64::from(glyph.h_offset);
+        let top = self.origin() + self.pixels(v) - i64::from(glyph.v_offset);
+        for y in 0..glyph.height {
+            for x in 0..glyph.width {
+                if glyph.pixel(x, y) {
+                    self.set(left + i64::from(x), top + i64::from(y));
+                }
+            }
+        }
+    }
+
+    fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+        let left = self.origin() + self.pixels(h);
+        let bottom = self.origin() + self.pixels(v);
+        let top = bottom - self.rule_pixels(height);
+        let right = left + self.rule_pixels(width);
+        self.fill(left, top, right, bottom);
+    }
+}
+
+/// Render every page of a dvi file into images
+///
+/// `fonts` are the PK fonts for each font number, made for `resolution` dots per inch, and the
+/// output is shrunk by `shrink`.
+pub fn render(
+    input: &[u8],
+    fonts: &HashMap<u32, PkFont>,
+    resolution: u32,
+    shrink: u32,
+) -> Result<Vec<Image>, Error> {
+    let mut raster = Raster::new(fonts, resolution).shrink(shrink);
+    Interpreter::new(fonts).run(input, &mut raster)?;
+    Ok(raster.into_pages())
+}
+
+fn png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(crc32(!0, kind), data);
+    writer.write_all(&(!crc).to_be_bytes())
+}
+
+/// Update a CRC-32 (as used by PNG) with `data`
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Wrap `data` in a zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 0xffff * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pk::Glyph;
+
+    fn fonts() -> HashMap<u32, PkFont> {
+        let mut glyphs = HashMap::new();
+        glyphs.insert(
+            u32::from(b'.'),
+            Glyph {
+                tfm_width: 0x0010_0000,
+                dx: 4 << 16,
+                dy: 0,
+                width: 2,
+                height: 2,
+                h_offset: 0,
+                v_offset: 2,
+                bitmap: vec![true; 4],
+            },
+        );
+        let mut fonts = HashMap::new();
+        fonts.insert(
+            0,
+            PkFont {
+                comment: Vec::new(),
+                design_size: 10 << 20,
+                checksum: 0,
+                hppp: 0,
+                vppp: 0,
+                glyphs,
+            },
+        );
+        fonts
+    }
+
+    #[test]
+    fn draw() {
+        let fonts = fonts();
+        // one dvi unit per pixel
+        let mut raster = Raster::new(&fonts, 72).page_size(4.0, 4.0);
+        raster.preamble(254_000, 72, 1000);
+        let def = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 4,
+            design_size: 4,
+            directory: None,
+            filename: Vec::from("dots"),
+        };
+        // the page is 4 pixels square, with the origin at (72, 72), so draw off the origin
+        raster.begin_page(&[0; 10]);
+        raster.char(-72, -70, &def, b'.'.into(), 4);
+        raster.rule(-70, -70, 1, 2);
+        raster.char(-72, -60, &def, b'?'.into(), 4);
+        raster.end_page();
+        let image = &raster.pages()[0];
+        assert_eq!((image.width, image.height), (4, 4));
+        #[rustfmt::skip]
+        assert_eq!(image.pixels, vec![
+            0, 0, 255, 255,
+            0, 0, 0, 0,
+            255, 255, 255, 255,
+            255, 255, 255, 255,
+        ]);
+    }
+
+    #[test]
+    fn shrink() {
+        let fonts = fonts();
+        let mut raster = Raster::new(&fonts, 72).page_size(4.0, 4.0).shrink(2);
+        raster.preamble(254_000, 72, 1000);
+        raster.begin_page(&[0; 10]);
+        raster.rule(-72, -70, 1, 3);
+        raster.end_page();
+        assert_eq!(raster.pages()[0].pixels, vec![128, 192, 255, 255]);
+    }
+
+    #[test]
+    fn pgm() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![0, 255],
+        };
+        let mut out = Vec::new();
+        image.write_pgm(&mut out).unwrap();
+        assert_eq!(out, b"P5\n2 1\n255\n\x00\xff");
+    }
+
+    #[test]
+    fn png() {
+        let image = Image {
+            width: 1,
+            height: 1,
+            pixels: vec![0],
+        };
+        let mut out = Vec::new();
+        image.write_png(&mut out).unwrap();
+        assert!(out.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert!(out.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(!crc32(!0, b"IEND"), 0xae42_6082);
+        // zlib header, one final stored block, then the adler-32 of "a"
+        assert_eq!(
+            zlib_stored(b"a"),
+            vec![0x78, 0x01, 1, 1, 0, 0xfe, 0xff, b'a', 0, 0x62, 0, 0x62]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Serde support for byte strings
+//!
+//! Byte strings are written as strings when they are valid UTF-8, which they almost always are,
+//! and otherwise as `{"base64": "..."}` using the standard alphabet with padding. Both forms are
+//! accepted when reading.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Repr<'a> {
+    Text(std::borrow::Cow<'a, str>),
+    Base64 { base64: String },
+}
+
+pub(crate) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Repr::Text(text.into()),
+        Err(_) => Repr::Base64 {
+            base64: encode(bytes),
+        },
+    }
+    .serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    match Repr::deserialize(deserializer)? {
+        Repr::Text(text) => Ok(text.into_owned().into_bytes()),
+        Repr::Base64 { base64 } => {
+            decode(&base64).ok_or_else(|| serde::de::Error::custom("invalid base64"))
+        }
+    }
+}
+
+/// The same for `Option<Vec<u8>>`, using `null` for `None`
+pub(crate) mod option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => super::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        #[derive(Deserialize)]
+        struct Bytes(#[serde(with = "super")] Vec<u8>);
+
+        Ok(Option::<Bytes>::deserialize(deserializer)?.map(|bytes| bytes.0))
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for (index, chunk) in text.chunks(4).enumerate() {
+        let last = index == text.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = ALPHABET.iter().position(|&a| a == c)?;
+            n = n << 6 | value as u32;
+        }
+        n <<= 6 * padding as u32;
+        let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        out.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Document, Page, Postamble, Preamble},
+        FontDef, Instruction,
+    };
+
+    #[test]
+    fn base64() {
+        for (bytes, text) in &[
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"\xff\xfe\x00\x80", "//4AgA=="),
+        ] {
+            assert_eq!(encode(bytes), *text);
+            assert_eq!(decode(text).as_deref(), Some(*bytes));
+        }
+        assert_eq!(decode("Zg="), None);
+        assert_eq!(decode("Zg==Zg=="), None);
+        assert_eq!(decode("Z!=="), None);
+    }
+
+    #[test]
+    fn json() {
+        let instructions = vec![
+            Instruction::Nop,
+            Instruction::Set(65),
+            Instruction::Xxx(Vec::from("color push Red")),
+            Instruction::Xxx(vec![0xff, 0]),
+            Instruction::W(None),
+        ];
+        let json = serde_json::to_string(&instructions).unwrap();
+        assert_eq!(
+            json,
+            r#"["Nop",{"Set":65},{"Xxx":"color push Red"},{"Xxx":{"base64":"/wA="}},{"W":null}]"#
+        );
+        let parsed: Vec<Instruction> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, instructions);
+
+        let font = FontDef {
+            number: 1,
+            checksum: 2,
+            scale_factor: 3,
+            design_size: 4,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        };
+        assert_eq!(
+            serde_json::to_string(&font).unwrap(),
+            r#"{"number":1,"checksum":2,"scale_factor":3,"design_size":4,"directory":null,"filename":"cmr10"}"#
+        );
+
+        let document = Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::from("comment"),
+            },
+            pages: vec![Page {
+                counters: [1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                instructions: vec![Instruction::FontDef(FontDef {
+                    directory: Some(vec![0xc0]),
+                    ..font.clone()
+                })],
+            }],
+            postamble: Postamble {
+                tallest_height: 1,
+                widest_width: 2,
+                max_stack_depth: 3,
+                fonts: vec![font],
+            },
+        };
+        let json = serde_json::to_string(&document).unwrap();
+        assert!(json.contains(r#""directory":{"base64":"wA=="}"#));
+        assert_eq!(serde_json::from_str::<Document>(&json).unwrap(), document);
+    }
+}
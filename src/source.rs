@@ -0,0 +1,259 @@
+//! Forward and inverse search using source specials
+//!
+//! TeX run with `-src-specials` writes `src:<line> <file>` specials, marking where the following
+//! material came from. The file is left out when it hasn't changed since the previous special. A
+//! [`SourceIndex`] records where each of these specials ends up on the page, and which characters
+//! follow it, so it can answer both "where is `main.tex` line 123?" and "which line produced the
+//! character at this point?".
+//!
+//! [`SourceIndex`]: ./struct.SourceIndex.html
+
+use crate::{
+    document::Document,
+    interpreter::{Device, Error, FontMetrics, PageIndex},
+    special::Special,
+    FontDef,
+};
+
+/// A source special and where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The index of the page in the document
+    pub page: usize,
+    /// The position of the special on the page, in dvi units
+    pub h: i32,
+    pub v: i32,
+    pub file: String,
+    pub line: u32,
+}
+
+/// A run of characters on one baseline that came from the same source line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    page: usize,
+    left: i32,
+    right: i32,
+    v: i32,
+    /// The index of the record for the characters
+    record: usize,
+}
+
+/// An index from source lines to positions on pages and back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceIndex {
+    records: Vec<Record>,
+    spans: Vec<Span>,
+}
+
+impl SourceIndex {
+    /// Build the index by interpreting every page of `document`
+    pub fn build<M: FontMetrics>(document: &Document, metrics: M) -> Result<Self, Error> {
+        let mut builder = Builder::default();
+        document.run(metrics, &mut builder)?;
+        Ok(builder.index)
+    }
+
+    /// Every source special in the document, in order
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Forward search: where the output for `line` of `file` starts
+    ///
+    /// This is the first special for `file` with the closest line at or before `line`, or
+    /// failing that the closest one after it. `file` matches a special's file name if they are
+    /// the same, or if one is a path ending with the other.
+    pub fn forward(&self, file: &str, line: u32) -> Option<&Record> {
+        let candidates = || self.records.iter().filter(|r| same_file(&r.file, file));
+        let before = candidates().filter(|r| r.line <= line).fold(
+            None,
+            |best: Option<&Record>, r| match best {
+                Some(best) if best.line >= r.line => Some(best),
+                _ => Some(r),
+            },
+        );
+        before.or_else(|| {
+            candidates()
+                .filter(|r| r.line > line)
+                .min_by_key(|r| r.line)
+        })
+    }
+
+    /// Inverse search: the source special for the character closest to (h,v) on `page`
+    ///
+    /// Distances are measured to the baselines of characters.
+    pub fn inverse(&self, page: usize, h: i32, v: i32) -> Option<&Record> {
+        let distance = |span: &Span| {
+            let dx = if h < span.left {
+                i64::from(span.left) - i64::from(h)
+            } else if h > span.right {
+                i64::from(h) - i64::from(span.right)
+            } else {
+                0
+            };
+            let dy = i64::from(v) - i64::from(span.v);
+            // the squares of differences between i32s can be too big for an i64
+            let (dx, dy) = (i128::from(dx), i128::from(dy));
+            dx * dx + dy * dy
+        };
+        let span = self
+            .spans
+            .iter()
+            .filter(|span| span.page == page)
+            .min_by_key(|span| distance(span))?;
+        self.records.get(span.record)
+    }
+}
+
+/// Whether two file names refer to the same file
+fn same_file(a: &str, b: &str) -> bool {
+    let ends_with = |long: &str, short: &str| {
+        long.strip_suffix(short)
+            .is_some_and(|start| start.is_empty() || start.ends_with('/'))
+    };
+    ends_with(a, b) || ends_with(b, a)
+}
+
+/// A `Device` that builds a `SourceIndex`
+#[derive(Debug, Default)]
+struct Builder {
+    index: SourceIndex,
+    page: PageIndex,
+    file: Option<String>,
+}
+
+impl Device for Builder {
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.page.begin();
+    }
+
+    fn special(&mut self, h: i32, v: i32, payload: &[u8]) {
+        if let Special::Source { line, file } = Special::parse(payload) {
+            if file.is_some() {
+                self.file = file;
+            }
+            self.index.records.push(Record {
+                page: self.page.get(),
+                h,
+                v,
+                file: self.file.clone().unwrap_or_default(),
+                line,
+            });
+        }
+    }
+
+    fn char(&mut self, h: i32, v: i32, _: &FontDef, _: u32, width: i32) {
+        if self.index.records.is_empty() {
+            return;
+        }
+        let record = self.index.records.len() - 1;
+        let end = h.saturating_add(width);
+        let (left, right) = (h.min(end), h.max(end));
+        if let Some(last) = self.index.spans.last_mut() {
+            if last.page == self.page.get() && last.v == v && last.record == record {
+                last.left = last.left.min(left);
+                last.right = last.right.max(right);
+                return;
+            }
+        }
+        self.index.spans.push(Span {
+            page: self.page.get(),
+            left,
+            right,
+            v,
+            record,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        Instruction,
+    };
+
+    struct Fixed;
+
+    impl FontMetrics for Fixed {
+        fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+            Some(10)
+        }
+    }
+
+    fn document() -> Document {
+        let src = |s: &str| Instruction::Xxx(Vec::from(s));
+        let font = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        };
+        let page = |instructions| Page {
+            counters: [0; 10],
+            instructions,
+        };
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: vec![
+                page(vec![
+                    Instruction::Font(0),
+                    src("src:3 main.tex"),
+                    Instruction::Set(b'a'.into()),
+                    Instruction::Set(b'b'.into()),
+                    Instruction::Down(100),
+                    src("src:10"),
+                    Instruction::Set(b'c'.into()),
+                ]),
+                page(vec![
+                    Instruction::Font(0),
+                    Instruction::Down(50),
+                    src("src:1 chapters/intro.tex"),
+                    Instruction::Set(b'd'.into()),
+                    Instruction::Right(100),
+                    src("src:20main.tex"),
+                    Instruction::Set(b'e'.into()),
+                ]),
+            ],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font],
+            },
+        }
+    }
+
+    #[test]
+    fn forward() {
+        let index = SourceIndex::build(&document(), Fixed).unwrap();
+        assert_eq!(index.records().len(), 4);
+        let found = index.forward("main.tex", 12).unwrap();
+        assert_eq!((found.page, found.h, found.v, found.line), (0, 20, 100, 10));
+        assert_eq!(index.forward("main.tex", 25).unwrap().page, 1);
+        assert_eq!(index.forward("main.tex", 1).unwrap().line, 3);
+        assert_eq!(index.forward("intro.tex", 1).unwrap().page, 1);
+        assert_eq!(index.forward("/home/me/main.tex", 3).unwrap().line, 3);
+        assert!(index.forward("other.tex", 1).is_none());
+        assert!(index.forward("ro.tex", 1).is_none());
+    }
+
+    #[test]
+    fn inverse() {
+        let index = SourceIndex::build(&document(), Fixed).unwrap();
+        assert_eq!(index.inverse(0, 15, 2).unwrap().line, 3);
+        assert_eq!(index.inverse(0, 25, 90).unwrap().line, 10);
+        assert_eq!(index.inverse(1, 0, 50).unwrap().file, "chapters/intro.tex");
+        assert_eq!(index.inverse(1, 200, 50).unwrap().line, 20);
+        assert!(index.inverse(2, 0, 0).is_none());
+    }
+}
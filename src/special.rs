@@ -0,0 +1,554 @@
+//! Parsing of `\special` payloads
+//!
+//! The `Xxx` instruction carries arbitrary bytes for the driver, and every driver has its own
+//! conventions for what they mean. This module recognises the common ones, as used by dvips,
+//! dvipdfmx, emTeX, tpic and the source and hypertext specials, and turns them into a
+//! [`Special`](./enum.Special.html). Anything else is kept as `Special::Unknown`.
+//!
+//! Parsing then dumping a special gives the same meaning back, but not necessarily the same
+//! bytes: whitespace is normalised and paper sizes are written in big points.
+
+use crate::util::dimension;
+use std::io::{self, Write};
+
+/// A special, classified by the driver convention it follows
+#[derive(Debug, Clone, PartialEq)]
+pub enum Special {
+    /// `color push <color>`: save the current colour and switch to a new one
+    ColorPush(Color),
+    /// `color pop`: go back to the colour before the last push
+    ColorPop,
+    /// `color <color>`: replace the whole colour stack with a single colour
+    Color(Color),
+    /// `papersize=<width>,<height>`, with the sizes in big points
+    PaperSize { width: f64, height: f64 },
+    /// `landscape`: rotate the paper
+    Landscape,
+    /// `" <code>`: PostScript run with the origin at the current point
+    PsQuote(String),
+    /// `ps: <code>`: PostScript run with the current point set
+    Ps(String),
+    /// `ps:: <code>`: PostScript passed through untouched
+    PsLiteral(String),
+    /// `header=<file>`: a PostScript file to include in the document setup
+    Header(String),
+    /// `pdf:<command> <args>`: a dvipdfmx command
+    Pdf { command: String, args: String },
+    /// `html:<tag>`: a hypertext tag
+    Html(String),
+    /// `src:<line> <file>`: the source position of the following material
+    Source { line: u32, file: Option<String> },
+    /// `em:<command> <args>`: an emTeX command
+    Em { command: String, args: String },
+    /// A tpic graphics command
+    Tpic(Tpic),
+    /// `PSfile=<file> <key>=<value>...`: an EPS figure to include
+    ///
+    /// `keyword` is `PSfile` as it was written, since dvips accepts it in any case, and `quoted`
+    /// is whether the file name was in double quotes.
+    PsFile {
+        keyword: String,
+        file: String,
+        quoted: bool,
+        options: Vec<(String, String)>,
+    },
+    /// A special this module doesn't know about
+    Unknown(Vec<u8>),
+}
+
+/// A colour, as understood by dvips' `color` specials
+#[derive(Debug, Clone, PartialEq)]
+pub enum Color {
+    Rgb(f64, f64, f64),
+    Cmyk(f64, f64, f64, f64),
+    Gray(f64),
+    /// One of the names in dvips' `color.pro`, like `Red` or `ForestGreen`
+    Named(String),
+}
+
+/// A tpic command
+///
+/// Lengths are in milli-inches, angles in radians, and y points down the page.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tpic {
+    /// `pn <size>`: set the pen size
+    PenSize(i32),
+    /// `pa <x> <y>`: add a point to the path
+    Path(i32, i32),
+    /// `fp`: draw the path and clear it
+    FlushPath,
+    /// `ip`: fill the path without drawing its outline, and clear it
+    InvisiblePath,
+    /// `da <length>`: draw the path dashed, with dashes `length` inches long
+    Dashed(f64),
+    /// `dt <length>`: draw the path dotted, with dots `length` inches apart
+    Dotted(f64),
+    /// `sp [<length>]`: draw the path as a spline, dashed if `length` is positive and dotted if
+    /// it is negative
+    Spline(Option<f64>),
+    /// `ar <x> <y> <rx> <ry> <start> <end>`: draw an elliptical arc
+    Arc {
+        x: i32,
+        y: i32,
+        rx: i32,
+        ry: i32,
+        start: f64,
+        end: f64,
+    },
+    /// `ia <x> <y> <rx> <ry> <start> <end>`: fill an elliptical arc without its outline
+    InvisibleArc {
+        x: i32,
+        y: i32,
+        rx: i32,
+        ry: i32,
+        start: f64,
+        end: f64,
+    },
+    /// `sh [<gray>]`: shade the next filled shape, 0 is white and 1 is black
+    Shade(Option<f64>),
+    /// `wh`: shade the next filled shape white
+    White,
+    /// `bk`: shade the next filled shape black
+    Black,
+    /// `tx <pattern>`: shade the next filled shape with a texture
+    Texture(String),
+}
+
+impl Special {
+    /// Classify and parse a special payload
+    pub fn parse(payload: &[u8]) -> Self {
+        let text = match std::str::from_utf8(payload) {
+            Ok(text) => text.trim_start(),
+            Err(_) => return Special::Unknown(payload.to_vec()),
+        };
+        Self::parse_str(text).unwrap_or_else(|| Special::Unknown(payload.to_vec()))
+    }
+
+    fn parse_str(text: &str) -> Option<Self> {
+        let (word, rest) = split_word(text);
+        let special = if word == "color" {
+            let (sub, args) = split_word(rest);
+            match sub {
+                "push" => Special::ColorPush(Color::parse(args)?),
+                "pop" if args.is_empty() => Special::ColorPop,
+                _ => Special::Color(Color::parse(rest)?),
+            }
+        } else if let Some(size) = text.strip_prefix("papersize=") {
+            let mut parts = size.trim().splitn(2, ',');
+            let width = dimension(parts.next()?)?;
+            let height = dimension(parts.next()?)?;
+            Special::PaperSize { width, height }
+        } else if text.trim_end() == "landscape" {
+            Special::Landscape
+        } else if let Some(code) = text.strip_prefix('"') {
+            Special::PsQuote(code.to_string())
+        } else if let Some(code) = text.strip_prefix("ps::") {
+            Special::PsLiteral(code.to_string())
+        } else if let Some(code) = text.strip_prefix("ps:") {
+            Special::Ps(code.to_string())
+        } else if let Some(file) = text.strip_prefix("header=") {
+            Special::Header(file.trim().to_string())
+        } else if let Some(command) = text.strip_prefix("pdf:") {
+            let (command, args) = split_word(command.trim_start());
+            Special::Pdf {
+                command: command.to_string(),
+                args: args.to_string(),
+            }
+        } else if let Some(tag) = text.strip_prefix("html:") {
+            Special::Html(tag.to_string())
+        } else if let Some(source) = text.strip_prefix("src:") {
+            let source = source.trim();
+            let digits = source.find(|c: char| !c.is_ascii_digit());
+            let (line, file) = source.split_at(digits.unwrap_or(source.len()));
+            let file = file.trim();
+            Special::Source {
+                line: line.parse().ok()?,
+                file: if file.is_empty() {
+                    None
+                } else {
+                    Some(file.to_string())
+                },
+            }
+        } else if let Some(command) = text.strip_prefix("em:") {
+            let (command, args) = split_word(command.trim_start());
+            Special::Em {
+                command: command.to_string(),
+                args: args.to_string(),
+            }
+        } else if starts_with_psfile(text) {
+            let (keyword, rest) = text.split_once('=')?;
+            let rest = rest.trim_start();
+            let (file, options, quoted) = match rest.strip_prefix('"') {
+                Some(quoted) => {
+                    let (file, options) = quoted.split_once('"')?;
+                    (file, options, true)
+                }
+                None => {
+                    let (file, options) = split_word(rest);
+                    (file, options, false)
+                }
+            };
+            let options = options
+                .split_whitespace()
+                .map(|option| {
+                    let (key, value) = option.split_once('=').unwrap_or((option, ""));
+                    (key.to_string(), value.to_string())
+                })
+                .collect();
+            Special::PsFile {
+                keyword: keyword.to_string(),
+                file: file.to_string(),
+                quoted,
+                options,
+            }
+        } else {
+            Special::Tpic(Tpic::parse(word, rest)?)
+        };
+        Some(special)
+    }
+
+    /// Write the special as the payload of an `Xxx` instruction
+    pub fn dump<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Special::ColorPush(color) => write!(writer, "color push {}", color),
+            Special::ColorPop => write!(writer, "color pop"),
+            Special::Color(color) => write!(writer, "color {}", color),
+            Special::PaperSize { width, height } => {
+                write!(writer, "papersize={}bp,{}bp", width, height)
+            }
+            Special::Landscape => write!(writer, "landscape"),
+            Special::PsQuote(code) => write!(writer, "\"{}", code),
+            Special::Ps(code) => write!(writer, "ps:{}", code),
+            Special::PsLiteral(code) => write!(writer, "ps::{}", code),
+            Special::Header(file) => write!(writer, "header={}", file),
+            Special::Pdf { command, args } => write_command(writer, "pdf:", command, args),
+            Special::Html(tag) => write!(writer, "html:{}", tag),
+            Special::Source { line, file } => match file {
+                Some(file) => write!(writer, "src:{} {}", line, file),
+                None => write!(writer, "src:{}", line),
+            },
+            Special::Em { command, args } => write_command(writer, "em:", command, args),
+            Special::Tpic(tpic) => tpic.dump(writer),
+            Special::PsFile {
+                keyword,
+                file,
+                quoted,
+                options,
+            } => {
+                if *quoted {
+                    write!(writer, "{}=\"{}\"", keyword, file)?;
+                } else {
+                    write!(writer, "{}={}", keyword, file)?;
+                }
+                for (key, value) in options {
+                    write!(writer, " {}={}", key, value)?;
+                }
+                Ok(())
+            }
+            Special::Unknown(payload) => writer.write_all(payload),
+        }
+    }
+}
+
+impl Color {
+    /// Parse a colour in dvips' syntax, like `rgb 1 0 0` or `Red`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace();
+        let model = words.next()?;
+        let values = words
+            .map(|word| word.parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let color = match (model, values.as_slice()) {
+            ("rgb", &[r, g, b]) => Color::Rgb(r, g, b),
+            ("cmyk", &[c, m, y, k]) => Color::Cmyk(c, m, y, k),
+            ("gray", &[g]) => Color::Gray(g),
+            ("rgb", _) | ("cmyk", _) | ("gray", _) => return None,
+            (name, &[]) => Color::Named(name.to_string()),
+            _ => return None,
+        };
+        Some(color)
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Color::Rgb(r, g, b) => write!(f, "rgb {} {} {}", r, g, b),
+            Color::Cmyk(c, m, y, k) => write!(f, "cmyk {} {} {} {}", c, m, y, k),
+            Color::Gray(g) => write!(f, "gray {}", g),
+            Color::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Tpic {
+    fn parse(command: &str, args: &str) -> Option<Self> {
+        let numbers = args
+            .split_whitespace()
+            .map(|word| word.parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>();
+        let int = |x: f64| x.round() as i32;
+        let tpic = match (command, numbers.as_deref()) {
+            ("pn", Some(&[size])) => Tpic::PenSize(int(size)),
+            ("pa", Some(&[x, y])) => Tpic::Path(int(x), int(y)),
+            ("fp", Some(&[])) => Tpic::FlushPath,
+            ("ip", Some(&[])) => Tpic::InvisiblePath,
+            ("da", Some(&[length])) => Tpic::Dashed(length),
+            ("dt", Some(&[length, ..])) => Tpic::Dotted(length),
+            ("sp", Some(&[])) => Tpic::Spline(None),
+            ("sp", Some(&[length])) => Tpic::Spline(Some(length)),
+            ("ar", Some(&[x, y, rx, ry, start, end])) => Tpic::Arc {
+                x: int(x),
+                y: int(y),
+                rx: int(rx),
+                ry: int(ry),
+                start,
+                end,
+            },
+            ("ia", Some(&[x, y, rx, ry, start, end])) => Tpic::InvisibleArc {
+                x: int(x),
+                y: int(y),
+                rx: int(rx),
+                ry: int(ry),
+                start,
+                end,
+            },
+            ("sh", Some(&[])) => Tpic::Shade(None),
+            ("sh", Some(&[gray])) => Tpic::Shade(Some(gray)),
+            ("wh", Some(&[])) => Tpic::White,
+            ("bk", Some(&[])) => Tpic::Black,
+            ("tx", _) => Tpic::Texture(args.trim().to_string()),
+            _ => return None,
+        };
+        Some(tpic)
+    }
+
+    fn dump<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Tpic::PenSize(size) => write!(writer, "pn {}", size),
+            Tpic::Path(x, y) => write!(writer, "pa {} {}", x, y),
+            Tpic::FlushPath => write!(writer, "fp"),
+            Tpic::InvisiblePath => write!(writer, "ip"),
+            Tpic::Dashed(length) => write!(writer, "da {}", length),
+            Tpic::Dotted(length) => write!(writer, "dt {}", length),
+            Tpic::Spline(None) => write!(writer, "sp"),
+            Tpic::Spline(Some(length)) => write!(writer, "sp {}", length),
+            Tpic::Arc {
+                x,
+                y,
+                rx,
+                ry,
+                start,
+                end,
+            } => write!(writer, "ar {} {} {} {} {} {}", x, y, rx, ry, start, end),
+            Tpic::InvisibleArc {
+                x,
+                y,
+                rx,
+                ry,
+                start,
+                end,
+            } => write!(writer, "ia {} {} {} {} {} {}", x, y, rx, ry, start, end),
+            Tpic::Shade(None) => write!(writer, "sh"),
+            Tpic::Shade(Some(gray)) => write!(writer, "sh {}", gray),
+            Tpic::White => write!(writer, "wh"),
+            Tpic::Black => write!(writer, "bk"),
+            Tpic::Texture(pattern) => write!(writer, "tx {}", pattern),
+        }
+    }
+}
+
+/// Split off the first word, returning it and the rest with leading whitespace removed
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_end();
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Whether `text` starts with `PSfile=` in any case
+fn starts_with_psfile(text: &str) -> bool {
+    text.get(..7)
+        .is_some_and(|start| start.eq_ignore_ascii_case("psfile="))
+}
+
+fn write_command<W: Write>(
+    writer: &mut W,
+    prefix: &str,
+    command: &str,
+    args: &str,
+) -> io::Result<()> {
+    if args.is_empty() {
+        write!(writer, "{}{}", prefix, command)
+    } else {
+        write!(writer, "{}{} {}", prefix, command, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(payload: &str, expected: Special) {
+        let special = Special::parse(payload.as_bytes());
+        assert_eq!(special, expected);
+        let mut out = Vec::new();
+        special.dump(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), payload);
+    }
+
+    #[test]
+    fn colors() {
+        round_trip(
+            "color push rgb 1 0 0.5",
+            Special::ColorPush(Color::Rgb(1.0, 0.0, 0.5)),
+        );
+        round_trip(
+            "color push Red",
+            Special::ColorPush(Color::Named("Red".into())),
+        );
+        round_trip("color pop", Special::ColorPop);
+        round_trip("color gray 0.5", Special::Color(Color::Gray(0.5)));
+        round_trip(
+            "color cmyk 0 1 1 0",
+            Special::Color(Color::Cmyk(0.0, 1.0, 1.0, 0.0)),
+        );
+        assert!(matches!(
+            Special::parse(b"color push rgb 1 0"),
+            Special::Unknown(_)
+        ));
+    }
+
+    #[test]
+    fn dvips() {
+        round_trip(
+            "papersize=100bp,200bp",
+            Special::PaperSize {
+                width: 100.0,
+                height: 200.0,
+            },
+        );
+        round_trip("landscape", Special::Landscape);
+        round_trip("\" 0 0 moveto", Special::PsQuote(" 0 0 moveto".into()));
+        round_trip("ps: 1 setgray", Special::Ps(" 1 setgray".into()));
+        round_trip("ps::[begin]", Special::PsLiteral("[begin]".into()));
+        round_trip("header=color.pro", Special::Header("color.pro".into()));
+        round_trip(
+            "PSfile=\"fig.eps\" llx=0 lly=0 urx=72 ury=72 rwi=1000",
+            Special::PsFile {
+                keyword: "PSfile".into(),
+                file: "fig.eps".into(),
+                quoted: true,
+                options: vec![
+                    ("llx".into(), "0".into()),
+                    ("lly".into(), "0".into()),
+                    ("urx".into(), "72".into()),
+                    ("ury".into(), "72".into()),
+                    ("rwi".into(), "1000".into()),
+                ],
+            },
+        );
+        round_trip(
+            "psfile=fig.eps",
+            Special::PsFile {
+                keyword: "psfile".into(),
+                file: "fig.eps".into(),
+                quoted: false,
+                options: Vec::new(),
+            },
+        );
+        round_trip(
+            "PSfile=\"my fig.eps\" rwi=10",
+            Special::PsFile {
+                keyword: "PSfile".into(),
+                file: "my fig.eps".into(),
+                quoted: true,
+                options: vec![("rwi".into(), "10".into())],
+            },
+        );
+        match Special::parse(b"papersize=210mm,297mm") {
+            Special::PaperSize { width, height } => {
+                assert!((width - 595.276).abs() < 0.001);
+                assert!((height - 841.89).abs() < 0.001);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn others() {
+        round_trip(
+            "pdf:pagesize width 595bp height 842bp",
+            Special::Pdf {
+                command: "pagesize".into(),
+                args: "width 595bp height 842bp".into(),
+            },
+        );
+        round_trip(
+            "html:<a href=\"#x\">",
+            Special::Html("<a href=\"#x\">".into()),
+        );
+        round_trip(
+            "src:12 main.tex",
+            Special::Source {
+                line: 12,
+                file: Some("main.tex".into()),
+            },
+        );
+        assert_eq!(
+            Special::parse(b"src:7chapter.tex"),
+            Special::Source {
+                line: 7,
+                file: Some("chapter.tex".into())
+            }
+        );
+        round_trip(
+            "src:3",
+            Special::Source {
+                line: 3,
+                file: None,
+            },
+        );
+        round_trip(
+            "em:line 1,2",
+            Special::Em {
+                command: "line".into(),
+                args: "1,2".into(),
+            },
+        );
+        round_trip(
+            "em:moveto",
+            Special::Em {
+                command: "moveto".into(),
+                args: String::new(),
+            },
+        );
+        round_trip("weird stuff", Special::Unknown(b"weird stuff".to_vec()));
+        assert_eq!(Special::parse(b"\xff"), Special::Unknown(vec![0xff]));
+    }
+
+    #[test]
+    fn tpic() {
+        round_trip("pn 8", Special::Tpic(Tpic::PenSize(8)));
+        round_trip("pa 100 -20", Special::Tpic(Tpic::Path(100, -20)));
+        round_trip("fp", Special::Tpic(Tpic::FlushPath));
+        round_trip("da 0.05", Special::Tpic(Tpic::Dashed(0.05)));
+        round_trip("sp", Special::Tpic(Tpic::Spline(None)));
+        round_trip("sp -0.1", Special::Tpic(Tpic::Spline(Some(-0.1))));
+        round_trip(
+            "ar 0 0 100 50 0 3.5",
+            Special::Tpic(Tpic::Arc {
+                x: 0,
+                y: 0,
+                rx: 100,
+                ry: 50,
+                start: 0.0,
+                end: 3.5,
+            }),
+        );
+        round_trip("sh 0.5", Special::Tpic(Tpic::Shade(Some(0.5))));
+        round_trip("bk", Special::Tpic(Tpic::Black));
+        assert!(matches!(Special::parse(b"pa 1"), Special::Unknown(_)));
+    }
+}
@@ -0,0 +1,786 @@
+//! Reading and writing dvi files over async streams
+//!
+//! [`AsyncDviReader`] reads instructions or whole pages from an `AsyncRead` as they arrive, and
+//! [`AsyncDviWriter`] writes them to an `AsyncWrite`, working out the pointers between pages as
+//! it goes. Both use the `futures-io` traits, so they work with any runtime. With the `tokio`
+//! feature, [`Tokio`] adapts tokio's readers and writers to them.
+//!
+//! The reader keeps a buffer of what it has read but not yet parsed. When the parser needs more
+//! input than is in the buffer, the reader reads another chunk and tries again, so an instruction
+//! can be split across reads in any way. Give the reader [`ParseLimits`] with `limits` to stop it
+//! buffering an oversized special or reading without end from an untrusted stream.
+//!
+//! [`AsyncDviReader`]: ./struct.AsyncDviReader.html
+//! [`AsyncDviWriter`]: ./struct.AsyncDviWriter.html
+//! [`Tokio`]: ./struct.Tokio.html
+//! [`ParseLimits`]: ../limits/struct.ParseLimits.html
+
+use crate::{
+    document::{self, Document, Page, Postamble, Preamble},
+    limits::Tally,
+    FontDef, Instruction, ParseLimits,
+};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::{
+    error, fmt,
+    future::poll_fn,
+    io::{self, ErrorKind},
+    pin::Pin,
+};
+
+/// How much to read at a time
+const CHUNK: usize = 8192;
+
+/// Things that can go wrong when reading a stream
+#[derive(Debug)]
+pub enum Error {
+    /// Reading failed
+    Io(io::Error),
+    /// What was read isn't a valid dvi file
+    Dvi(document::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Dvi(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Dvi(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<document::Error> for Error {
+    fn from(e: document::Error) -> Self {
+        Error::Dvi(e)
+    }
+}
+
+/// Reads instructions or pages from an `AsyncRead`
+#[derive(Debug)]
+pub struct AsyncDviReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// Where reads go before they are added to `buffer`
+    chunk: Vec<u8>,
+    /// How much of `buffer` has been parsed
+    start: usize,
+    /// The offset in the file of the start of `buffer`
+    offset: usize,
+    eof: bool,
+    /// Whether the `PostPost` has been read
+    finished: bool,
+    preamble: Option<Preamble>,
+    postamble: Option<Postamble>,
+    tally: Tally,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDviReader<R> {
+    pub fn new(reader: R) -> Self {
+        AsyncDviReader {
+            reader,
+            buffer: Vec::new(),
+            chunk: vec![0; CHUNK],
+            start: 0,
+            offset: 0,
+            eof: false,
+            finished: false,
+            preamble: None,
+            postamble: None,
+            tally: Tally::new(ParseLimits::unlimited()),
+        }
+    }
+
+    /// Fail with `document::Error::Limit` when the stream goes over one of the limits
+    ///
+    /// There are no limits by default.
+    pub fn limits(mut self, limits: ParseLimits) -> Self {
+        self.tally = Tally::new(limits);
+        self
+    }
+
+    /// The offset in the file of the next instruction
+    pub fn offset(&self) -> usize {
+        self.offset + self.start
+    }
+
+    /// The preamble, once `page` has read it
+    pub fn preamble(&self) -> Option<&Preamble> {
+        self.preamble.as_ref()
+    }
+
+    /// The postamble, once `page` has read past the last page
+    pub fn postamble(&self) -> Option<&Postamble> {
+        self.postamble.as_ref()
+    }
+
+    /// Read the next instruction, or `None` at the end of the file
+    ///
+    /// Nothing is parsed after the `PostPost` and its padding, so the stream can carry on with
+    /// something else: `into_parts` gives back whatever has been read past it. The padding is
+    /// taken to end at the first byte that isn't 223, or once at least 4 have arrived, so any
+    /// more that arrive later are left in the stream.
+    ///
+    /// This is cancel safe: if the future is dropped before it finishes, no input is lost and
+    /// the next call carries on where it left off. `page` and `document` are not, since they
+    /// lose the instructions they have read so far.
+    pub async fn instruction(&mut self) -> Result<Option<Instruction>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        loop {
+            let input = &self.buffer[self.start..];
+            let offset = self.offset();
+            let limit = |limit| document::Error::Limit(limit, offset);
+            self.tally.check_payload(input).map_err(limit)?;
+            match Instruction::parse(input) {
+                // the padding may not all have arrived, but TeX always writes at least 4 bytes of
+                // it. Waiting for the file to be a multiple of 4 bytes long could wait forever
+                // on an open stream if it isn't.
+                Ok((rest, Instruction::PostPost { two_two_three, .. }))
+                    if rest.is_empty() && !self.eof && two_two_three < 4 =>
+                {
+                    self.fill().await?
+                }
+                Ok((rest, instruction)) => {
+                    self.tally.count(&instruction).map_err(limit)?;
+                    self.start += input.len() - rest.len();
+                    self.finished = matches!(instruction, Instruction::PostPost { .. });
+                    return Ok(Some(instruction));
+                }
+                Err(nom::Err::Incomplete(_)) if !self.eof => self.fill().await?,
+                Err(nom::Err::Incomplete(_)) if input.is_empty() => return Ok(None),
+                Err(nom::Err::Incomplete(_)) => return Err(document::Error::Truncated.into()),
+                Err(_) => return Err(document::Error::Parse(self.offset()).into()),
+            }
+        }
+    }
+
+    /// Read the next page, or `None` after the last one
+    ///
+    /// The preamble is read before the first page, and the postamble after the last one. Font
+    /// definitions between pages are moved to the start of the following page, as
+    /// `Document::parse` does.
+    pub async fn page(&mut self) -> Result<Option<Page>, Error> {
+        let mut defs = Vec::new();
+        loop {
+            let offset = self.offset();
+            let instruction = match self.instruction().await? {
+                Some(instruction) => instruction,
+                None if self.postamble.is_some() => return Ok(None),
+                None => return Err(document::Error::Truncated.into()),
+            };
+            match instruction {
+                Instruction::Pre {
+                    format,
+                    numerator,
+                    denominator,
+                    magnification,
+                    comment,
+                } if self.preamble.is_none() => {
+                    self.preamble = Some(Preamble {
+                        format,
+                        numerator,
+                        denominator,
+                        magnification,
+                        comment,
+                    })
+                }
+                _ if self.preamble.is_none() => return Err(document::Error::Unexpected(0).into()),
+                Instruction::Nop => (),
+                Instruction::FontDef(def) => defs.push((offset, def)),
+                Instruction::Bop(counters, _) => {
+                    let mut instructions = defs
+                        .into_iter()
+                        .map(|(_, def)| Instruction::FontDef(def))
+                        .collect();
+                    self.page_body(&mut instructions).await?;
+                    return Ok(Some(Page {
+                        counters,
+                        instructions,
+                    }));
+                }
+                Instruction::Post {
+                    tallest_height,
+                    widest_width,
+                    max_stack_depth,
+                    ..
+                } => {
+                    let fonts = self.postamble_fonts().await?;
+                    document::check_trailing_font_defs(&defs, &fonts)?;
+                    self.postamble = Some(Postamble {
+                        tallest_height,
+                        widest_width,
+                        max_stack_depth,
+                        fonts,
+                    });
+                    return Ok(None);
+                }
+                _ => return Err(document::Error::Unexpected(offset).into()),
+            }
+        }
+    }
+
+    /// Read the rest of the file into a `Document`
+    ///
+    /// Pages that have already been read with `page` are not included.
+    pub async fn document(mut self) -> Result<Document, Error> {
+        let mut pages = Vec::new();
+        while let Some(page) = self.page().await? {
+            pages.push(page);
+        }
+        match (self.preamble, self.postamble) {
+            (Some(preamble), Some(postamble)) => Ok(Document {
+                preamble,
+                pages,
+                postamble,
+            }),
+            _ => Err(document::Error::Truncated.into()),
+        }
+    }
+
+    /// The reader, dropping anything that has been read from it but not parsed
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// The reader, and what has been read from it but not parsed
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        let mut buffer = self.buffer;
+        buffer.drain(..self.start);
+        (self.reader, buffer)
+    }
+
+    /// Read the instructions of a page up to its `Eop`
+    async fn page_body(&mut self, instructions: &mut Vec<Instruction>) -> Result<(), Error> {
+        loop {
+            let offset = self.offset();
+            match self.instruction().await? {
+                Some(Instruction::Eop) => return Ok(()),
+                Some(Instruction::Bop(..))
+                | Some(Instruction::Pre { .. })
+                | Some(Instruction::Post { .. })
+                | Some(Instruction::PostPost { .. }) => {
+                    return Err(document::Error::Unexpected(offset).into())
+                }
+                Some(instruction) => instructions.push(instruction),
+                None => return Err(document::Error::Truncated.into()),
+            }
+        }
+    }
+
+    /// Read the font definitions in the postamble, and the `PostPost`
+    async fn postamble_fonts(&mut self) -> Result<Vec<FontDef>, Error> {
+        let mut fonts = Vec::new();
+        loop {
+            let offset = self.offset();
+            match self.instruction().await? {
+                Some(Instruction::FontDef(def)) => fonts.push(def),
+                Some(Instruction::Nop) => (),
+                Some(Instruction::PostPost { .. }) => return Ok(fonts),
+                Some(_) => return Err(document::Error::Unexpected(offset).into()),
+                None => return Err(document::Error::Truncated.into()),
+            }
+        }
+    }
+
+    /// Read another chunk into the buffer
+    ///
+    /// The buffer is only changed once a read has finished, so dropping the future part way
+    /// leaves the reader as it was.
+    async fn fill(&mut self) -> io::Result<()> {
+        if self.start > self.buffer.len() / 2 {
+            self.buffer.drain(..self.start);
+            self.offset += self.start;
+            self.start = 0;
+        }
+        let (reader, chunk) = (&mut self.reader, &mut self.chunk);
+        let read = loop {
+            match poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, chunk)).await {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                read => break read?,
+            }
+        };
+        self.buffer.extend_from_slice(&self.chunk[..read]);
+        self.eof = read == 0;
+        Ok(())
+    }
+}
+
+/// Writes instructions or pages to an `AsyncWrite`
+///
+/// Write the preamble first, then the pages, then call `finish` to write the postamble. The
+/// pointers in `Bop` instructions are filled in as they are written.
+///
+/// Instructions are collected in a buffer, which is written out at the end of each page, when it
+/// grows past a few kilobytes, and by `finish`.
+#[derive(Debug)]
+pub struct AsyncDviWriter<W> {
+    writer: W,
+    /// What has been dumped but not yet written
+    buffer: Vec<u8>,
+    /// How many bytes have been written from the buffer
+    count: usize,
+    /// Where the last `Bop` was written, or -1
+    last_bop: i32,
+    pages: u16,
+    preamble: Option<Preamble>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncDviWriter<W> {
+    pub fn new(writer: W) -> Self {
+        AsyncDviWriter {
+            writer,
+            buffer: Vec::new(),
+            count: 0,
+            last_bop: -1,
+            pages: 0,
+            preamble: None,
+        }
+    }
+
+    /// Write an instruction
+    ///
+    /// The pointer of a `Bop` is replaced by the position of the previous one. `Post` and
+    /// `PostPost` are written by `finish`, and are an error here.
+    pub async fn instruction(&mut self, instruction: &Instruction) -> io::Result<()> {
+        match instruction {
+            Instruction::Pre {
+                format,
+                numerator,
+                denominator,
+                magnification,
+                comment,
+            } if self.position() == 0 => {
+                self.preamble = Some(Preamble {
+                    format: *format,
+                    numerator: *numerator,
+                    denominator: *denominator,
+                    magnification: *magnification,
+                    comment: comment.clone(),
+                })
+            }
+            Instruction::Pre { .. } => return Err(invalid("the preamble must come first")),
+            Instruction::Post { .. } | Instruction::PostPost { .. } => {
+                return Err(invalid("the postamble is written by finish"))
+            }
+            _ if self.position() == 0 => return Err(invalid("the preamble must come first")),
+            Instruction::Bop(counters, _) => {
+                let bop = self.position() as i32;
+                Instruction::Bop(*counters, self.last_bop).dump(&mut self.buffer)?;
+                self.last_bop = bop;
+                self.pages = self.pages.saturating_add(1);
+                return Ok(());
+            }
+            _ => (),
+        }
+        instruction.dump(&mut self.buffer)?;
+        if matches!(instruction, Instruction::Eop) || self.buffer.len() >= CHUNK {
+            self.flush_buffer().await?;
+        }
+        Ok(())
+    }
+
+    /// Write the preamble
+    pub async fn preamble(&mut self, preamble: &Preamble) -> io::Result<()> {
+        self.instruction(&Instruction::Pre {
+            format: preamble.format,
+            numerator: preamble.numerator,
+            denominator: preamble.denominator,
+            magnification: preamble.magnification,
+            comment: preamble.comment.clone(),
+        })
+        .await
+    }
+
+    /// Write a page, from its `Bop` to its `Eop`
+    pub async fn page(&mut self, page: &Page) -> io::Result<()> {
+        self.instruction(&Instruction::Bop(page.counters, -1))
+            .await?;
+        for instruction in &page.instructions {
+            self.instruction(instruction).await?;
+        }
+        self.instruction(&Instruction::Eop).await
+    }
+
+    /// Write the postamble and flush the writer, returning it
+    pub async fn finish(mut self, postamble: &Postamble) -> io::Result<W> {
+        let preamble = match &self.preamble {
+            Some(preamble) => preamble,
+            None => return Err(invalid("the preamble must come first")),
+        };
+        document::dump_postamble(
+            preamble,
+            postamble,
+            self.pages,
+            self.last_bop,
+            self.position(),
+            &mut self.buffer,
+        )?;
+        self.flush_buffer().await?;
+        let writer = &mut self.writer;
+        poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await?;
+        Ok(self.writer)
+    }
+
+    /// Write a whole document, returning the writer
+    pub async fn document(mut self, document: &Document) -> io::Result<W> {
+        self.preamble(&document.preamble).await?;
+        for page in &document.pages {
+            self.page(page).await?;
+        }
+        self.finish(&document.postamble).await
+    }
+
+    /// The offset in the file of the next instruction
+    fn position(&self) -> usize {
+        self.count + self.buffer.len()
+    }
+
+    /// Write everything in the buffer
+    ///
+    /// What has been written is removed from the buffer after every write, so dropping the
+    /// future part way doesn't write anything twice.
+    async fn flush_buffer(&mut self) -> io::Result<()> {
+        while !self.buffer.is_empty() {
+            let (writer, buffer) = (&mut self.writer, &self.buffer);
+            match poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buffer)).await {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.buffer.drain(..n);
+                    self.count += n;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// Adapts a tokio reader or writer to the `futures-io` traits
+///
+/// For example `AsyncDviReader::new(Tokio(socket))` reads from a `tokio::net::TcpStream`.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct Tokio<T>(pub T);
+
+#[cfg(feature = "tokio")]
+impl<T: tokio::io::AsyncRead + Unpin> AsyncRead for Tokio<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        Pin::new(&mut self.0)
+            .poll_read(cx, &mut buf)
+            .map_ok(|()| buf.filled().len())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: tokio::io::AsyncWrite + Unpin> AsyncWrite for Tokio<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limit;
+    use std::{
+        future::Future,
+        task::{Context, Poll, Waker},
+    };
+
+    /// Run a future to completion, for readers and writers that never wait on anything
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// A reader that gives a few bytes at a time, and isn't ready every other time
+    struct Trickle<'a> {
+        input: &'a [u8],
+        ready: bool,
+        /// Never reach the end, like a socket that the other side keeps open
+        open: bool,
+    }
+
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready || (self.open && self.input.is_empty()) {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = buf.len().min(self.input.len()).min(3);
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    fn trickle(input: &[u8]) -> AsyncDviReader<Trickle<'_>> {
+        AsyncDviReader::new(Trickle {
+            input,
+            ready: false,
+            open: false,
+        })
+    }
+
+    #[test]
+    fn read() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let document = Document::parse(&input).unwrap();
+        let mut reader = trickle(&input);
+        let first = block_on(reader.page()).unwrap().unwrap();
+        assert_eq!(reader.preamble(), Some(&document.preamble));
+        assert_eq!(first, document.pages[0]);
+        assert_eq!(
+            block_on(reader.page()).unwrap().as_ref(),
+            document.pages.get(1)
+        );
+        assert_eq!(block_on(reader.page()).unwrap(), None);
+        assert_eq!(reader.postamble(), Some(&document.postamble));
+        assert_eq!(reader.offset(), input.len());
+
+        assert_eq!(block_on(trickle(&input).document()).unwrap(), document);
+        // including all of the padding after the PostPost
+        let mut reader = trickle(&input);
+        let mut instructions = Vec::new();
+        while let Some(instruction) = block_on(reader.instruction()).unwrap() {
+            instructions.push(instruction);
+        }
+        let expected = crate::Instructions::new(&input)
+            .map(|instruction| Instruction::from(instruction.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(instructions, expected);
+    }
+
+    #[test]
+    fn cancel() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let mut reader = trickle(&input);
+        // give up on the first instruction while it is waiting for input, as a timeout would
+        {
+            let mut future = Box::pin(reader.instruction());
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(
+            block_on(reader.document()).unwrap(),
+            Document::parse(&input).unwrap()
+        );
+    }
+
+    #[test]
+    fn open_stream() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let reader = AsyncDviReader::new(Trickle {
+            input: &input,
+            ready: false,
+            open: true,
+        });
+        assert_eq!(
+            block_on(reader.document()).unwrap(),
+            Document::parse(&input).unwrap()
+        );
+    }
+
+    #[test]
+    fn after_the_end() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let mut followed = input.clone();
+        followed.extend_from_slice(b"more");
+        let mut reader = AsyncDviReader::new(&followed[..]);
+        while block_on(reader.instruction()).unwrap().is_some() {}
+        assert_eq!(reader.offset(), input.len());
+        let (rest, buffered) = reader.into_parts();
+        assert_eq!([&buffered[..], rest].concat(), b"more");
+
+        // padding that leaves the file a length TeX wouldn't write mustn't wait forever
+        let mut padded = input.clone();
+        padded.push(223);
+        let reader = AsyncDviReader::new(Trickle {
+            input: &padded,
+            ready: false,
+            open: true,
+        });
+        assert_eq!(
+            block_on(reader.document()).unwrap(),
+            Document::parse(&input).unwrap()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        match block_on(trickle(&input[..100]).document()) {
+            Err(Error::Dvi(document::Error::Truncated)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut broken = input.clone();
+        broken[42] = 250;
+        match block_on(trickle(&broken).document()) {
+            Err(Error::Dvi(document::Error::Parse(42))) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        // a font defined after the last page that the postamble doesn't know about
+        let post = 1826;
+        let mut trailing = input[..post].to_vec();
+        let def = FontDef {
+            number: 99,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        };
+        Instruction::FontDef(def).dump(&mut trailing).unwrap();
+        trailing.extend_from_slice(&input[post..]);
+        match block_on(trickle(&trailing).document()) {
+            Err(Error::Dvi(document::Error::Unexpected(1826))) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let limits = ParseLimits {
+            max_pages: 1,
+            ..ParseLimits::default()
+        };
+        match block_on(trickle(&input).limits(limits).document()) {
+            Err(Error::Dvi(document::Error::Limit(Limit::Pages, offset))) => {
+                assert_eq!(input[offset], 139)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let limits = ParseLimits {
+            max_stack_depth: 4,
+            ..ParseLimits::default()
+        };
+        match block_on(trickle(&input).limits(limits).document()) {
+            Err(Error::Dvi(document::Error::Limit(Limit::StackDepth, offset))) => {
+                assert_eq!(input[offset], 141)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(block_on(trickle(&input).limits(ParseLimits::default()).document()).is_ok());
+
+        // only the length of a long special is read before giving up
+        let mut reader =
+            AsyncDviReader::new(&[242, 0x7f, 0xff, 0xff, 0xff][..]).limits(ParseLimits::default());
+        match block_on(reader.instruction()) {
+            Err(Error::Dvi(document::Error::Limit(Limit::SpecialLength, 0))) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    /// Counts the writes made to it
+    #[derive(Default)]
+    struct Writes {
+        out: Vec<u8>,
+        writes: usize,
+    }
+
+    impl AsyncWrite for Writes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            self.out.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn write() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let document = Document::parse(&input).unwrap();
+        let out = block_on(AsyncDviWriter::new(Vec::new()).document(&document)).unwrap();
+        assert_eq!(out, input);
+        // one write for each page, and one for the postamble
+        let out = block_on(AsyncDviWriter::new(Writes::default()).document(&document)).unwrap();
+        assert_eq!(out.out, input);
+        assert_eq!(out.writes, document.pages.len() + 1);
+
+        let mut writer = AsyncDviWriter::new(Vec::new());
+        assert!(block_on(writer.page(&document.pages[0])).is_err());
+        block_on(writer.preamble(&document.preamble)).unwrap();
+        assert!(block_on(writer.instruction(&Instruction::PostPost {
+            post_pointer: 0,
+            ident: 2,
+            two_two_three: 4
+        }))
+        .is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio() {
+        let input = std::fs::read("tests/source/main.dvi").unwrap();
+        let document = block_on(AsyncDviReader::new(Tokio(&input[..])).document()).unwrap();
+        let out = block_on(AsyncDviWriter::new(Tokio(Vec::new())).document(&document)).unwrap();
+        assert_eq!(out.0, input);
+    }
+}
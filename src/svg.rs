@@ -0,0 +1,245 @@
+//! Render dvi pages as SVG images
+//!
+//! Rules are drawn as `<rect>` elements and characters as `<text>` elements, using a font family
+//! chosen from the name of the dvi font. The result looks right when the viewer has fonts
+//! installed under those family names (for example the Computer Modern fonts shipped by most TeX
+//! distributions in OpenType format).
+//!
+//! All lengths in the output are in big points (1/72 inch), the default unit of SVG.
+
+use crate::{
+    interpreter::{Device, Error, FontMetrics, Interpreter},
+    util::{bp_per_unit, num},
+    FontDef,
+};
+use std::{collections::HashMap, fmt::Write};
+
+/// The distance in big points from the edges of the paper to the reference point of the page
+const ORIGIN: f64 = 72.0;
+
+/// A `Device` that draws each page as a separate SVG document
+#[derive(Debug, Clone)]
+pub struct Svg {
+    families: HashMap<Vec<u8>, String>,
+    page_width: f64,
+    page_height: f64,
+    /// Big points per dvi unit
+    scale: f64,
+    pages: Vec<String>,
+    current: String,
+}
+
+impl Default for Svg {
+    fn default() -> Self {
+        Svg {
+            families: HashMap::new(),
+            // US letter, TeX's default
+            page_width: 612.0,
+            page_height: 792.0,
+            // TeX's usual units, in case there is no preamble
+            scale: bp_per_unit(25_400_000, 473_628_672, 1000),
+            pages: Vec::new(),
+            current: String::new(),
+        }
+    }
+}
+
+impl Svg {
+    /// Create a renderer for US letter paper
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `family` for text in the font with file name `filename`
+    ///
+    /// By default the file name of the font is used as the family.
+    pub fn font_family(mut self, filename: impl Into<Vec<u8>>, family: impl Into<String>) -> Self {
+        self.families
+            .insert(filename.into(), escape(&family.into()));
+        self
+    }
+
+    /// Set the size of the paper in big points
+    pub fn page_size(mut self, width: f64, height: f64) -> Self {
+        self.page_width = width;
+        self.page_height = height;
+        self
+    }
+
+    /// The pages that have been drawn so far
+    pub fn pages(&self) -> &[String] {
+        &self.pages
+    }
+
+    /// Take the finished pages
+    pub fn into_pages(self) -> Vec<String> {
+        self.pages
+    }
+
+    fn x(&self, h: i32) -> f64 {
+        ORIGIN + f64::from(h) * self.scale
+    }
+
+    fn y(&self, v: i32) -> f64 {
+        ORIGIN + f64::from(v) * self.scale
+    }
+
+    fn family(&self, font: &FontDef) -> String {
+        match self.families.get(&font.filename) {
+            Some(family) => family.clone(),
+            None => escape(&String::from_utf8_lossy(&font.filename)),
+        }
+    }
+}
+
+impl Device for Svg {
+    fn preamble(&mut self, numerator: u32, denominator: u32, magnification: u32) {
+        self.scale = bp_per_unit(numerator, denominator, magnification);
+    }
+
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.current.clear();
+        // writing to a string can't fail
+        let _ = write!(
+            self.current,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" \
+             width=\"{w}pt\" height=\"{h}pt\" viewBox=\"0 0 {w} {h}\">\n",
+            w = num(self.page_width),
+            h = num(self.page_height),
+        );
+    }
+
+    fn end_page(&mut self) {
+        self.current.push_str("</svg>\n");
+        self.pages.push(self.current.clone());
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, ch: u32, _: i32) {
+        let size = f64::from(font.scale_factor) * self.scale;
+        let _ = writeln!(
+            self.current,
+            "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" \
+             xml:space=\"preserve\">{}</text>",
+            num(self.x(h)),
+            num(self.y(v)),
+            self.family(font),
+            num(size),
+            char_ref(ch),
+        );
+    }
+
+    fn rule(&mut self, h: i32, v: i32, height: i32, width: i32) {
+        let _ = writeln!(
+            self.current,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>",
+            num(self.x(h)),
+            num(self.y(v.saturating_sub(height))),
+            num(f64::from(width) * self.scale),
+            num(f64::from(height) * self.scale),
+        );
+    }
+}
+
+/// Render every page of a dvi file, returning one SVG document per page
+pub fn render<M: FontMetrics>(input: &[u8], metrics: M) -> Result<Vec<String>, Error> {
+    let mut svg = Svg::new();
+    Interpreter::new(metrics).run(input, &mut svg)?;
+    Ok(svg.into_pages())
+}
+
+/// Escape text for use in XML
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Write a character code as XML text
+///
+/// Codes that aren't allowed in XML (most of the C0 control characters, which TeX fonts use for
+/// things like Greek capitals) are moved into the private use area starting at U+E000.
+fn char_ref(ch: u32) -> String {
+    match std::char::from_u32(ch) {
+        Some(c @ ' '..='~') => escape(c.encode_utf8(&mut [0; 4])),
+        Some(c) if !c.is_control() => format!("&#x{:x};", ch),
+        _ => format!("&#x{:x};", 0xe000 + (ch & 0xfff)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Instruction;
+
+    struct Fixed;
+
+    impl FontMetrics for Fixed {
+        fn char_width(&self, _: &FontDef, _: u32) -> Option<i32> {
+            Some(0x10000)
+        }
+    }
+
+    #[test]
+    fn page() {
+        let program = [
+            Instruction::Pre {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            Instruction::FontDef(FontDef {
+                number: 0,
+                checksum: 0,
+                scale_factor: 10 << 16,
+                design_size: 10 << 16,
+                directory: None,
+                filename: Vec::from("cmr10"),
+            }),
+            Instruction::Bop([1, 0, 0, 0, 0, 0, 0, 0, 0, 0], -1),
+            Instruction::Font(0),
+            Instruction::Set(b'<'.into()),
+            Instruction::Set(0x0b),
+            Instruction::SetRule(0x10000, 0x20000),
+            Instruction::Eop,
+        ];
+        let mut bytes = Vec::new();
+        for instruction in &program {
+            instruction.dump(&mut bytes).unwrap();
+        }
+        let mut svg = Svg::new().font_family("cmr10", "CMU Serif");
+        Interpreter::new(Fixed).run(&bytes, &mut svg).unwrap();
+        let pages = svg.into_pages();
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert!(page.contains("font-family=\"CMU Serif\""));
+        assert!(page.contains(">&lt;</text>"));
+        assert!(page.contains(">&#xe00b;</text>"));
+        // one point is slightly less than one big point
+        assert!(page.contains("<rect x=\"73.993\" y=\"71.004\" width=\"1.993\" height=\"0.996\"/>"));
+        assert!(page.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn extreme_positions() {
+        let mut svg = Svg::new();
+        svg.preamble(25_400_000, 473_628_672, 1000);
+        svg.begin_page(&[0; 10]);
+        // positions come from the file, so these mustn't overflow
+        svg.rule(0, i32::MIN + 5, 100, 100);
+        svg.end_page();
+        let y = num(ORIGIN + f64::from(i32::MIN) * svg.scale);
+        assert!(svg.into_pages()[0].contains(&format!("y=\"{}\"", y)));
+    }
+}
@@ -0,0 +1,273 @@
+//! Extract plain text from dvi pages
+//!
+//! Dvi files don't contain words or lines, only characters at positions, so the text has to be
+//! rebuilt from the layout. TeX sets each line from left to right before moving down the page, so
+//! characters are taken in the order they are typeset:
+//!
+//!  - a horizontal gap wider than half an interword space (as given by the font's TFM file) becomes
+//!    a space,
+//!  - a move of the baseline by more than half the font size starts a new line, smaller moves are
+//!    taken to be sub- and superscripts,
+//!  - character codes are turned into Unicode using the font's [`Encoding`], and accents are
+//!    attached to the letter that follows them as combining characters.
+//!
+//! [`Encoding`]: ../encoding/enum.Encoding.html
+
+use crate::{
+    document::Document,
+    encoding::Encoding,
+    interpreter::{Device, Error},
+    tfm::Tfm,
+    util::scale_fix_word,
+    FontDef,
+};
+use std::{collections::HashMap, convert::TryFrom};
+
+/// A `Device` that collects the text on each page
+#[derive(Debug, Clone)]
+pub struct Text<'a> {
+    fonts: &'a HashMap<u32, Tfm>,
+    encodings: HashMap<Vec<u8>, Encoding>,
+    pages: Vec<String>,
+    current: String,
+    /// Where the last character ended, and its baseline
+    last: Option<(i32, i32)>,
+    /// An accent waiting for its letter, as combining and spacing forms
+    accent: Option<(char, &'static str)>,
+}
+
+impl<'a> Text<'a> {
+    /// Create an extractor using the metrics in `fonts`, indexed by font number
+    pub fn new(fonts: &'a HashMap<u32, Tfm>) -> Self {
+        Text {
+            fonts,
+            encodings: HashMap::new(),
+            pages: Vec::new(),
+            current: String::new(),
+            last: None,
+            accent: None,
+        }
+    }
+
+    /// Use `encoding` for the font with file name `filename`
+    ///
+    /// By default the encoding is guessed from the file name.
+    pub fn encoding(mut self, filename: impl Into<Vec<u8>>, encoding: Encoding) -> Self {
+        self.encodings.insert(filename.into(), encoding);
+        self
+    }
+
+    /// The text of the pages finished so far
+    pub fn pages(&self) -> &[String] {
+        &self.pages
+    }
+
+    /// Take the text of the finished pages
+    pub fn into_pages(self) -> Vec<String> {
+        self.pages
+    }
+
+    /// The smallest gap that counts as a space between words, in dvi units
+    fn space(&self, font: &FontDef) -> i32 {
+        let space = self
+            .fonts
+            .get(&font.number)
+            .and_then(Tfm::space)
+            .and_then(|space| scale_fix_word(space, font.scale_factor))
+            .unwrap_or_else(|| i32::try_from(font.scale_factor).unwrap_or(i32::MAX) / 3);
+        space / 2
+    }
+
+    /// Write out an accent that never found its letter
+    fn flush_accent(&mut self) {
+        if let Some((_, spacing)) = self.accent.take() {
+            self.current.push_str(spacing);
+        }
+    }
+}
+
+impl<'a> Device for Text<'a> {
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.current.clear();
+        self.last = None;
+        self.accent = None;
+    }
+
+    fn end_page(&mut self) {
+        self.flush_accent();
+        let text = self.current.trim_end().to_string();
+        self.pages.push(text);
+    }
+
+    fn char(&mut self, h: i32, v: i32, font: &FontDef, ch: u32, width: i32) {
+        let encoding = match self.encodings.get(&font.filename) {
+            Some(&encoding) => encoding,
+            None => Encoding::for_font(&font.filename),
+        };
+        if let Some((last_h, last_v)) = self.last {
+            let size = i64::from(font.scale_factor);
+            if (i64::from(v) - i64::from(last_v)).abs() > size / 2 {
+                self.flush_accent();
+                self.current.push('\n');
+            } else if i64::from(h) - i64::from(last_h) > i64::from(self.space(font)) {
+                self.flush_accent();
+                self.current.push(' ');
+            }
+        }
+        let text = encoding.decode(ch).unwrap_or("\u{fffd}");
+        if let Some(combining) = encoding.accent(ch) {
+            // the accent is drawn first, then TeX moves back to draw the letter under it
+            self.flush_accent();
+            self.accent = Some((combining, text));
+            return;
+        }
+        self.current.push_str(text);
+        if let Some((combining, _)) = self.accent.take() {
+            self.current.push(combining);
+        }
+        self.last = Some((h.saturating_add(width), v));
+    }
+}
+
+/// Extract the text of every page of a document
+///
+/// `fonts` are the TFM files for each font number.
+pub fn extract(document: &Document, fonts: &HashMap<u32, Tfm>) -> Result<Vec<String>, Error> {
+    let mut text = Text::new(fonts);
+    document.run(fonts, &mut text)?;
+    Ok(text.into_pages())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::{Page, Postamble, Preamble},
+        tfm::tests::tfm_bytes,
+        Instruction,
+    };
+
+    fn font(number: u32, filename: &str) -> FontDef {
+        FontDef {
+            number,
+            checksum: 0,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from(filename),
+        }
+    }
+
+    fn document(instructions: Vec<Instruction>) -> Document {
+        Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::new(),
+            },
+            pages: vec![Page {
+                counters: [1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                instructions,
+            }],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font(0, "cmr10"), font(1, "ecrm1000")],
+            },
+        }
+    }
+
+    /// The test font, with every character 5pt wide and spaces of 3.33pt
+    fn fonts() -> HashMap<u32, Tfm> {
+        let (_, mut tfm) = Tfm::parse(&tfm_bytes()).unwrap();
+        let a = tfm.chars[&97];
+        tfm.chars = (0..256).map(|code| (code, a)).collect();
+        let mut fonts = HashMap::new();
+        fonts.insert(0, tfm.clone());
+        fonts.insert(1, tfm);
+        fonts
+    }
+
+    fn set(text: &[u8]) -> Vec<Instruction> {
+        text.iter().map(|&c| Instruction::Set(c.into())).collect()
+    }
+
+    #[test]
+    fn words_and_lines() {
+        let mut instructions = vec![Instruction::Font(0), Instruction::Push];
+        instructions.extend(set(b"a"));
+        instructions.push(Instruction::Set(12));
+        instructions.extend(set(b"ne"));
+        // an interword space, then a small kern that shouldn't become one
+        instructions.push(Instruction::Right(3 << 16));
+        instructions.extend(set(b"da"));
+        instructions.push(Instruction::Right(1 << 15));
+        instructions.extend(set(b"y"));
+        // a superscript stays on the line
+        instructions.push(Instruction::Down(-3 << 16));
+        instructions.extend(set(b"2"));
+        instructions.push(Instruction::Pop);
+        instructions.push(Instruction::Down(12 << 16));
+        instructions.extend(set(b"caf"));
+        // \'e: the accent, a kern back to where it started, then the letter
+        instructions.extend(set(&[19]));
+        instructions.push(Instruction::Right(-5 << 16));
+        instructions.extend(set(b"e"));
+        let document = document(instructions);
+        let pages = extract(&document, &fonts()).unwrap();
+        assert_eq!(pages, vec!["afine day2\ncafe\u{301}"]);
+    }
+
+    #[test]
+    fn encodings() {
+        let mut instructions = vec![Instruction::Font(1)];
+        instructions.extend(set(&[28, 0xe9, 0x22]));
+        instructions.push(Instruction::Font(0));
+        instructions.extend(set(&[0x22]));
+        let fonts = fonts();
+        let mut text = Text::new(&fonts);
+        document(instructions.clone())
+            .run(&fonts, &mut text)
+            .unwrap();
+        assert_eq!(text.pages(), ["fié\"”"]);
+
+        let mut text = Text::new(&fonts).encoding("cmr10", Encoding::Ot1Typewriter);
+        document(instructions).run(&fonts, &mut text).unwrap();
+        assert_eq!(text.pages(), ["fié\"\""]);
+    }
+
+    #[test]
+    fn space_without_metrics() {
+        let fonts = HashMap::new();
+        let text = Text::new(&fonts);
+        assert_eq!(text.space(&font(0, "cmr10")), (10 << 16) / 3 / 2);
+        let huge = FontDef {
+            scale_factor: u32::MAX,
+            ..font(0, "cmr10")
+        };
+        assert_eq!(text.space(&huge), i32::MAX / 3 / 2);
+    }
+
+    #[test]
+    fn scale_too_large() {
+        let mut document = document(vec![Instruction::Font(0), Instruction::Set(b'a'.into())]);
+        document.postamble.fonts[0].scale_factor = 1 << 27;
+        assert_eq!(
+            extract(&document, &fonts()),
+            Err(Error::MissingWidth { font: 0, ch: 97 })
+        );
+    }
+
+    #[test]
+    fn extreme_positions() {
+        let mut instructions = vec![Instruction::Font(0), Instruction::Right(i32::MAX)];
+        instructions.extend(set(b"a"));
+        instructions.push(Instruction::Down(i32::MIN));
+        instructions.extend(set(b"b"));
+        let pages = extract(&document(instructions), &fonts()).unwrap();
+        assert_eq!(pages, vec!["a\nb"]);
+    }
+}
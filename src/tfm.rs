@@ -0,0 +1,227 @@
+//! Parser for TeX font metric (TFM) files
+//!
+//! TFM files hold the dimensions of the characters in a font, which is everything TeX knows about
+//! a font. Only the parts needed to position characters are read: the lig/kern program and
+//! extensible recipes are skipped.
+
+use crate::{interpreter::FontMetrics, util::scale_fix_word, FontDef};
+use nom::{
+    bytes::streaming::take,
+    multi::count,
+    number::streaming::{be_i32, be_u16, be_u32, be_u8},
+    IResult,
+};
+use std::collections::HashMap;
+
+/// The metrics of a font
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tfm {
+    pub checksum: u32,
+    /// The design size in points, as a fix_word
+    pub design_size: i32,
+    /// The dimensions of each character, by character code
+    pub chars: HashMap<u32, CharMetrics>,
+    /// The font parameters, starting with `slant`
+    pub params: Vec<i32>,
+}
+
+/// The dimensions of a single character
+///
+/// These are fix_words relative to the design size, to turn them into dvi units use
+/// [`Tfm::scaled`](./struct.Tfm.html#method.scaled).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharMetrics {
+    pub width: i32,
+    pub height: i32,
+    pub depth: i32,
+    pub italic: i32,
+}
+
+impl Tfm {
+    /// Parse a TFM file
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let fail = |input| nom::Err::Failure((input, nom::error::ErrorKind::Verify));
+        let start = input;
+        let (input, sizes) = count(be_u16, 12)(input)?;
+        let (lf, lh, bc, ec) = (sizes[0], sizes[1], sizes[2], sizes[3]);
+        let (nw, nh, nd, ni) = (sizes[4], sizes[5], sizes[6], sizes[7]);
+        let (nl, nk, ne, np) = (sizes[8], sizes[9], sizes[10], sizes[11]);
+        let chars_len = (u32::from(ec) + 1).checked_sub(u32::from(bc));
+        let valid = lh >= 2
+            && chars_len.is_some()
+            && u32::from(lf)
+                == 6 + u32::from(lh)
+                    + chars_len.unwrap_or(0)
+                    + u32::from(nw)
+                    + u32::from(nh)
+                    + u32::from(nd)
+                    + u32::from(ni)
+                    + u32::from(nl)
+                    + u32::from(nk)
+                    + u32::from(ne)
+                    + u32::from(np);
+        if !valid {
+            return Err(fail(start));
+        }
+        let (input, checksum) = be_u32(input)?;
+        let (input, design_size) = be_i32(input)?;
+        let (input, _) = take(4 * (usize::from(lh) - 2))(input)?;
+        let (input, infos) = count(char_info, chars_len.unwrap_or(0) as usize)(input)?;
+        let (input, widths) = count(be_i32, nw.into())(input)?;
+        let (input, heights) = count(be_i32, nh.into())(input)?;
+        let (input, depths) = count(be_i32, nd.into())(input)?;
+        let (input, italics) = count(be_i32, ni.into())(input)?;
+        let (input, _) = take(4 * (usize::from(nl) + usize::from(nk) + usize::from(ne)))(input)?;
+        let (input, params) = count(be_i32, np.into())(input)?;
+
+        let mut chars = HashMap::new();
+        for (code, &(w, h, d, i)) in (u32::from(bc)..).zip(infos.iter()) {
+            // a width index of zero means the character doesn't exist
+            if w == 0 {
+                continue;
+            }
+            let get = |table: &[i32], index: u8| table.get(usize::from(index)).copied();
+            let metrics = CharMetrics {
+                width: get(&widths, w).ok_or_else(|| fail(start))?,
+                height: get(&heights, h).ok_or_else(|| fail(start))?,
+                depth: get(&depths, d).ok_or_else(|| fail(start))?,
+                italic: get(&italics, i).ok_or_else(|| fail(start))?,
+            };
+            chars.insert(code, metrics);
+        }
+        Ok((
+            input,
+            Tfm {
+                checksum,
+                design_size,
+                chars,
+                params,
+            },
+        ))
+    }
+
+    /// The metrics of `ch` in dvi units, when the font is loaded at `scale_factor`
+    ///
+    /// This is `None` if the font has no `ch`, or if `scale_factor` is 2^27 or more, which TeX
+    /// doesn't allow.
+    pub fn scaled(&self, ch: u32, scale_factor: u32) -> Option<CharMetrics> {
+        let m = self.chars.get(&ch)?;
+        Some(CharMetrics {
+            width: scale_fix_word(m.width, scale_factor)?,
+            height: scale_fix_word(m.height, scale_factor)?,
+            depth: scale_fix_word(m.depth, scale_factor)?,
+            italic: scale_fix_word(m.italic, scale_factor)?,
+        })
+    }
+
+    /// The normal space between words, relative to the design size
+    pub fn space(&self) -> Option<i32> {
+        self.params.get(1).copied()
+    }
+
+    /// The height of lowercase letters, relative to the design size
+    pub fn x_height(&self) -> Option<i32> {
+        self.params.get(4).copied()
+    }
+
+    /// The size of one em, relative to the design size
+    pub fn quad(&self) -> Option<i32> {
+        self.params.get(5).copied()
+    }
+}
+
+impl FontMetrics for HashMap<u32, Tfm> {
+    fn char_width(&self, font: &FontDef, ch: u32) -> Option<i32> {
+        let width = self.get(&font.number)?.chars.get(&ch)?.width;
+        scale_fix_word(width, font.scale_factor)
+    }
+}
+
+/// A char_info word: the width, height, depth and italic correction indices
+fn char_info(input: &[u8]) -> IResult<&[u8], (u8, u8, u8, u8)> {
+    let (input, width) = be_u8(input)?;
+    let (input, height_depth) = be_u8(input)?;
+    let (input, italic_tag) = be_u8(input)?;
+    let (input, _remainder) = be_u8(input)?;
+    Ok((
+        input,
+        (
+            width,
+            height_depth >> 4,
+            height_depth & 0xf,
+            italic_tag >> 2,
+        ),
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A font with characters `a` and `b`, and the seven usual parameters
+    pub(crate) fn tfm_bytes() -> Vec<u8> {
+        let words = |v: &[i32]| v.iter().flat_map(|w| w.to_be_bytes()).collect::<Vec<u8>>();
+        let (bc, ec, nw, nh, nd, ni, np) = (97u16, 98u16, 3u16, 2u16, 2u16, 1u16, 7u16);
+        let lh = 2u16;
+        let lf = 6 + lh + (ec - bc + 1) + nw + nh + nd + ni + np;
+        let mut out = Vec::new();
+        for v in &[lf, lh, bc, ec, nw, nh, nd, ni, 0, 0, 0, np] {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+        out.extend(words(&[0x1234_5678, 10 << 20]));
+        // a: width 1, height 1, depth 0; b: width 2, height 1, depth 1
+        out.extend_from_slice(&[1, 0x10, 0, 0, 2, 0x11, 0, 0]);
+        out.extend(words(&[0, 0x0008_0000, 0x0010_0000]));
+        out.extend(words(&[0, 0x0007_0000]));
+        out.extend(words(&[0, 0x0002_0000]));
+        out.extend(words(&[0]));
+        out.extend(words(&[0, 0x0005_5555, 0, 0, 0x0007_0000, 0x0010_0000, 0]));
+        out
+    }
+
+    #[test]
+    fn parse() {
+        let bytes = tfm_bytes();
+        let (rest, tfm) = Tfm::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(tfm.checksum, 0x1234_5678);
+        assert_eq!(tfm.design_size, 10 << 20);
+        assert_eq!(tfm.chars.len(), 2);
+        assert_eq!(
+            tfm.chars[&98],
+            CharMetrics {
+                width: 0x0010_0000,
+                height: 0x0007_0000,
+                depth: 0x0002_0000,
+                italic: 0,
+            }
+        );
+        assert_eq!(tfm.space(), Some(0x0005_5555));
+        assert_eq!(tfm.quad(), Some(0x0010_0000));
+        assert_eq!(tfm.scaled(97, 10 << 16).map(|m| m.width), Some(5 << 16));
+    }
+
+    #[test]
+    fn scale_too_large() {
+        let (_, tfm) = Tfm::parse(&tfm_bytes()).unwrap();
+        assert_eq!(tfm.scaled(97, 1 << 27), None);
+        let mut fonts = HashMap::new();
+        fonts.insert(0, tfm);
+        let def = FontDef {
+            number: 0,
+            checksum: 0,
+            scale_factor: 1 << 27,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("test"),
+        };
+        assert_eq!(fonts.char_width(&def, 97), None);
+    }
+
+    #[test]
+    fn bad_lengths() {
+        let mut bytes = tfm_bytes();
+        bytes[1] += 1;
+        assert!(Tfm::parse(&bytes).is_err());
+    }
+}
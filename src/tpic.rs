@@ -0,0 +1,344 @@
+//! Interpretation of tpic graphics specials
+//!
+//! tpic specials, as written by eepic and PiCTeX, build up a path of points given in milli-inches
+//! relative to the current position, then draw it with a command like `fp` or `da`. [`Graphics`]
+//! keeps track of the path, pen and shading between specials and turns the drawing commands into
+//! [`Shape`]s in dvi units, which a renderer can draw however it likes.
+//!
+//! [`Graphics`]: ./struct.Graphics.html
+//! [`Shape`]: ./enum.Shape.html
+
+use crate::{
+    document::Document,
+    interpreter::{Device, Error, FontMetrics},
+    special::{Special, Tpic},
+};
+
+/// How the outline of a shape is drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stroke {
+    /// Not at all, the shape is only filled
+    None,
+    Solid,
+    /// With dashes of the given length in dvi units
+    Dashed(i32),
+    /// With dots the given distance apart in dvi units
+    Dotted(i32),
+}
+
+/// Something to draw
+///
+/// Coordinates are absolute positions on the page in dvi units. `pen` is the width of the
+/// outline in dvi units, and `fill` is the gray level to fill with, where 0 is white and 1 is
+/// black.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// Straight lines joining the points
+    Polyline {
+        points: Vec<(i32, i32)>,
+        pen: i32,
+        stroke: Stroke,
+        fill: Option<f64>,
+    },
+    /// A quadratic B-spline through the points, as drawn by tpic's `sp`
+    Spline {
+        points: Vec<(i32, i32)>,
+        pen: i32,
+        stroke: Stroke,
+    },
+    /// Part of an ellipse, from angle `start` to `end` in radians, clockwise on the page
+    Arc {
+        center: (i32, i32),
+        rx: i32,
+        ry: i32,
+        start: f64,
+        end: f64,
+        pen: i32,
+        stroke: Stroke,
+        fill: Option<f64>,
+    },
+}
+
+/// The state tpic specials work on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graphics {
+    /// Dvi units per milli-inch
+    scale: f64,
+    /// The pen size in milli-inches
+    pen: i32,
+    /// The points of the path in milli-inches, relative to the current position
+    path: Vec<(i32, i32)>,
+    /// The shade for the next filled shape
+    shade: Option<f64>,
+}
+
+impl Default for Graphics {
+    fn default() -> Self {
+        Graphics::new(25_400_000, 473_628_672)
+    }
+}
+
+impl Graphics {
+    /// Create the graphics state for a file using the units from its preamble
+    pub fn new(numerator: u32, denominator: u32) -> Self {
+        Graphics {
+            // a milli-inch is 254 tenths of a micrometre
+            scale: 254.0 * f64::from(denominator) / f64::from(numerator),
+            pen: 1,
+            path: Vec::new(),
+            shade: None,
+        }
+    }
+
+    /// Process a tpic command at position (h,v), returning what it draws
+    pub fn execute(&mut self, h: i32, v: i32, command: &Tpic) -> Option<Shape> {
+        match *command {
+            Tpic::PenSize(size) => self.pen = size,
+            Tpic::Path(x, y) => self.path.push((x, y)),
+            Tpic::FlushPath => return self.polyline(h, v, Stroke::Solid),
+            Tpic::InvisiblePath => return self.polyline(h, v, Stroke::None),
+            Tpic::Dashed(inches) => {
+                let stroke = Stroke::Dashed(self.inches(inches));
+                return self.polyline(h, v, stroke);
+            }
+            Tpic::Dotted(inches) => {
+                let stroke = Stroke::Dotted(self.inches(inches));
+                return self.polyline(h, v, stroke);
+            }
+            Tpic::Spline(length) => {
+                let stroke = match length {
+                    Some(inches) if inches > 0.0 => Stroke::Dashed(self.inches(inches)),
+                    Some(inches) if inches < 0.0 => Stroke::Dotted(self.inches(-inches)),
+                    _ => Stroke::Solid,
+                };
+                let points = self.take_path(h, v);
+                if points.is_empty() {
+                    return None;
+                }
+                return Some(Shape::Spline {
+                    points,
+                    pen: self.length(self.pen),
+                    stroke,
+                });
+            }
+            Tpic::Arc {
+                x,
+                y,
+                rx,
+                ry,
+                start,
+                end,
+            } => return self.arc(h, v, (x, y, rx, ry), start, end, Stroke::Solid),
+            Tpic::InvisibleArc {
+                x,
+                y,
+                rx,
+                ry,
+                start,
+                end,
+            } => return self.arc(h, v, (x, y, rx, ry), start, end, Stroke::None),
+            Tpic::Shade(gray) => self.shade = Some(gray.unwrap_or(0.5)),
+            Tpic::White => self.shade = Some(0.0),
+            Tpic::Black => self.shade = Some(1.0),
+            // textures can't be represented, so use a middling gray like dvips does
+            Tpic::Texture(_) => self.shade = Some(0.5),
+        }
+        None
+    }
+
+    /// Process a special, returning what it draws if it is a tpic command
+    pub fn special(&mut self, h: i32, v: i32, payload: &[u8]) -> Option<Shape> {
+        match Special::parse(payload) {
+            Special::Tpic(command) => self.execute(h, v, &command),
+            _ => None,
+        }
+    }
+
+    fn length(&self, mils: i32) -> i32 {
+        (f64::from(mils) * self.scale).round() as i32
+    }
+
+    fn inches(&self, inches: f64) -> i32 {
+        (inches * 1000.0 * self.scale).round() as i32
+    }
+
+    /// Take the points of the path, as positions on the page
+    fn take_path(&mut self, h: i32, v: i32) -> Vec<(i32, i32)> {
+        let path = std::mem::take(&mut self.path);
+        path.into_iter()
+            .map(|(x, y)| {
+                (
+                    h.saturating_add(self.length(x)),
+                    v.saturating_add(self.length(y)),
+                )
+            })
+            .collect()
+    }
+
+    fn polyline(&mut self, h: i32, v: i32, stroke: Stroke) -> Option<Shape> {
+        let points = self.take_path(h, v);
+        let fill = self.shade.take();
+        if points.is_empty() || (stroke == Stroke::None && fill.is_none()) {
+            return None;
+        }
+        Some(Shape::Polyline {
+            points,
+            pen: self.length(self.pen),
+            stroke,
+            fill,
+        })
+    }
+
+    fn arc(
+        &mut self,
+        h: i32,
+        v: i32,
+        (x, y, rx, ry): (i32, i32, i32, i32),
+        start: f64,
+        end: f64,
+        stroke: Stroke,
+    ) -> Option<Shape> {
+        let fill = self.shade.take();
+        if stroke == Stroke::None && fill.is_none() {
+            return None;
+        }
+        Some(Shape::Arc {
+            center: (
+                h.saturating_add(self.length(x)),
+                v.saturating_add(self.length(y)),
+            ),
+            rx: self.length(rx),
+            ry: self.length(ry),
+            start,
+            end,
+            pen: self.length(self.pen),
+            stroke,
+            fill,
+        })
+    }
+}
+
+/// The shapes drawn by tpic specials on every page of a document
+pub fn extract<M: FontMetrics>(document: &Document, metrics: M) -> Result<Vec<Vec<Shape>>, Error> {
+    let mut collector = Collector::default();
+    document.run(metrics, &mut collector)?;
+    Ok(collector.pages)
+}
+
+/// A `Device` that collects tpic shapes for each page
+#[derive(Debug, Default)]
+struct Collector {
+    graphics: Graphics,
+    pages: Vec<Vec<Shape>>,
+}
+
+impl Device for Collector {
+    fn preamble(&mut self, numerator: u32, denominator: u32, _: u32) {
+        self.graphics = Graphics::new(numerator, denominator);
+    }
+
+    fn begin_page(&mut self, _: &[i32; 10]) {
+        self.pages.push(Vec::new());
+    }
+
+    fn special(&mut self, h: i32, v: i32, payload: &[u8]) {
+        if let Some(shape) = self.graphics.special(h, v, payload) {
+            if let Some(page) = self.pages.last_mut() {
+                page.push(shape);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Units where a milli-inch is 10 dvi units
+    fn graphics() -> Graphics {
+        Graphics::new(254, 10)
+    }
+
+    #[test]
+    fn paths() {
+        let mut g = graphics();
+        assert_eq!(g.special(0, 0, b"pn 2"), None);
+        assert_eq!(g.special(0, 0, b"pa 0 0"), None);
+        assert_eq!(g.special(0, 0, b"pa 10 -5"), None);
+        assert_eq!(
+            g.special(100, 200, b"fp"),
+            Some(Shape::Polyline {
+                points: vec![(100, 200), (200, 150)],
+                pen: 20,
+                stroke: Stroke::Solid,
+                fill: None,
+            })
+        );
+        // the path is cleared after drawing
+        assert_eq!(g.special(0, 0, b"fp"), None);
+
+        g.special(0, 0, b"pa 0 0");
+        g.special(0, 0, b"pa 1 1");
+        assert_eq!(
+            g.special(0, 0, b"da 0.05"),
+            Some(Shape::Polyline {
+                points: vec![(0, 0), (10, 10)],
+                pen: 20,
+                stroke: Stroke::Dashed(500),
+                fill: None,
+            })
+        );
+
+        g.special(0, 0, b"pa 0 0");
+        g.special(0, 0, b"pa 1 1");
+        g.special(0, 0, b"pa 2 0");
+        assert_eq!(
+            g.special(0, 0, b"sp -0.01"),
+            Some(Shape::Spline {
+                points: vec![(0, 0), (10, 10), (20, 0)],
+                pen: 20,
+                stroke: Stroke::Dotted(100),
+            })
+        );
+    }
+
+    #[test]
+    fn shading() {
+        let mut g = graphics();
+        g.special(0, 0, b"pa 0 0");
+        g.special(0, 0, b"pa 1 0");
+        // an invisible path with no shading draws nothing
+        assert_eq!(g.special(0, 0, b"ip"), None);
+
+        g.special(0, 0, b"pa 0 0");
+        g.special(0, 0, b"pa 1 0");
+        g.special(0, 0, b"bk");
+        assert_eq!(
+            g.special(0, 0, b"ip"),
+            Some(Shape::Polyline {
+                points: vec![(0, 0), (10, 0)],
+                pen: 10,
+                stroke: Stroke::None,
+                fill: Some(1.0),
+            })
+        );
+
+        g.special(0, 0, b"sh 0.25");
+        assert_eq!(
+            g.special(5, 5, b"ar 1 2 3 4 0 3.5"),
+            Some(Shape::Arc {
+                center: (15, 25),
+                rx: 30,
+                ry: 40,
+                start: 0.0,
+                end: 3.5,
+                pen: 10,
+                stroke: Stroke::Solid,
+                fill: Some(0.25),
+            })
+        );
+        // shading only applies to one shape
+        assert_eq!(g.special(0, 0, b"ia 0 0 1 1 0 1"), None);
+        assert_eq!(g.special(0, 0, b"color pop"), None);
+    }
+}
@@ -1,19 +1,285 @@
-use nom::IResult;
-use std::io::{self, Write};
+#[cfg(feature = "std")]
+use crate::document::{self, Document, Page};
+use crate::{dumper, parser, FontDef, Instruction};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use nom::Needed;
+use nom::{error::ErrorKind, IResult};
+
+/// Somewhere to write bytes
+///
+/// With the `std` feature this is implemented for everything that implements `std::io::Write`,
+/// with `io::Error` as the error. Without it, it is implemented for `Vec<u8>`, which can't fail.
+pub trait Sink {
+    type Error;
+
+    /// Write all of `bytes`
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Sink for W {
+    type Error = std::io::Error;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_all(bytes)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Sink for Vec<u8> {
+    type Error = core::convert::Infallible;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
 
 /// A type that can be written to a stream (serialized)
 pub trait Dump {
-    fn dump<W>(&self, _: &mut W) -> io::Result<()>
+    fn dump<S>(&self, _: &mut S) -> Result<(), S::Error>
     where
-        W: Write;
+        S: Sink + ?Sized;
 }
 
 /// A type that can be parsed from a byte slice
+///
+/// This is implemented for `&[u8]` inputs, and for `bytes::Bytes` with the `bytes` feature. An
+/// owned `Vec<u8>` can be turned into `Bytes` without copying it, and the rest of a `Bytes` is
+/// returned without copying either, so reading a buffer one value at a time stays linear.
 pub trait Parse<V>
 where
     V: AsRef<[u8]>,
     Self: Sized,
 {
-    /// Returns the Instruction and how many bytes were used
+    /// Returns the rest of the input and the parsed value
     fn parse(v: V) -> IResult<V, Self>;
 }
+
+impl Dump for Instruction {
+    fn dump<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<(), S::Error> {
+        dumper::dump(self, sink)
+    }
+}
+
+impl Dump for FontDef {
+    fn dump<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<(), S::Error> {
+        dumper::dump_font_def(self, sink)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Dump for Page {
+    fn dump<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<(), S::Error> {
+        Page::dump(self, sink)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Dump for Document {
+    fn dump<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<(), S::Error> {
+        Document::dump(self, sink)
+    }
+}
+
+fn font_def(input: &[u8]) -> IResult<&[u8], FontDef> {
+    match parser::parse(input)? {
+        (rest, Instruction::FontDef(def)) => Ok((rest, def)),
+        _ => Err(nom::Err::Error((input, ErrorKind::Tag))),
+    }
+}
+
+#[cfg(feature = "std")]
+fn document(input: &[u8]) -> IResult<&[u8], Document> {
+    match Document::parse_partial(input, &crate::ParseLimits::unlimited()) {
+        Ok(ok) => Ok(ok),
+        Err(document::Error::Truncated) => Err(nom::Err::Incomplete(Needed::Unknown)),
+        Err(document::Error::Parse(offset)) => {
+            Err(nom::Err::Error((&input[offset..], ErrorKind::Switch)))
+        }
+        Err(document::Error::Unexpected(offset)) => {
+            Err(nom::Err::Error((&input[offset..], ErrorKind::Tag)))
+        }
+        Err(document::Error::Incompatible)
+        | Err(document::Error::UndefinedFont(_))
+        | Err(document::Error::NoFreeFontNumber) => {
+            Err(nom::Err::Error((input, ErrorKind::Verify)))
+        }
+        Err(document::Error::Limit(_, offset)) => {
+            Err(nom::Err::Failure((&input[offset..], ErrorKind::TooLarge)))
+        }
+    }
+}
+
+/// Parse an owned input by parsing a slice of it, using `rest` to take what is left over
+#[cfg(feature = "bytes")]
+fn parse_owned<V: AsRef<[u8]>, T>(
+    v: V,
+    parse: fn(&[u8]) -> IResult<&[u8], T>,
+    rest: fn(&V, usize) -> V,
+) -> IResult<V, T> {
+    let input = v.as_ref();
+    let used = |remaining: &[u8]| input.len() - remaining.len();
+    match parse(input) {
+        Ok((remaining, value)) => Ok((rest(&v, used(remaining)), value)),
+        Err(e) => Err(e.map(|(remaining, kind)| (rest(&v, used(remaining)), kind))),
+    }
+}
+
+/// Implement `Parse` for slices and `Bytes`, given a parser for slices
+macro_rules! impl_parse {
+    ($ty:ty, $parse:expr) => {
+        impl<'a> Parse<&'a [u8]> for $ty {
+            fn parse(v: &'a [u8]) -> IResult<&'a [u8], Self> {
+                $parse(v)
+            }
+        }
+
+        #[cfg(feature = "bytes")]
+        impl Parse<bytes::Bytes> for $ty {
+            fn parse(v: bytes::Bytes) -> IResult<bytes::Bytes, Self> {
+                parse_owned(v, $parse, |v, used| v.slice(used..))
+            }
+        }
+    };
+}
+
+impl_parse!(Instruction, parser::parse);
+impl_parse!(FontDef, font_def);
+#[cfg(feature = "std")]
+impl_parse!(Page, Page::parse);
+#[cfg(feature = "std")]
+impl_parse!(Document, document);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Dump anything, then parse it back from each kind of input
+    fn round_trip<T>(value: &T)
+    where
+        T: Dump + for<'a> Parse<&'a [u8]> + PartialEq + core::fmt::Debug,
+    {
+        let mut out = Vec::new();
+        value.dump(&mut out).unwrap();
+        out.push(138);
+        let (rest, parsed) = <T as Parse<&[u8]>>::parse(&out).unwrap();
+        assert_eq!(rest, [138]);
+        assert_eq!(&parsed, value);
+    }
+
+    #[cfg(feature = "bytes")]
+    fn round_trip_bytes<T>(value: &T)
+    where
+        T: Dump + Parse<bytes::Bytes> + PartialEq + core::fmt::Debug,
+    {
+        let mut out = Vec::new();
+        value.dump(&mut out).unwrap();
+        out.push(138);
+        let (rest, parsed) = T::parse(bytes::Bytes::from(out)).unwrap();
+        assert_eq!(rest, bytes::Bytes::from_static(&[138]));
+        assert_eq!(&parsed, value);
+    }
+
+    fn def() -> FontDef {
+        FontDef {
+            number: 3,
+            checksum: 0x1234_5678,
+            scale_factor: 10 << 16,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from("cmr10"),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&Instruction::Xxx(Vec::from("color push Black")));
+        round_trip(&def());
+        #[cfg(feature = "bytes")]
+        round_trip_bytes(&def());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn page_round_trips() {
+        let page = Page {
+            counters: [1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            instructions: vec![
+                Instruction::FontDef(def()),
+                Instruction::Font(3),
+                Instruction::Set(65),
+            ],
+        };
+        round_trip(&page);
+        #[cfg(feature = "bytes")]
+        round_trip_bytes(&page);
+        round_trip(&Document {
+            preamble: document::Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 1000,
+                comment: Vec::from("test"),
+            },
+            pages: vec![page],
+            postamble: document::Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![def()],
+            },
+        });
+    }
+
+    /// A fixed size buffer, as a device without an allocator might use
+    struct Buffer {
+        bytes: [u8; 8],
+        len: usize,
+    }
+
+    impl Sink for Buffer {
+        type Error = usize;
+
+        fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), usize> {
+            let end = self.len + bytes.len();
+            if end > self.bytes.len() {
+                return Err(self.len);
+            }
+            self.bytes[self.len..end].copy_from_slice(bytes);
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sinks() {
+        let mut buffer = Buffer {
+            bytes: [0; 8],
+            len: 0,
+        };
+        Instruction::Right(-5).dump(&mut buffer).unwrap();
+        Instruction::Font(300).dump(&mut buffer).unwrap();
+        assert_eq!(buffer.bytes[..buffer.len], [143, 0xfb, 236, 1, 44]);
+        // the opcode and font number fit, the checksum doesn't
+        assert_eq!(def().dump(&mut buffer), Err(7));
+    }
+
+    #[test]
+    fn errors() {
+        let mut out = Vec::new();
+        Instruction::Nop.dump(&mut out).unwrap();
+        match <FontDef as Parse<&[u8]>>::parse(&out) {
+            Err(nom::Err::Error((rest, ErrorKind::Tag))) => assert_eq!(rest, [138]),
+            other => panic!("unexpected {:?}", other),
+        }
+        #[cfg(feature = "std")]
+        assert!(matches!(
+            <Document as Parse<&[u8]>>::parse(&[247, 2]),
+            Err(nom::Err::Incomplete(_))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+//! Reports on which fonts and characters a document uses
+//!
+//! This works from the instructions alone, without interpreting the pages, so it doesn't need
+//! any font files. The report is useful for subsetting fonts, and together with font metrics it
+//! can find characters that a font doesn't have before anything is printed.
+
+use crate::{document::Document, interpreter::FontMetrics, FontDef, Instruction};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How a document uses one font
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontUsage {
+    pub def: FontDef,
+    /// The size the font is used at, in points
+    pub at_size: f64,
+    /// The design size of the font, in points
+    pub design_size: f64,
+    /// The magnification of the font, times 1000, including the magnification of the document
+    ///
+    /// This is what `dvitype` prints, and decides which PK file to use: a 10pt font used at 12pt
+    /// in a document magnified by 1000 has magnification 1200.
+    pub magnification: u32,
+    /// Every character code used
+    pub chars: BTreeSet<u32>,
+    /// The character codes used on each page, by page index, for pages that use the font
+    pub pages: BTreeMap<usize, BTreeSet<u32>>,
+}
+
+impl FontUsage {
+    /// The name of the font, which is its file name
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.def.filename).into_owned()
+    }
+
+    /// The characters used that `metrics` doesn't have a width for
+    pub fn missing<M: FontMetrics>(&self, metrics: &M) -> BTreeSet<u32> {
+        self.chars
+            .iter()
+            .copied()
+            .filter(|&ch| metrics.char_width(&self.def, ch).is_none())
+            .collect()
+    }
+}
+
+/// Report on every font defined in a document, ordered by font number
+///
+/// Fonts that are defined but never used are included with no characters.
+pub fn font_usage(document: &Document) -> Vec<FontUsage> {
+    let pre = &document.preamble;
+    // points per dvi unit, without magnification
+    let pt = f64::from(pre.numerator) * 72.27 / (f64::from(pre.denominator) * 254_000.0);
+    let mut fonts: BTreeMap<u32, FontUsage> = BTreeMap::new();
+    let mut define = |def: &FontDef| {
+        fonts.entry(def.number).or_insert_with(|| FontUsage {
+            def: def.clone(),
+            at_size: f64::from(def.scale_factor) * pt,
+            design_size: f64::from(def.design_size) * pt,
+            magnification: magnification(pre.magnification, def),
+            chars: BTreeSet::new(),
+            pages: BTreeMap::new(),
+        });
+    };
+    for def in &document.postamble.fonts {
+        define(def);
+    }
+    for instruction in document.pages.iter().flat_map(|page| &page.instructions) {
+        if let Instruction::FontDef(def) = instruction {
+            define(def);
+        }
+    }
+
+    for (index, page) in document.pages.iter().enumerate() {
+        // the font is reset at the start of every page
+        let mut font = None;
+        for instruction in &page.instructions {
+            let ch = match *instruction {
+                Instruction::Font(number) => {
+                    font = Some(number);
+                    continue;
+                }
+                Instruction::Set(ch) | Instruction::Put(ch) => ch,
+                _ => continue,
+            };
+            if let Some(usage) = font.and_then(|number| fonts.get_mut(&number)) {
+                usage.chars.insert(ch);
+                usage.pages.entry(index).or_default().insert(ch);
+            }
+        }
+    }
+    fonts.into_values().collect()
+}
+
+/// The magnification of a font as `dvitype` works it out
+pub(crate) fn magnification(document: u32, def: &FontDef) -> u32 {
+    if def.design_size == 0 {
+        return document;
+    }
+    let ratio = f64::from(def.scale_factor) / f64::from(def.design_size);
+    (f64::from(document) * ratio).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{Page, Postamble, Preamble};
+    use std::collections::HashMap;
+
+    fn font(number: u32, filename: &str, scale_factor: u32) -> FontDef {
+        FontDef {
+            number,
+            checksum: 0,
+            scale_factor,
+            design_size: 10 << 16,
+            directory: None,
+            filename: Vec::from(filename),
+        }
+    }
+
+    #[test]
+    fn report() {
+        let document = Document {
+            preamble: Preamble {
+                format: 2,
+                numerator: 25_400_000,
+                denominator: 473_628_672,
+                magnification: 2000,
+                comment: Vec::new(),
+            },
+            pages: vec![
+                Page {
+                    counters: [1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                    instructions: vec![
+                        Instruction::Font(0),
+                        Instruction::Set(b'a'.into()),
+                        Instruction::Put(b'b'.into()),
+                        Instruction::Font(1),
+                        Instruction::Set(b'a'.into()),
+                    ],
+                },
+                Page {
+                    counters: [2, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                    instructions: vec![
+                        Instruction::FontDef(font(3, "cmtt10", 10 << 16)),
+                        // no font selected yet on this page
+                        Instruction::Set(b'x'.into()),
+                        Instruction::Font(0),
+                        Instruction::Set(b'c'.into()),
+                    ],
+                },
+            ],
+            postamble: Postamble {
+                tallest_height: 0,
+                widest_width: 0,
+                max_stack_depth: 0,
+                fonts: vec![font(1, "cmbx10", 12 << 16), font(0, "cmr10", 10 << 16)],
+            },
+        };
+        let usage = font_usage(&document);
+        assert_eq!(usage.len(), 3);
+        assert_eq!(usage[0].name(), "cmr10");
+        assert_eq!(usage[0].at_size, 10.0);
+        assert_eq!(usage[0].design_size, 10.0);
+        assert_eq!(usage[0].magnification, 2000);
+        assert_eq!(
+            usage[0].chars,
+            [b'a', b'b', b'c'].iter().map(|&c| u32::from(c)).collect()
+        );
+        assert_eq!(usage[0].pages.len(), 2);
+        assert_eq!(
+            usage[0].pages[&1],
+            [u32::from(b'c')].iter().copied().collect()
+        );
+        assert_eq!(usage[1].name(), "cmbx10");
+        assert_eq!(usage[1].at_size, 12.0);
+        assert_eq!(usage[1].magnification, 2400);
+        assert_eq!(usage[2].name(), "cmtt10");
+        assert!(usage[2].chars.is_empty());
+
+        let mut widths = HashMap::new();
+        widths.insert(u32::from(b'a'), 0);
+        struct Metrics(HashMap<u32, i32>);
+        impl FontMetrics for Metrics {
+            fn char_width(&self, _: &FontDef, ch: u32) -> Option<i32> {
+                self.0.get(&ch).copied()
+            }
+        }
+        assert_eq!(
+            usage[0].missing(&Metrics(widths)),
+            [b'b', b'c'].iter().map(|&c| u32::from(c)).collect()
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use core::convert::TryFrom;
 use nom::IResult;
 
 /// Get the number of bytes required to store a u32
@@ -29,18 +30,82 @@ pub(crate) fn byte_width_signed(val: i32) -> u8 {
     }
 }
 
-/// A parser to count the number of times the byte 223 occurs. This parser converts Incomplete to
-/// Done, which is normally bad, but since these trailing bytes make no difference to the semantic
-/// meaning of the document, we don't care if we haven't read them all yet.
-pub(crate) fn parse_223(mut i: &[u8]) -> IResult<&[u8], u32> {
-    use nom::bytes::streaming::tag;
+/// Scale a TFM fix_word by a font's scale factor, giving dvi units
+///
+/// This is the algorithm from TeX (and dvitype), which is exact, so the result will agree with
+/// the widths TeX used when it wrote the dvi file. Like TeX, it gives `None` for scale factors
+/// of 2^27 or more, which are too large for it.
+#[cfg(feature = "std")]
+pub(crate) fn scale_fix_word(fix: i32, scale: u32) -> Option<i32> {
+    if scale >= 1 << 27 {
+        return None;
+    }
+    let mut z = i64::from(scale);
+    let mut alpha = 16;
+    while z >= 0o40000000 {
+        z /= 2;
+        alpha += alpha;
+    }
+    let beta = 256 / alpha;
+    let alpha = alpha * z;
+    let [b0, b1, b2, b3] = fix.to_be_bytes();
+    let (b1, b2, b3) = (i64::from(b1), i64::from(b2), i64::from(b3));
+    let scaled = (((b3 * z) / 0o400 + b2 * z) / 0o400 + b1 * z) / beta;
+    Some(match b0 {
+        0 => scaled as i32,
+        _ => (scaled - alpha) as i32,
+    })
+}
+
+/// Big points (1/72 inch) per dvi unit for the given preamble values
+#[cfg(feature = "std")]
+pub(crate) fn bp_per_unit(numerator: u32, denominator: u32, magnification: u32) -> f64 {
+    // num/den gives units of 10^-7 meters, and there are 254000 of those in 72 big points
+    f64::from(numerator) / f64::from(denominator) * f64::from(magnification) / 1000.0 * 72.0
+        / 254_000.0
+}
 
-    let mut count = 0;
-    while let Ok((inext, _)) = tag::<_, _, ()>(&[223][..])(i) {
-        count += 1;
-        i = inext;
+/// Format a number with at most 3 decimal places and no trailing zeros
+#[cfg(feature = "std")]
+pub(crate) fn num(v: f64) -> String {
+    let s = format!("{:.3}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        "0".into()
+    } else {
+        s.into()
     }
-    Ok((i, count))
+}
+
+/// Parse a TeX dimension such as `210mm` or `8.5truein`, giving big points
+#[cfg(feature = "std")]
+pub(crate) fn dimension(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic())?;
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.trim().parse().ok()?;
+    let unit = unit.strip_prefix("true").unwrap_or(unit);
+    let per_unit = match unit {
+        "bp" => 1.0,
+        "pt" => 72.0 / 72.27,
+        "in" => 72.0,
+        "mm" => 72.0 / 25.4,
+        "cm" => 72.0 / 2.54,
+        "pc" => 12.0 * 72.0 / 72.27,
+        "dd" => 1238.0 / 1157.0 * 72.0 / 72.27,
+        "cc" => 12.0 * 1238.0 / 1157.0 * 72.0 / 72.27,
+        "sp" => 72.0 / 72.27 / 65536.0,
+        _ => return None,
+    };
+    Some(value * per_unit)
+}
+
+/// A parser to count the number of times the byte 223 occurs. This parser never returns
+/// Incomplete, which is normally bad, but since these trailing bytes make no difference to the
+/// semantic meaning of the document, we don't care if we haven't read them all yet.
+pub(crate) fn parse_223(i: &[u8]) -> IResult<&[u8], u32> {
+    let count = i.iter().take_while(|&&b| b == 223).count();
+    Ok((&i[count..], u32::try_from(count).unwrap_or(u32::MAX)))
 }
 
 #[cfg(test)]
@@ -56,7 +121,48 @@ mod tests {
         assert_eq!(byte_width(0), 1);
     }
 
+    #[cfg(feature = "std")]
+    #[test]
+    fn scale_fix_word() {
+        use super::scale_fix_word;
+        // 0.5 and -0.5 of 10pt
+        assert_eq!(scale_fix_word(0x0008_0000, 10 << 16), Some(5 << 16));
+        assert_eq!(scale_fix_word(-0x0008_0000, 10 << 16), Some(-(5 << 16)));
+        // 1.0 of a font larger than 2^23 units
+        assert_eq!(scale_fix_word(0x0010_0000, 200 << 16), Some(200 << 16));
+        assert_eq!(scale_fix_word(0, 10 << 16), Some(0));
+        // the largest scale TeX allows, and one too large
+        assert_eq!(
+            scale_fix_word(0x0010_0000, (1 << 27) - 16),
+            Some((1 << 27) - 16)
+        );
+        assert_eq!(scale_fix_word(0x0010_0000, 1 << 27), None);
+        assert_eq!(scale_fix_word(0x0010_0000, u32::MAX), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn num() {
+        use super::num;
+        assert_eq!(num(1.0), "1");
+        assert_eq!(num(1.25), "1.25");
+        assert_eq!(num(-0.0001), "0");
+        assert_eq!(num(-2.5), "-2.5");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn dimension() {
+        use super::dimension;
+        assert_eq!(dimension("1in"), Some(72.0));
+        assert_eq!(dimension("2truebp"), Some(2.0));
+        assert_eq!(dimension("72.27pt"), Some(72.0));
+        assert_eq!(dimension("12"), None);
+        assert_eq!(dimension("3furlongs"), None);
+    }
+
     #[test]
+    #[allow(clippy::legacy_numeric_constants)]
     fn byte_width_signed() {
         use super::byte_width_signed;
         assert_eq!(byte_width_signed(0), 1);
@@ -0,0 +1,84 @@
+#![cfg(feature = "cli")]
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Pipe `input` through one of the binaries
+fn run(binary: &str, input: &[u8]) -> Vec<u8> {
+    run_with(binary, &[], input)
+}
+
+/// The same, passing `args` to the binary
+fn run_with(binary: &str, args: &[&str], input: &[u8]) -> Vec<u8> {
+    let mut child = Command::new(binary)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn round_trip() {
+    let dvi = fs::read("tests/source/main.dvi").unwrap();
+    let json = run(env!("CARGO_BIN_EXE_dvi2json"), &dvi);
+    assert!(json.starts_with(b"{\n  \"preamble\": {"));
+    assert_eq!(run(env!("CARGO_BIN_EXE_json2dvi"), &json), dvi);
+}
+
+#[test]
+fn yaml_round_trip() {
+    let dvi = fs::read("tests/source/main.dvi").unwrap();
+    let yaml = run_with(env!("CARGO_BIN_EXE_dvi2json"), &["--yaml"], &dvi);
+    assert!(yaml.starts_with(b"preamble:\n"));
+    assert_eq!(
+        run_with(env!("CARGO_BIN_EXE_json2dvi"), &["--yaml"], &yaml),
+        dvi
+    );
+}
+
+/// Run the `dvi` tool with `args`, returning what it printed
+fn dvi(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dvi"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn multitool() {
+    let info = dvi(&["info", "tests/source/main.dvi"]);
+    assert!(info.contains("pages: 2\n"));
+    assert!(info.contains("  14 cmr12 at 12.00pt\n"));
+    let dump = dvi(&["dump", "tests/source/main.dvi"]);
+    assert!(dump.starts_with("0: pre 2 25400000/473628672 mag 1000"));
+    assert!(dump.contains("42: bop 1 0 0 0 0 0 0 0 0 0 previous -1\n"));
+    assert!(dvi(&["fonts", "tests/source/main.dvi"]).contains("15 cmr17 at 20.74pt"));
+
+    let dir = std::env::temp_dir();
+    let selected = dir.join(format!("dvi-select-{}.dvi", std::process::id()));
+    let joined = dir.join(format!("dvi-concat-{}.dvi", std::process::id()));
+    let (selected, joined) = (selected.to_str().unwrap(), joined.to_str().unwrap());
+    dvi(&["select", "tests/source/main.dvi", "2", "-o", selected]);
+    dvi(&["concat", "tests/source/main.dvi", selected, "-o", joined]);
+    assert!(dvi(&["validate", joined]).ends_with("ok, 3 pages\n"));
+    assert!(dvi(&["info", joined]).contains("pages: 3\n"));
+    fs::remove_file(selected).unwrap();
+    fs::remove_file(joined).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dvi"))
+        .args(["select", "tests/source/main.dvi", "3"])
+        .output()
+        .unwrap()
+        .status;
+    assert!(!status.success());
+}
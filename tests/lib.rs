@@ -3,6 +3,7 @@ use dvi::Instruction;
 use std::fs::File;
 use std::io::Read;
 
+#[allow(clippy::len_zero, clippy::needless_borrow)]
 fn parse(input: &[u8]) -> Vec<Instruction> {
     let mut input = input;
     let mut instructions = Vec::new();
@@ -60,6 +61,23 @@ fn main() {
         );
     }
     assert_eq!(instructions, parsed_again);
+
+    let borrowed = dvi::Instructions::new(&input_owned)
+        .map(|instruction| instruction.map(Instruction::from))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(instructions, borrowed);
     //println!("{:#?}", instructions);
     //panic!();
 }
+
+#[cfg(feature = "std")]
+#[test]
+fn lazy() {
+    let input_owned = std::fs::read("tests/source/main.dvi").unwrap();
+    let document = dvi::document::Document::parse(&input_owned).unwrap();
+    let lazy = dvi::lazy::LazyDocument::new(&input_owned[..]).unwrap();
+    assert_eq!(lazy.len(), document.pages.len());
+    assert_eq!(lazy.postamble(), &document.postamble);
+    assert_eq!(lazy.page(0).unwrap().unwrap(), document.pages[0]);
+}